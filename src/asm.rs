@@ -0,0 +1,794 @@
+use crate::chip8::PROGRAM_START;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/**
+ *  Assembler for the Cowgod style syntax the disassembler prints, so a listing goes straight
+ *  back to the ROM it came from.
+ *
+ *  ```text
+ *      ; comments run to the end of the line
+ *      count   equ 5               ; constants, can refer to labels and other constants
+ *      start:  LD V0, count        ; numbers are decimal, #hex, 0xhex, $hex or %binary
+ *              LD I, smiley
+ *              DRW V0, V1, 4
+ *              JP start
+ *      smiley: DB %..####..        ; in %binary . is 0 and # is 1, so sprites can be drawn
+ *              DB %.#....#.
+ *              DW #1234, start+2   ; big endian words
+ *              DB "text", 0
+ *              include "other.8s"  ; relative to the including file
+ *  ```
+ *
+ *  Mnemonics, registers and directives are case insensitive, labels and constants are not.
+ */
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Target {
+    Chip8,
+    Schip,
+    XoChip,
+}
+
+impl Target {
+    fn max_rom_size(self) -> usize {
+        match self {
+            Target::Chip8 | Target::Schip => 0x1000 - PROGRAM_START as usize,
+            Target::XoChip => 0x10000 - PROGRAM_START as usize,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Target::Chip8 => "chip8",
+            Target::Schip => "schip",
+            Target::XoChip => "xochip",
+        }
+    }
+}
+
+impl std::str::FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "chip8" => Ok(Target::Chip8),
+            "schip" => Ok(Target::Schip),
+            "xochip" => Ok(Target::XoChip),
+            _ => Err(format!("Unknown instruction set '{}'. One of: chip8, schip, xochip", s))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.col, self.message)
+    }
+}
+
+/**
+ *  Assembles source text into a ROM to be loaded at PROGRAM_START.
+ *  Includes are looked up relative to the current directory.
+ */
+pub fn assemble(source: &str, target: Target) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler::new(target);
+    asm.parse_source("<source>", source, Path::new("."), 0)?;
+    asm.finish()
+}
+
+pub fn assemble_file(path: &str, target: Target) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler::new(target);
+    asm.parse_file(Path::new(path), None, 0)?;
+    asm.finish()
+}
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+// Constants that refer to each other deeper than this are taken to be a cycle
+const MAX_EXPR_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+struct Pos {
+    file: usize,
+    line: usize,
+    col: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Num(i64),
+    Str(String),
+    Punct(char),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    col: usize,
+}
+
+// Sum of signed terms, e.g. table+2 or end-start
+#[derive(Debug, Clone)]
+struct Expr {
+    terms: Vec<(bool, Term)>,
+    pos: Pos,
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Num(i64),
+    Sym(String),
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    V(u8),
+    Range(u8, u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(Expr),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone)]
+enum Data {
+    Expr(Expr),
+    Str(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Instruction { mnemonic: String, operands: Vec<Operand> },
+    Bytes(Vec<Data>),
+    Words(Vec<Expr>),
+}
+
+enum Symbol {
+    Label(u16),
+    Const(Expr),
+}
+
+struct Assembler {
+    target: Target,
+    files: Vec<String>,
+    items: Vec<(Pos, Item)>,
+    symbols: HashMap<String, (Symbol, Pos)>,
+    addr: usize,
+}
+
+impl Assembler {
+
+    fn new(target: Target) -> Self {
+        Assembler {
+            target,
+            files: Vec::new(),
+            items: Vec::new(),
+            symbols: HashMap::new(),
+            addr: PROGRAM_START as usize,
+        }
+    }
+
+    fn error(&self, pos: &Pos, message: String) -> AsmError {
+        AsmError {
+            file: self.files.get(pos.file).cloned().unwrap_or_default(),
+            line: pos.line,
+            col: pos.col,
+            message,
+        }
+    }
+
+    fn parse_file(&mut self, path: &Path, from: Option<&Pos>, depth: usize) -> Result<(), AsmError> {
+        let source = std::fs::read_to_string(path).map_err(|e| {
+            let message = format!("Unable to read '{}' :: {}", path.display(), e);
+            match from {
+                Some(pos) => self.error(pos, message),
+                None => AsmError { file: path.display().to_string(), line: 0, col: 0, message }
+            }
+        })?;
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+        self.parse_source(&path.display().to_string(), &source, &dir, depth)
+    }
+
+    fn parse_source(&mut self, name: &str, source: &str, dir: &Path, depth: usize) -> Result<(), AsmError> {
+        let file = self.files.len();
+        self.files.push(name.to_string());
+
+        for (n, text) in source.lines().enumerate() {
+            let line = n + 1;
+            let tokens = tokenize(text).map_err(|(col, msg)| self.error(&Pos { file, line, col }, msg))?;
+            let mut p = Parser { tokens: &tokens, at: 0, file, line, end_col: text.len() + 1 };
+            let include = self.parse_line(&mut p).map_err(|(pos, msg)| self.error(&pos, msg))?;
+            if let Some((path, pos)) = include {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(self.error(&pos, format!("Includes nested deeper than {}, is a file including itself?", MAX_INCLUDE_DEPTH)));
+                }
+                self.parse_file(&dir.join(path), Some(&pos), depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the file named by an include, for parse_source to read in
+    fn parse_line(&mut self, p: &mut Parser) -> Result<Option<(String, Pos)>, (Pos, String)> {
+        // Any number of labels, then at most one statement
+        while let (Some(Tok::Ident(name)), Some(Tok::Punct(':'))) = (p.peek(0), p.peek(1)) {
+            let name = name.clone();
+            let pos = p.pos();
+            p.at += 2;
+            self.define(name, Symbol::Label(self.addr as u16), pos)?;
+        }
+
+        let (word, pos) = match p.next() {
+            None => return Ok(None),
+            Some((Tok::Ident(word), pos)) => (word, pos),
+            Some((_, pos)) => return Err((pos, "Expected a label, instruction or directive".to_string())),
+        };
+
+        if let Some(Tok::Ident(kw)) = p.peek(0) {
+            if kw.eq_ignore_ascii_case("equ") {
+                p.at += 1;
+                let value = p.expr()?;
+                p.end()?;
+                self.define(word, Symbol::Const(value), pos)?;
+                return Ok(None);
+            }
+        }
+
+        let item = match word.to_lowercase().as_str() {
+            "db" => {
+                let mut data = Vec::new();
+                loop {
+                    match p.peek(0) {
+                        Some(Tok::Str(s)) => {
+                            data.push(Data::Str(s.clone().into_bytes()));
+                            p.at += 1;
+                        },
+                        _ => data.push(Data::Expr(p.expr()?)),
+                    }
+                    if !p.comma() {
+                        break;
+                    }
+                }
+                Item::Bytes(data)
+            },
+            "dw" => {
+                let mut words = vec![p.expr()?];
+                while p.comma() {
+                    words.push(p.expr()?);
+                }
+                Item::Words(words)
+            },
+            "include" => {
+                let path = match p.next() {
+                    Some((Tok::Str(path), _)) => path,
+                    _ => return Err((pos, "include expects a quoted file name".to_string())),
+                };
+                p.end()?;
+                return Ok(Some((path, pos)));
+            },
+            _ => {
+                let mut operands = Vec::new();
+                if !p.at_end() {
+                    operands.push(p.operand()?);
+                    while p.comma() {
+                        operands.push(p.operand()?);
+                    }
+                }
+                Item::Instruction { mnemonic: word.to_uppercase(), operands }
+            },
+        };
+        p.end()?;
+
+        let size = match &item {
+            Item::Instruction { operands, .. } => match operands.as_slice() {
+                [Operand::I, Operand::Long(_)] => 4,
+                _ => 2,
+            },
+            Item::Bytes(data) => data.iter().map(|d| match d {
+                Data::Str(s) => s.len(),
+                Data::Expr(_) => 1,
+            }).sum(),
+            Item::Words(words) => words.len() * 2,
+        };
+        self.items.push((pos.clone(), item));
+        self.addr += size;
+        if self.addr - PROGRAM_START as usize > self.target.max_rom_size() {
+            return Err((pos, format!("Program doesn't fit in {} memory ({} bytes max)",
+                self.target.name(), self.target.max_rom_size())));
+        }
+        Ok(None)
+    }
+
+    fn define(&mut self, name: String, symbol: Symbol, pos: Pos) -> Result<(), (Pos, String)> {
+        if is_reserved(&name) {
+            return Err((pos, format!("'{}' is a register or keyword and can't be used as a name", name)));
+        }
+        if let Some((_, prev)) = self.symbols.get(&name) {
+            return Err((pos, format!("'{}' is already defined at {}:{}", name, prev.line, prev.col)));
+        }
+        self.symbols.insert(name, (symbol, pos));
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr, depth: usize) -> Result<i64, (Pos, String)> {
+        if depth > MAX_EXPR_DEPTH {
+            return Err((expr.pos.clone(), "Constant refers back to itself".to_string()));
+        }
+        let mut total: i64 = 0;
+        for (negate, term) in &expr.terms {
+            let value = match term {
+                Term::Num(n) => *n,
+                Term::Sym(name) => match self.symbols.get(name) {
+                    Some((Symbol::Label(addr), _)) => *addr as i64,
+                    Some((Symbol::Const(e), _)) => self.eval(e, depth + 1)?,
+                    None => return Err((expr.pos.clone(), format!("Undefined label or constant '{}'", name))),
+                },
+            };
+            total = if *negate { total.wrapping_sub(value) } else { total.wrapping_add(value) };
+        }
+        Ok(total)
+    }
+
+    // Negative values are accepted down to -(max+1)/2 and stored two's complement
+    fn eval_range(&self, expr: &Expr, max: i64, what: &str) -> Result<u16, (Pos, String)> {
+        let v = self.eval(expr, 0)?;
+        if v > max || v < -(max + 1) / 2 {
+            return Err((expr.pos.clone(), format!("{} {} is out of range (0 to {})", what, v, max)));
+        }
+        Ok((v & max) as u16)
+    }
+
+    fn finish(self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::with_capacity(self.addr - PROGRAM_START as usize);
+        for (pos, item) in &self.items {
+            match item {
+                Item::Instruction { mnemonic, operands } => {
+                    let (words, needs) = self.encode(pos, mnemonic, operands).map_err(|(pos, msg)| self.error(&pos, msg))?;
+                    if needs > self.target {
+                        return Err(self.error(pos, format!("{} needs the {} instruction set, assembling for {}",
+                            mnemonic, needs.name(), self.target.name())));
+                    }
+                    for w in words {
+                        rom.extend_from_slice(&w.to_be_bytes());
+                    }
+                },
+                Item::Bytes(data) => for d in data {
+                    match d {
+                        Data::Str(s) => rom.extend_from_slice(s),
+                        Data::Expr(e) => rom.push(self.eval_range(e, 0xFF, "Byte").map_err(|(pos, msg)| self.error(&pos, msg))? as u8),
+                    }
+                },
+                Item::Words(words) => for w in words {
+                    let w = self.eval_range(w, 0xFFFF, "Word").map_err(|(pos, msg)| self.error(&pos, msg))?;
+                    rom.extend_from_slice(&w.to_be_bytes());
+                },
+            }
+        }
+        Ok(rom)
+    }
+
+    // Opcode words for one instruction and the smallest instruction set that has it
+    fn encode(&self, pos: &Pos, mnemonic: &str, ops: &[Operand]) -> Result<(Vec<u16>, Target), (Pos, String)> {
+        use Operand::*;
+        use Target::*;
+        let addr = |e: &self::Expr| self.eval_range(e, 0xFFF, "Address");
+        let byte = |e: &self::Expr| self.eval_range(e, 0xFF, "Byte");
+        let nibble = |e: &self::Expr| self.eval_range(e, 0xF, "Value");
+        let xy = |x: &u8, y: &u8| (*x as u16) << 8 | (*y as u16) << 4;
+
+        let (op, needs) = match (mnemonic, ops) {
+            ("CLS", []) => (0x00E0, Chip8),
+            ("RET", []) => (0x00EE, Chip8),
+            ("SYS", [Expr(e)]) => (addr(e)?, Chip8),
+            ("SCD", [Expr(e)]) => (0x00C0 | nibble(e)?, Schip),
+            ("SCU", [Expr(e)]) => (0x00D0 | nibble(e)?, XoChip),
+            ("SCR", []) => (0x00FB, Schip),
+            ("SCL", []) => (0x00FC, Schip),
+            ("EXIT", []) => (0x00FD, Schip),
+            ("LOW", []) => (0x00FE, Schip),
+            ("HIGH", []) => (0x00FF, Schip),
+            ("JP", [Expr(e)]) => (0x1000 | addr(e)?, Chip8),
+            ("JP", [V(0), Expr(e)]) => (0xB000 | addr(e)?, Chip8),
+            ("CALL", [Expr(e)]) => (0x2000 | addr(e)?, Chip8),
+            ("SE", [V(x), Expr(e)]) => (0x3000 | xy(x, &0) | byte(e)?, Chip8),
+            ("SNE", [V(x), Expr(e)]) => (0x4000 | xy(x, &0) | byte(e)?, Chip8),
+            ("SE", [V(x), V(y)]) => (0x5000 | xy(x, y), Chip8),
+            ("SNE", [V(x), V(y)]) => (0x9000 | xy(x, y), Chip8),
+            ("LD", [IndirectI, Range(x, y)]) => (0x5002 | xy(x, y), XoChip),
+            ("LD", [Range(x, y), IndirectI]) => (0x5003 | xy(x, y), XoChip),
+            ("LD", [V(x), Expr(e)]) => (0x6000 | xy(x, &0) | byte(e)?, Chip8),
+            ("ADD", [V(x), Expr(e)]) => (0x7000 | xy(x, &0) | byte(e)?, Chip8),
+            ("LD", [V(x), V(y)]) => (0x8000 | xy(x, y), Chip8),
+            ("OR", [V(x), V(y)]) => (0x8001 | xy(x, y), Chip8),
+            ("AND", [V(x), V(y)]) => (0x8002 | xy(x, y), Chip8),
+            ("XOR", [V(x), V(y)]) => (0x8003 | xy(x, y), Chip8),
+            ("ADD", [V(x), V(y)]) => (0x8004 | xy(x, y), Chip8),
+            ("SUB", [V(x), V(y)]) => (0x8005 | xy(x, y), Chip8),
+            ("SHR", [V(x), V(y)]) => (0x8006 | xy(x, y), Chip8),
+            // Shifting Vx into itself behaves the same whichever shift quirk is in effect
+            ("SHR", [V(x)]) => (0x8006 | xy(x, x), Chip8),
+            ("SUBN", [V(x), V(y)]) => (0x8007 | xy(x, y), Chip8),
+            ("SHL", [V(x), V(y)]) => (0x800E | xy(x, y), Chip8),
+            ("SHL", [V(x)]) => (0x800E | xy(x, x), Chip8),
+            ("LD", [I, Expr(e)]) => (0xA000 | addr(e)?, Chip8),
+            ("RND", [V(x), Expr(e)]) => (0xC000 | xy(x, &0) | byte(e)?, Chip8),
+            ("DRW", [V(x), V(y), Expr(e)]) => (0xD000 | xy(x, y) | nibble(e)?, Chip8),
+            ("SKP", [V(x)]) => (0xE09E | xy(x, &0), Chip8),
+            ("SKNP", [V(x)]) => (0xE0A1 | xy(x, &0), Chip8),
+            ("LD", [I, Long(e)]) => {
+                let long = self.eval_range(e, 0xFFFF, "Address")?;
+                return Ok((vec![0xF000, long], XoChip));
+            },
+            ("PLANE", [Expr(e)]) => (0xF001 | nibble(e)? << 8, XoChip),
+            ("AUDIO", []) => (0xF002, XoChip),
+            ("LD", [V(x), Dt]) => (0xF007 | xy(x, &0), Chip8),
+            ("LD", [V(x), K]) => (0xF00A | xy(x, &0), Chip8),
+            ("LD", [Dt, V(x)]) => (0xF015 | xy(x, &0), Chip8),
+            ("LD", [St, V(x)]) => (0xF018 | xy(x, &0), Chip8),
+            ("ADD", [I, V(x)]) => (0xF01E | xy(x, &0), Chip8),
+            ("LD", [F, V(x)]) => (0xF029 | xy(x, &0), Chip8),
+            ("LD", [Hf, V(x)]) => (0xF030 | xy(x, &0), Schip),
+            ("LD", [B, V(x)]) => (0xF033 | xy(x, &0), Chip8),
+            ("PITCH", [V(x)]) => (0xF03A | xy(x, &0), XoChip),
+            ("LD", [IndirectI, V(x)]) => (0xF055 | xy(x, &0), Chip8),
+            ("LD", [V(x), IndirectI]) => (0xF065 | xy(x, &0), Chip8),
+            // The original SCHIP only had 8 flag registers
+            ("LD", [R, V(x)]) => (0xF075 | xy(x, &0), if *x < 8 { Schip } else { XoChip }),
+            ("LD", [V(x), R]) => (0xF085 | xy(x, &0), if *x < 8 { Schip } else { XoChip }),
+            _ if !is_mnemonic(mnemonic) => return Err((pos.clone(), format!("Unknown instruction or directive '{}'", mnemonic))),
+            _ => return Err((pos.clone(), format!("Invalid operands for {}", mnemonic))),
+        };
+        Ok((vec![op], needs))
+    }
+}
+
+const MNEMONICS: [&str; 30] = [
+    "CLS", "RET", "SYS", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE", "SNE", "LD", "ADD",
+    "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+fn is_mnemonic(s: &str) -> bool {
+    MNEMONICS.contains(&s)
+}
+
+fn register(s: &str) -> Option<u8> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(c), None) | (Some('V'), Some(c), None) => c.to_digit(16).map(|d| d as u8),
+        _ => None
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    let upper = name.to_uppercase();
+    register(name).is_some() || is_mnemonic(&upper)
+        || ["I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG", "DB", "DW", "EQU", "INCLUDE"].contains(&upper.as_str())
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    at: usize,
+    file: usize,
+    line: usize,
+    // Column reported for errors at the end of the line
+    end_col: usize,
+}
+
+impl<'a> Parser<'a> {
+
+    fn peek(&self, ahead: usize) -> Option<&'a Tok> {
+        self.tokens.get(self.at + ahead).map(|t| &t.tok)
+    }
+
+    fn pos(&self) -> Pos {
+        let col = self.tokens.get(self.at).map_or(self.end_col, |t| t.col);
+        Pos { file: self.file, line: self.line, col }
+    }
+
+    fn next(&mut self) -> Option<(Tok, Pos)> {
+        let pos = self.pos();
+        let tok = self.tokens.get(self.at)?.tok.clone();
+        self.at += 1;
+        Some((tok, pos))
+    }
+
+    fn at_end(&self) -> bool {
+        self.at >= self.tokens.len()
+    }
+
+    fn end(&self) -> Result<(), (Pos, String)> {
+        if self.at_end() {
+            Ok(())
+        } else {
+            Err((self.pos(), "Unexpected text at the end of the line".to_string()))
+        }
+    }
+
+    fn comma(&mut self) -> bool {
+        if self.peek(0) == Some(&Tok::Punct(',')) {
+            self.at += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, (Pos, String)> {
+        let pos = self.pos();
+        if self.peek(0) == Some(&Tok::Punct('[')) {
+            match (self.peek(1), self.peek(2)) {
+                (Some(Tok::Ident(i)), Some(Tok::Punct(']'))) if i.eq_ignore_ascii_case("i") => {
+                    self.at += 3;
+                    return Ok(Operand::IndirectI);
+                },
+                _ => return Err((pos, "Expected [I]".to_string())),
+            }
+        }
+        if let Some(Tok::Ident(word)) = self.peek(0) {
+            if let Some(x) = register(word) {
+                self.at += 1;
+                // Vx-Vy register range
+                if let (Some(Tok::Punct('-')), Some(Tok::Ident(w))) = (self.peek(0), self.peek(1)) {
+                    if let Some(y) = register(w) {
+                        self.at += 2;
+                        return Ok(Operand::Range(x, y));
+                    }
+                }
+                return Ok(Operand::V(x));
+            }
+            let op = match word.to_uppercase().as_str() {
+                "I" => Operand::I,
+                "DT" => Operand::Dt,
+                "ST" => Operand::St,
+                "K" => Operand::K,
+                "F" => Operand::F,
+                "HF" => Operand::Hf,
+                "B" => Operand::B,
+                "R" => Operand::R,
+                "LONG" => {
+                    self.at += 1;
+                    return Ok(Operand::Long(self.expr()?));
+                },
+                _ => return Ok(Operand::Expr(self.expr()?)),
+            };
+            self.at += 1;
+            return Ok(op);
+        }
+        Ok(Operand::Expr(self.expr()?))
+    }
+
+    fn expr(&mut self) -> Result<Expr, (Pos, String)> {
+        let pos = self.pos();
+        let mut terms = Vec::new();
+        let mut negate = false;
+        if self.peek(0) == Some(&Tok::Punct('-')) {
+            self.at += 1;
+            negate = true;
+        }
+        loop {
+            let term = match self.next() {
+                Some((Tok::Num(n), _)) => Term::Num(n),
+                Some((Tok::Ident(name), _)) => Term::Sym(name),
+                Some((_, pos)) => return Err((pos, "Expected a number, label or constant".to_string())),
+                None => return Err((self.pos(), "Expected a number, label or constant".to_string())),
+            };
+            terms.push((negate, term));
+            negate = match self.peek(0) {
+                Some(Tok::Punct('+')) => false,
+                Some(Tok::Punct('-')) => true,
+                _ => break,
+            };
+            self.at += 1;
+        }
+        Ok(Expr { terms, pos })
+    }
+}
+
+// Splits one line into tokens, errors carry the 1-based column
+fn tokenize(line: &str) -> Result<Vec<Token>, (usize, String)> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut at = 0;
+
+    while at < chars.len() {
+        let c = chars[at];
+        let col = at + 1;
+        if c == ';' {
+            break;
+        }
+        if c.is_whitespace() {
+            at += 1;
+            continue;
+        }
+
+        let start = at;
+        let take = |at: &mut usize, pred: &dyn Fn(char) -> bool| {
+            let from = *at;
+            while *at < chars.len() && pred(chars[*at]) {
+                *at += 1;
+            }
+            chars[from..*at].iter().collect::<String>()
+        };
+
+        let tok = if c.is_ascii_alphabetic() || c == '_' {
+            Tok::Ident(take(&mut at, &|c| c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+        } else if c.is_ascii_digit() || c == '#' || c == '$' || c == '%' {
+            let (radix, digits) = if c == '#' || c == '$' {
+                at += 1;
+                (16, take(&mut at, &|c| c.is_ascii_hexdigit()))
+            } else if c == '%' {
+                at += 1;
+                let bits = take(&mut at, &|c| c == '0' || c == '1' || c == '.' || c == '#');
+                (2, bits.replace('.', "0").replace('#', "1"))
+            } else if c == '0' && chars.get(at + 1).is_some_and(|c| *c == 'x' || *c == 'X') {
+                at += 2;
+                (16, take(&mut at, &|c| c.is_ascii_hexdigit()))
+            } else {
+                (10, take(&mut at, &|c| c.is_ascii_digit()))
+            };
+            if at < chars.len() && (chars[at].is_ascii_alphanumeric() || chars[at] == '_') {
+                take(&mut at, &|c| c.is_ascii_alphanumeric() || c == '_');
+                return Err((col, format!("Invalid number '{}'", chars[start..at].iter().collect::<String>())));
+            }
+            match i64::from_str_radix(&digits, radix) {
+                Ok(n) if !digits.is_empty() => Tok::Num(n),
+                _ => return Err((col, format!("Invalid number '{}'", chars[start..at].iter().collect::<String>()))),
+            }
+        } else if c == '"' {
+            at += 1;
+            let mut s = String::new();
+            loop {
+                match chars.get(at) {
+                    None => return Err((col, "Unterminated string".to_string())),
+                    Some('"') => break,
+                    Some('\\') => {
+                        at += 1;
+                        match chars.get(at) {
+                            Some('n') => s.push('\n'),
+                            Some('0') => s.push('\0'),
+                            Some(c) => s.push(*c),
+                            None => return Err((col, "Unterminated string".to_string())),
+                        }
+                    },
+                    Some(c) => s.push(*c),
+                }
+                at += 1;
+            }
+            at += 1;
+            Tok::Str(s)
+        } else if ":,[]+-".contains(c) {
+            at += 1;
+            Tok::Punct(c)
+        } else {
+            return Err((col, format!("Unexpected character '{}'", c)));
+        };
+        tokens.push(Token { tok, col });
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::{self, Analysis, Syntax};
+
+    fn error(source: &str, target: Target) -> AsmError {
+        assemble(source, target).unwrap_err()
+    }
+
+    // Code, a sprite the trace finds, a wide sprite, XO-CHIP instructions and data nothing reaches
+    const ROM: &[u8] = &[
+        0x00, 0xFF,                     // HIGH
+        0xA2, 0x14,                     // LD I, 214
+        0xD0, 0x13,                     // DRW V0, V1, 3
+        0xF0, 0x00, 0x02, 0x17,         // LD I, long 217
+        0xD0, 0x10,                     // DRW V0, V1, 0
+        0x52, 0x42,                     // LD [I], V2-V4
+        0x22, 0x12,                     // CALL 212
+        0x12, 0x10,                     // JP 210
+        0x00, 0xEE,                     // RET
+        0x3C, 0x42, 0x3C,               // 3 row sprite
+        0xFF, 0xFF, 0x80, 0x01,         // the start of a 16x16 sprite
+        0x12,                           // an odd byte at the end
+    ];
+
+    fn round_trip(rom: &[u8], analysis: &Analysis) {
+        let listing = disasm::listing(rom, analysis, Syntax::Cowgod);
+        let assembled = assemble(&listing, Target::XoChip).unwrap_or_else(|e| panic!("{}\n{}", e, listing));
+        assert_eq!(assembled, rom, "\n{}", listing);
+    }
+
+    #[test]
+    fn assembles_traced_listings_back_to_the_rom() {
+        round_trip(ROM, &Analysis::trace(ROM));
+    }
+
+    #[test]
+    fn assembles_linear_listings_back_to_the_rom() {
+        round_trip(ROM, &Analysis::linear(ROM));
+        // Every word, instruction or not
+        let all: Vec<u8> = (0..=255).collect();
+        round_trip(&all, &Analysis::linear(&all));
+    }
+
+    #[test]
+    fn assembles_listings_of_the_test_roms_back() {
+        for name in &["font.8o", "flags.8o", "hires.8o", "keywait.8o"] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms").join(name);
+            let rom = crate::octo::compile_file(path.to_str().unwrap()).unwrap().rom;
+            round_trip(&rom, &Analysis::trace(&rom));
+            round_trip(&rom, &Analysis::linear(&rom));
+        }
+    }
+
+    #[test]
+    fn labels_constants_and_data() {
+        let source = "
+            rows    equ 2
+            start:  LD V0, rows+1       ; a constant plus a number
+                    LD I, smiley
+                    DRW V0, V1, rows
+            loop:   JP loop
+            smiley: DB %.#..#..., %#.....#.
+                    DW #1234, start+2
+                    DB \"hi\", 0, -1
+                    JP end
+            end:
+        ";
+        assert_eq!(assemble(source, Target::Chip8).unwrap(), [
+            0x60, 0x03, 0xA2, 0x08, 0xD0, 0x12, 0x12, 0x06,
+            0x48, 0x82, 0x12, 0x34, 0x02, 0x02, b'h', b'i', 0x00, 0xFF, 0x12, 0x14,
+        ]);
+    }
+
+    #[test]
+    fn includes_relative_to_the_including_file() {
+        let dir = std::env::temp_dir().join(format!("rusty-chip8-asm-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("main.8s"), "JP routine\ninclude \"lib/sub.8s\"\n").unwrap();
+        std::fs::write(dir.join("lib").join("sub.8s"), "routine: CLS\n    include \"more.8s\"\n").unwrap();
+        std::fs::write(dir.join("lib").join("more.8s"), "RET\n").unwrap();
+        let rom = assemble_file(dir.join("main.8s").to_str().unwrap(), Target::Chip8);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(rom.unwrap(), [0x12, 0x02, 0x00, 0xE0, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn rejects_instructions_the_target_lacks() {
+        let e = error("CLS\nSCD 4", Target::Chip8);
+        assert_eq!((e.line, e.message.as_str()), (2, "SCD needs the schip instruction set, assembling for chip8"));
+        assert_eq!(error("LD I, long #1234", Target::Schip).message, "LD needs the xochip instruction set, assembling for schip");
+        assert_eq!(error("LD R, V8", Target::Schip).message, "LD needs the xochip instruction set, assembling for schip");
+        assert!(assemble("SCD 4\nLD R, V7", Target::Schip).is_ok());
+        assert!(assemble("SCU 4", Target::XoChip).is_ok());
+    }
+
+    #[test]
+    fn errors_point_at_the_line_and_column() {
+        let at = |e: AsmError| (e.line, e.col, e.message);
+        assert_eq!(at(error("CLS\n\n    JP nowhere", Target::Chip8)), (3, 8, "Undefined label or constant 'nowhere'".to_string()));
+        assert_eq!(at(error("LD V0, 256", Target::Chip8)), (1, 8, "Byte 256 is out of range (0 to 255)".to_string()));
+        assert_eq!(at(error("CLS\nFOO V1", Target::Chip8)), (2, 1, "Unknown instruction or directive 'FOO'".to_string()));
+        assert_eq!(at(error("ADD V1, I", Target::Chip8)), (1, 1, "Invalid operands for ADD".to_string()));
+        assert_eq!(at(error("a: CLS\na: RET", Target::Chip8)), (2, 1, "'a' is already defined at 1:1".to_string()));
+        assert_eq!(at(error("x equ y\ny equ x\nLD V0, x", Target::Chip8)).2, "Constant refers back to itself");
+        assert_eq!(at(error("LD V0, 12ab", Target::Chip8)), (1, 8, "Invalid number '12ab'".to_string()));
+        assert_eq!(error("CLS\nJP", Target::Chip8).file, "<source>");
+    }
+}
@@ -0,0 +1,156 @@
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+const SAMPLE_RATE: i32 = 44100;
+// How long the beeper takes to fade in/out, short enough to sound instant but long enough to not click
+const RAMP_SECONDS: f32 = 0.005;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl Waveform {
+    // phase is in [0, 1)
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (phase * 2.0 * PI).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        }
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            _ => Err(format!("Unknown waveform '{}'. Expected one of: square, sine, triangle", s))
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BeeperSettings {
+    pub waveform: Waveform,
+    // Hz
+    pub frequency: f32,
+    // 0.0 - 1.0
+    pub volume: f32,
+}
+
+impl Default for BeeperSettings {
+    fn default() -> Self {
+        BeeperSettings {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.25,
+        }
+    }
+}
+
+/**
+ *  SDL audio callback playing the CHIP-8 buzzer. The main loop flips it on and off
+ *  every frame from Chip8::buzzer_active(), the gain is ramped towards the target
+ *  so the tone never starts or stops in the middle of a wave.
+ */
+pub struct Beeper {
+    settings: BeeperSettings,
+    sample_rate: f32,
+    phase: f32,
+    gain: f32,
+    ramp_step: f32,
+    active: bool,
+    muted: bool,
+    // XO-CHIP programs bring their own 1-bit waveform and playback rate
+    pattern: Option<([u8; 16], f32)>,
+    pattern_phase: f32,
+}
+
+impl Beeper {
+
+    pub fn open(audio: &sdl2::AudioSubsystem, settings: BeeperSettings) -> Result<AudioDevice<Beeper>, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(512),
+        };
+
+        let device = audio.open_playback(None, &desired, |spec| {
+            let sample_rate = spec.freq as f32;
+            Beeper {
+                settings,
+                sample_rate,
+                phase: 0.0,
+                gain: 0.0,
+                ramp_step: 1.0 / (RAMP_SECONDS * sample_rate),
+                active: false,
+                muted: false,
+                pattern: None,
+                pattern_phase: 0.0,
+            }
+        })?;
+        device.resume();
+        Ok(device)
+    }
+
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    pub fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, playback_rate: f32) {
+        self.pattern = pattern.map(|p| (*p, playback_rate));
+    }
+
+    // Returns true if the beeper is now muted
+    pub fn toggle_mute(&mut self) -> bool {
+        self.muted = !self.muted;
+        self.muted
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        match self.pattern {
+            Some((pattern, rate)) => {
+                let bit = self.pattern_phase as usize;
+                self.pattern_phase = (self.pattern_phase + rate / self.sample_rate) % 128.0;
+                if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 }
+            },
+            None => {
+                let sample = self.settings.waveform.sample(self.phase);
+                self.phase = (self.phase + self.settings.frequency / self.sample_rate) % 1.0;
+                sample
+            }
+        }
+    }
+}
+
+impl AudioCallback for Beeper {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let target = if self.active && !self.muted { 1.0 } else { 0.0 };
+        for x in out.iter_mut() {
+            if self.gain < target {
+                self.gain = (self.gain + self.ramp_step).min(target);
+            } else if self.gain > target {
+                self.gain = (self.gain - self.ramp_step).max(target);
+            }
+
+            *x = if self.gain > 0.0 {
+                self.next_sample() * self.gain * self.settings.volume
+            } else {
+                // Restart from the top of the wave so the next beep ramps in cleanly
+                self.phase = 0.0;
+                self.pattern_phase = 0.0;
+                0.0
+            };
+        }
+    }
+}
@@ -0,0 +1,548 @@
+/**
+ *  What the 1802 is wired to. Memory, the N lines (OUT/INP ports 1-7) and the four EF input flags.
+ */
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+    fn input(&mut self, port: u8) -> u8;
+    fn output(&mut self, port: u8, value: u8);
+    // EF1-EF4, true when the line is asserted (pulled low on the real chip)
+    fn ef(&self, flag: u8) -> bool;
+}
+
+/**
+ *  RCA CDP1802 COSMAC CPU.
+ *  Sixteen 16 bit registers any of which can be the program counter (P) or data pointer (X),
+ *  an 8 bit accumulator D with carry DF, and the Q output line.
+ *  Timing is counted in machine cycles (8 clock pulses): 2 per instruction, 3 for long branches/skips.
+ */
+#[derive(Debug, Clone)]
+pub struct Cdp1802 {
+    pub r: [u16; 16],
+    pub d: u8,
+    pub df: bool,
+    pub p: u8,
+    pub x: u8,
+    // X and P saved by an interrupt or MARK
+    pub t: u8,
+    pub ie: bool,
+    pub q: bool,
+    // Stopped by IDL until the next DMA or interrupt
+    pub idle: bool,
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Self {
+        Cdp1802::new()
+    }
+}
+
+impl Cdp1802 {
+    // State after a reset: R0 is the program counter and data pointer, interrupts on
+    pub fn new() -> Self {
+        Cdp1802 {
+            r: [0; 16],
+            d: 0,
+            df: false,
+            p: 0,
+            x: 0,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        }
+    }
+
+    // Next byte at R(P), advancing it
+    fn fetch(&mut self, bus: &mut dyn Bus) -> u8 {
+        let p = self.p as usize;
+        let value = bus.read(self.r[p]);
+        self.r[p] = self.r[p].wrapping_add(1);
+        value
+    }
+
+    fn rx(&self) -> u16 {
+        self.r[self.x as usize]
+    }
+
+    fn inc_x(&mut self) {
+        let x = self.x as usize;
+        self.r[x] = self.r[x].wrapping_add(1);
+    }
+
+    /**
+     *  Takes an interrupt if they're enabled. X and P are saved in T, then R1 becomes the program
+     *  counter and R2 the data pointer. Returns the machine cycles used, 0 when interrupts are off.
+     */
+    pub fn interrupt(&mut self) -> u32 {
+        if !self.ie {
+            return 0;
+        }
+        self.t = self.x << 4 | self.p;
+        self.p = 1;
+        self.x = 2;
+        self.ie = false;
+        self.idle = false;
+        1
+    }
+
+    // One DMA out cycle: the byte at R0 goes to the device and R0 moves on
+    pub fn dma_out(&mut self, bus: &mut dyn Bus) -> u8 {
+        let value = bus.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        value
+    }
+
+    /**
+     *  Runs one instruction and returns the machine cycles it took.
+     *  While idle nothing happens and 1 cycle passes.
+     */
+    pub fn step(&mut self, bus: &mut dyn Bus) -> u32 {
+        if self.idle {
+            return 1;
+        }
+        let opcode = self.fetch(bus);
+        let n = (opcode & 0x0F) as usize;
+
+        match opcode >> 4 {
+            0x0 => {
+                if n == 0 {
+                    // IDL
+                    self.idle = true;
+                } else {
+                    // LDN - D = M(RN)
+                    self.d = bus.read(self.r[n]);
+                }
+            },
+            // INC / DEC
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            // Short branches, to the byte after the opcode within the current page
+            0x3 => {
+                let taken = match n {
+                    0x0 => true,
+                    0x1 => self.q,
+                    0x2 => self.d == 0,
+                    0x3 => self.df,
+                    0x4..=0x7 => bus.ef(n as u8 - 0x3),
+                    // SKP
+                    0x8 => false,
+                    0x9 => !self.q,
+                    0xA => self.d != 0,
+                    0xB => !self.df,
+                    _ => !bus.ef(n as u8 - 0xB),
+                };
+                let p = self.p as usize;
+                if taken {
+                    let target = bus.read(self.r[p]);
+                    self.r[p] = self.r[p] & 0xFF00 | target as u16;
+                } else {
+                    self.r[p] = self.r[p].wrapping_add(1);
+                }
+            },
+            // LDA - D = M(RN), RN + 1
+            0x4 => {
+                self.d = bus.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            },
+            // STR - M(RN) = D
+            0x5 => bus.write(self.r[n], self.d),
+            0x6 => match n {
+                // IRX
+                0x0 => self.inc_x(),
+                // OUT 1-7 - M(RX) to the port, RX + 1
+                0x1..=0x7 => {
+                    let value = bus.read(self.rx());
+                    bus.output(n as u8, value);
+                    self.inc_x();
+                },
+                // 68 is unused on the 1802
+                0x8 => {},
+                // INP 1-7 - port to M(RX) and D
+                _ => {
+                    let value = bus.input(n as u8 - 0x8);
+                    bus.write(self.rx(), value);
+                    self.d = value;
+                },
+            },
+            0x7 => match n {
+                // RET / DIS - restore X and P from M(RX), RX + 1
+                0x0 | 0x1 => {
+                    let xp = bus.read(self.rx());
+                    self.inc_x();
+                    self.x = xp >> 4;
+                    self.p = xp & 0x0F;
+                    self.ie = n == 0;
+                },
+                // LDXA
+                0x2 => {
+                    self.d = bus.read(self.rx());
+                    self.inc_x();
+                },
+                // STXD
+                0x3 => {
+                    bus.write(self.rx(), self.d);
+                    let x = self.x as usize;
+                    self.r[x] = self.r[x].wrapping_sub(1);
+                },
+                // ADC / SDB / SMB, and ADCI / SDBI / SMBI with the operand at R(P)
+                0x4 | 0x5 | 0x7 => {
+                    let m = bus.read(self.rx());
+                    self.arithmetic(n, m, self.df);
+                },
+                0xC | 0xD | 0xF => {
+                    let m = self.fetch(bus);
+                    self.arithmetic(n - 0x8, m, self.df);
+                },
+                // SHRC
+                0x6 => {
+                    let carry = self.d & 1 != 0;
+                    self.d = self.d >> 1 | (self.df as u8) << 7;
+                    self.df = carry;
+                },
+                // SHLC
+                0xE => {
+                    let carry = self.d & 0x80 != 0;
+                    self.d = self.d << 1 | self.df as u8;
+                    self.df = carry;
+                },
+                // SAV - M(RX) = T
+                0x8 => bus.write(self.rx(), self.t),
+                // MARK - T = XP, M(R2) = T, X = P, R2 - 1
+                0x9 => {
+                    self.t = self.x << 4 | self.p;
+                    bus.write(self.r[2], self.t);
+                    self.x = self.p;
+                    self.r[2] = self.r[2].wrapping_sub(1);
+                },
+                // REQ / SEQ
+                0xA => self.q = false,
+                _ => self.q = true,
+            },
+            // GLO / GHI / PLO / PHI
+            0x8 => self.d = self.r[n] as u8,
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            0xA => self.r[n] = self.r[n] & 0xFF00 | self.d as u16,
+            0xB => self.r[n] = self.r[n] & 0x00FF | (self.d as u16) << 8,
+            // Long branches and skips, 3 cycles each
+            0xC => {
+                let condition = match n & 0x3 {
+                    0x0 => true,
+                    0x1 => self.q,
+                    0x2 => self.d == 0,
+                    _ => self.df,
+                };
+                let p = self.p as usize;
+                match n {
+                    // NOP
+                    0x4 => {},
+                    // LSNQ / LSNZ / LSNF / LSKP, skip the next two bytes if the condition is false
+                    0x5..=0x7 => if !condition { self.r[p] = self.r[p].wrapping_add(2) },
+                    0x8 => self.r[p] = self.r[p].wrapping_add(2),
+                    // LSIE
+                    0xC => if self.ie { self.r[p] = self.r[p].wrapping_add(2) },
+                    // LSQ / LSZ / LSDF
+                    0xD..=0xF => if condition { self.r[p] = self.r[p].wrapping_add(2) },
+                    // LBR / LBQ / LBZ / LBDF and the negated LBNQ / LBNZ / LBNF
+                    _ => {
+                        let taken = if n < 0x8 { condition } else { !condition };
+                        if taken {
+                            let hi = bus.read(self.r[p]) as u16;
+                            let lo = bus.read(self.r[p].wrapping_add(1)) as u16;
+                            self.r[p] = hi << 8 | lo;
+                        } else {
+                            self.r[p] = self.r[p].wrapping_add(2);
+                        }
+                    },
+                }
+                return 3;
+            },
+            // SEP / SEX
+            0xD => self.p = n as u8,
+            0xE => self.x = n as u8,
+            _ => {
+                // F0-F7 take their operand from M(RX), F8-FF are the immediate versions
+                let operand = |cpu: &mut Self, bus: &mut dyn Bus| {
+                    if n < 0x8 { bus.read(cpu.rx()) } else { cpu.fetch(bus) }
+                };
+                match n {
+                    // LDX / LDI
+                    0x0 | 0x8 => self.d = operand(self, bus),
+                    0x1 | 0x9 => self.d |= operand(self, bus),
+                    0x2 | 0xA => self.d &= operand(self, bus),
+                    0x3 | 0xB => self.d ^= operand(self, bus),
+                    // SHR
+                    0x6 => {
+                        self.df = self.d & 1 != 0;
+                        self.d >>= 1;
+                    },
+                    // SHL
+                    0xE => {
+                        self.df = self.d & 0x80 != 0;
+                        self.d <<= 1;
+                    },
+                    // ADD / SD / SM and ADI / SDI / SMI, the same as the carry versions with no carry in
+                    _ => {
+                        let m = operand(self, bus);
+                        self.arithmetic(n & 0x7, m, n & 0x7 != 0x4);
+                    },
+                }
+            },
+        }
+        2
+    }
+
+    /**
+     *  op 4 adds, 5 is M - D and 7 is D - M, carry in and out in DF.
+     *  For subtraction DF is set when there was no borrow, carry is the incoming DF.
+     */
+    fn arithmetic(&mut self, op: usize, m: u8, carry: bool) {
+        let (a, b) = match op {
+            0x4 => {
+                let sum = self.d as u16 + m as u16 + carry as u16;
+                self.d = sum as u8;
+                self.df = sum > 0xFF;
+                return;
+            },
+            0x5 => (m, self.d),
+            _ => (self.d, m),
+        };
+        let result = a as i16 - b as i16 - !carry as i16;
+        self.d = result as u8;
+        self.df = result >= 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 64 KB of RAM with the EF lines set by the test and OUT writes recorded
+    struct TestBus {
+        memory: [u8; 0x10000],
+        ef: [bool; 4],
+        output: Option<(u8, u8)>,
+    }
+
+    impl Bus for TestBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.memory[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, value: u8) {
+            self.memory[addr as usize] = value;
+        }
+
+        fn input(&mut self, port: u8) -> u8 {
+            0x10 + port
+        }
+
+        fn output(&mut self, port: u8, value: u8) {
+            self.output = Some((port, value));
+        }
+
+        fn ef(&self, flag: u8) -> bool {
+            self.ef[flag as usize - 1]
+        }
+    }
+
+    fn bus(program: &[u8]) -> TestBus {
+        let mut bus = TestBus { memory: [0; 0x10000], ef: [false; 4], output: None };
+        bus.memory[..program.len()].copy_from_slice(program);
+        bus
+    }
+
+    // Runs the next few instructions and returns the cycles they took
+    fn run(cpu: &mut Cdp1802, bus: &mut TestBus, instructions: usize) -> u32 {
+        (0..instructions).map(|_| cpu.step(bus)).sum()
+    }
+
+    #[test]
+    fn add_carries_into_df() {
+        // LDI F0, ADI 20, ADCI 01, ADCI 01
+        let mut bus = bus(&[0xF8, 0xF0, 0xFC, 0x20, 0x7C, 0x01, 0x7C, 0x01]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!((cpu.d, cpu.df), (0x10, true));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0x12, false));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0x13, false));
+    }
+
+    #[test]
+    fn add_from_memory() {
+        // LDI 80, SEX 3 with R3 at 0, ADD: 0x80 + M(0) = 0x80 + 0xF8
+        let mut bus = bus(&[0xF8, 0x80, 0xE3, 0xF4]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 3);
+        assert_eq!((cpu.d, cpu.df), (0x78, true));
+    }
+
+    #[test]
+    fn subtract_clears_df_on_borrow() {
+        // LDI 10, SMI 20, SMBI 01
+        let mut bus = bus(&[0xF8, 0x10, 0xFF, 0x20, 0x7F, 0x01]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!((cpu.d, cpu.df), (0xF0, false));
+        // The borrow takes one more off
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0xEE, true));
+    }
+
+    #[test]
+    fn subtract_d_from_memory() {
+        // LDI 10, SDI 30, SDI 00, SDBI 00
+        let mut bus = bus(&[0xF8, 0x10, 0xFD, 0x30, 0xFD, 0x00, 0x7D, 0x00]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!((cpu.d, cpu.df), (0x20, true));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0xE0, false));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0x1F, false));
+    }
+
+    #[test]
+    fn shifts_move_bits_through_df() {
+        // LDI 81, SHR, SHL, SHL
+        let mut bus = bus(&[0xF8, 0x81, 0xF6, 0xFE, 0xFE]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!((cpu.d, cpu.df), (0x40, true));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0x80, false));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0x00, true));
+    }
+
+    #[test]
+    fn ring_shifts_bring_df_in() {
+        // LDI 81, SHLC with DF clear, SHRC, SHRC
+        let mut bus = bus(&[0xF8, 0x81, 0x7E, 0x76, 0x76]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!((cpu.d, cpu.df), (0x02, true));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0x81, false));
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, cpu.df), (0x40, true));
+    }
+
+    #[test]
+    fn short_branches_stay_in_the_page() {
+        let mut cpu = Cdp1802::new();
+        cpu.r[0] = 0x1FE;
+        // BR 40 at the end of a page jumps within the page it fetched the target from
+        let mut bus = bus(&[]);
+        bus.memory[0x1FE..0x200].copy_from_slice(&[0x30, 0x40]);
+        assert_eq!(cpu.step(&mut bus), 2);
+        assert_eq!(cpu.r[0], 0x140);
+
+        // LDI 00, BNZ 20 falls through, BZ 30 is taken
+        let mut bus = self::bus(&[0xF8, 0x00, 0x3A, 0x20, 0x32, 0x30]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!(cpu.r[0], 0x04);
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!(cpu.r[0], 0x30);
+    }
+
+    #[test]
+    fn short_branches_on_ef() {
+        // B3 10, BN3 20
+        let mut bus = bus(&[0x36, 0x10, 0x3E, 0x20]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!(cpu.r[0], 0x20);
+
+        let mut cpu = Cdp1802::new();
+        bus.ef[2] = true;
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!(cpu.r[0], 0x10);
+    }
+
+    #[test]
+    fn long_branches_and_skips_take_three_cycles() {
+        // LBR 1234
+        let mut bus = bus(&[0xC0, 0x12, 0x34]);
+        let mut cpu = Cdp1802::new();
+        assert_eq!(run(&mut cpu, &mut bus, 1), 3);
+        assert_eq!(cpu.r[0], 0x1234);
+
+        // LBNZ 1234 with D = 0 falls through, LSZ skips the two bytes after it
+        let mut bus = self::bus(&[0xCA, 0x12, 0x34, 0xCE, 0xF8, 0x01, 0xF8, 0x02]);
+        let mut cpu = Cdp1802::new();
+        assert_eq!(run(&mut cpu, &mut bus, 3), 3 + 3 + 2);
+        assert_eq!((cpu.r[0], cpu.d), (0x08, 0x02));
+    }
+
+    #[test]
+    fn sep_and_sex_switch_registers() {
+        let program = [
+            // R3 = 0010, R5 = 0040, SEP 3
+            0xF8, 0x10, 0xA3, 0xF8, 0x40, 0xA5, 0xD3, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // 0010: SEX 5, LDI AA, STXD, SEP 0
+            0xE5, 0xF8, 0xAA, 0x73, 0xD0,
+        ];
+        let mut bus = bus(&program);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 5);
+        assert_eq!((cpu.p, cpu.r[3]), (3, 0x10));
+        run(&mut cpu, &mut bus, 4);
+        assert_eq!((cpu.p, cpu.x), (0, 5));
+        assert_eq!(bus.memory[0x40], 0xAA);
+        assert_eq!(cpu.r[5], 0x3F);
+        // R0 carries on after the SEP 3
+        assert_eq!(cpu.r[0], 0x07);
+        assert_eq!(cpu.r[3], 0x15);
+    }
+
+    #[test]
+    fn out_and_inp_go_through_rx() {
+        // SEX 3 with R3 at 0, OUT 2 sends M(R3) then increments it, INP 4
+        let mut bus = bus(&[0xE3, 0x62, 0x6C]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!(bus.output, Some((2, 0xE3)));
+        assert_eq!(cpu.r[3], 1);
+        run(&mut cpu, &mut bus, 1);
+        assert_eq!((cpu.d, bus.memory[1]), (0x14, 0x14));
+    }
+
+    #[test]
+    fn interrupt_saves_xp_and_ret_restores_it() {
+        let mut bus = bus(&[]);
+        let mut cpu = Cdp1802::new();
+        cpu.p = 3;
+        cpu.x = 4;
+        cpu.r[1] = 0x100;
+        cpu.r[2] = 0x200;
+        assert_eq!(cpu.interrupt(), 1);
+        assert_eq!((cpu.p, cpu.x, cpu.t, cpu.ie), (1, 2, 0x43, false));
+        // Masked now
+        assert_eq!(cpu.interrupt(), 0);
+
+        // SAV, RET
+        bus.memory[0x100..0x102].copy_from_slice(&[0x78, 0x70]);
+        run(&mut cpu, &mut bus, 2);
+        assert_eq!((cpu.p, cpu.x, cpu.ie), (3, 4, true));
+        assert_eq!(cpu.r[2], 0x201);
+    }
+
+    #[test]
+    fn idle_waits_for_dma() {
+        let mut bus = bus(&[0x00, 0xAB]);
+        let mut cpu = Cdp1802::new();
+        run(&mut cpu, &mut bus, 1);
+        assert!(cpu.idle);
+        assert_eq!(run(&mut cpu, &mut bus, 3), 3);
+        assert_eq!(cpu.r[0], 1);
+        assert_eq!(cpu.dma_out(&mut bus), 0xAB);
+        assert!(!cpu.idle);
+        assert_eq!(cpu.r[0], 2);
+    }
+}
@@ -1,54 +1,78 @@
-use crate::util::{Flat2DArray};
-use rand::{thread_rng, Rng};
-use std::num::Wrapping;
+use crate::framebuffer::Framebuffer;
+use crate::quirks::{Quirks, MemoryIncrement};
+use crate::rng::{Rng, XorShift};
+#[cfg(feature = "std")]
+use crate::savestate::{StateReader, StateWriter};
+#[cfg(feature = "std")]
+use crate::sanitizer::Sanitizer;
+use core::fmt;
+use core::num::Wrapping;
 
 
-const PROGRAM_START: u16 = 0x200;
+pub const PROGRAM_START: u16 = 0x200;
+// XO-CHIP gives programs the full 16 bit address space
+pub const MEMORY_SIZE: usize = 0x10000;
 
 #[derive(Copy, Clone)]
-struct Opcode(u16);
+pub struct Opcode(pub u16);
 
 impl Opcode {
 
-    fn x(self) -> usize {
+    pub fn x(self) -> usize {
         ((self.0 & 0x0F00) >> 8) as usize
     }
     
-    fn y(self) -> usize {
+    pub fn y(self) -> usize {
         ((self.0 & 0x00F0) >> 4) as usize
     }
+
+    pub fn n(self) -> u8 {
+        (self.0 & 0x000F) as u8
+    }
     
-    fn kk(self) -> u8 {
+    pub fn kk(self) -> u8 {
         (self.0 & 0x00FF) as u8
     }
     
-    fn addr(self) -> u16 {
+    pub fn addr(self) -> u16 {
         self.0 & 0x0FFF
     }
+
+    // Bytes the instruction takes up, F000 nnnn is the only one that takes up two words
+    pub fn size(self) -> u16 {
+        if self.0 == 0xF000 { 4 } else { 2 }
+    }
     
 }
 
 #[derive(Copy, Clone, Default)]
-#[repr(C)]
 struct Pixel {
-    r: u8, g: u8, b: u8, 
-    _pad: u8 // ignored
+    r: u8, g: u8, b: u8
 }
 
 impl Pixel {
     fn new(r: u8, g: u8, b: u8) -> Self {
-        Pixel {
-            r, g, b,
-            _pad: 0 // ignoSred
-        }
+        Pixel { r, g, b }
+    }
+
+    fn from_rgb(rgb: u32) -> Self {
+        Pixel::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
     }
 
-    fn white() -> Self {
-        Pixel::new(255, 255, 255)
+    fn to_rgb(self) -> u32 {
+        (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32
     }
 }
 
+// Each gfx cell holds one bit per XO-CHIP bitplane
+const PLANE_1: u8 = 0b01;
+const PLANE_2: u8 = 0b10;
+
+// Background, plane 1, plane 2, both planes
+pub const DEFAULT_PALETTE: [u32; 4] = [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555];
+
 const FONT_MEM_OFFSET: u16 = 0x00;
+const BIG_FONT_MEM_OFFSET: u16 = FONT_MEM_OFFSET + CHIP8_FONTSET.len() as u16;
 const CHIP8_FONTSET: [u8; 80] = [ 
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
 	0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -68,8 +92,155 @@ const CHIP8_FONTSET: [u8; 80] = [
 	0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
-pub struct Chip8 {
-    memory: [u8; 4096], // 4KB
+// SUPER-CHIP 10 byte (8x10) hex digits, used by Fx30
+const SCHIP_BIG_FONTSET: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+
+// A contiguous block of memory touched by a single instruction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryRange {
+    pub start: u16,
+    pub len: u16,
+}
+
+impl MemoryRange {
+    fn new(start: u16, len: usize) -> Self {
+        MemoryRange { start, len: len as u16 }
+    }
+
+    // Inclusive end address
+    pub fn end(&self) -> u16 {
+        self.start.wrapping_add(self.len.saturating_sub(1))
+    }
+
+    pub fn overlaps(&self, start: u16, end: u16) -> bool {
+        self.len > 0 && self.start <= end && start <= self.end()
+    }
+}
+
+/**
+ *  Memory read and written through I by the last instruction cycle() ran (sprite data, Fx33,
+ *  Fx55/Fx65, etc). Instruction fetches are not included.
+ */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub read: Option<MemoryRange>,
+    pub write: Option<MemoryRange>,
+}
+
+/**
+ *  Registers and stack as they were when a fault happened, for showing a crash screen.
+ *  pc is the address of the instruction that faulted and opcode the instruction itself.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineState {
+    pub pc: u16,
+    pub opcode: u16,
+    pub i: u16,
+    pub sp: u16,
+    pub v: [u8; 16],
+    pub stack: [u16; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    InvalidOpcode(MachineState),
+    // 0nnn, a call into COSMAC VIP machine code that only the real interpreter (--vip) can run
+    MachineCode(MachineState),
+    // 2nnn with every stack slot already in use
+    StackOverflow(MachineState),
+    // 00EE outside of any subroutine
+    StackUnderflow(MachineState),
+    // An access of len bytes starting at addr runs past the end of memory, pc included
+    MemoryOutOfBounds { addr: u16, len: usize, state: MachineState },
+    RomTooLarge { size: usize, max: usize, state: MachineState },
+}
+
+impl Chip8Error {
+    pub fn state(&self) -> &MachineState {
+        match self {
+            Chip8Error::InvalidOpcode(state) => state,
+            Chip8Error::MachineCode(state) => state,
+            Chip8Error::StackOverflow(state) => state,
+            Chip8Error::StackUnderflow(state) => state,
+            Chip8Error::MemoryOutOfBounds { state, .. } => state,
+            Chip8Error::RomTooLarge { state, .. } => state,
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state();
+        match self {
+            Chip8Error::InvalidOpcode(_) => write!(f, "Invalid opcode {:04X} at {:04X}", state.opcode, state.pc),
+            Chip8Error::MachineCode(_) => write!(f, "{:04X} at {:04X} calls 1802 machine code at {:03X}, hybrid ROMs only run with --vip",
+                state.opcode, state.pc, state.opcode & 0x0FFF),
+            Chip8Error::StackOverflow(_) => write!(f, "Stack overflow, {:04X} at {:04X} nested more than {} calls deep",
+                state.opcode, state.pc, state.stack.len() - 1),
+            Chip8Error::StackUnderflow(_) => write!(f, "Stack underflow, 00EE at {:04X} with no subroutine to return from", state.pc),
+            Chip8Error::MemoryOutOfBounds { addr, len, .. } => write!(f, "{:04X} at {:04X} accessed {} bytes at {:04X}, past the end of memory",
+                state.opcode, state.pc, len, addr),
+            Chip8Error::RomTooLarge { size, max, .. } => write!(f, "Program is {} bytes, only {} fit in memory", size, max),
+        }
+    }
+}
+
+/**
+ *  Fx0A halts the machine until a key is pressed, and with the key_wait_release quirk released
+ *  again. Timers keep running while it waits.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyWait {
+    // Waiting for any key to go down, it will be stored in Vx
+    Press { x: u8 },
+    // key went down, waiting for it to come back up
+    Release { x: u8, key: u8 },
+}
+
+impl fmt::Display for KeyWait {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyWait::Press { x } => write!(f, "Fx0A waiting for a key press into V{:X}", x),
+            KeyWait::Release { x, key } => write!(f, "Fx0A waiting for key {:X} to be released into V{:X}", key, x),
+        }
+    }
+}
+
+// What went wrong inside execute(), cycle() turns it into a Chip8Error once the machine state is attached
+enum Fault {
+    InvalidOpcode,
+    MachineCode,
+    StackOverflow,
+    StackUnderflow,
+    OutOfBounds { addr: u16, len: usize },
+}
+
+/**
+ *  R supplies the random numbers for Cxkk, see rng::Rng.
+ *  Everything but save states, the sanitizer and loading from a file works without std.
+ */
+#[derive(Clone)]
+pub struct Chip8<R = XorShift> {
+    memory: [u8; MEMORY_SIZE], // 64KB, classic programs only ever see the first 4KB
     v: [u8; 16], // Registers
     i: u16,
     pc: u16, // Program Counter         
@@ -79,7 +250,30 @@ pub struct Chip8 {
     stack: [u16; 16],
     keyboard: [bool; 16],
 
-    gfx: Flat2DArray<u8>,
+    gfx: Framebuffer,
+    // SCHIP 128x64 mode
+    hires: bool,
+    // Set by 00FD, the program has asked to quit
+    exited: bool,
+    // SCHIP RPL user flags, saved/restored by Fx75/Fx85. XO-CHIP allows all 16
+    rpl: [u8; 16],
+    // XO-CHIP bitplanes selected by Fn01 for drawing, clearing and scrolling
+    planes: u8,
+    palette: [Pixel; 4],
+    // XO-CHIP 1-bit audio samples loaded by F002, None until a program sets one
+    audio_pattern: Option<[u8; 16]>,
+    // XO-CHIP Fx3A pitch register
+    pitch: u8,
+
+    quirks: Quirks,
+    // Set by cycle_timers every 60hz tick, consumed by Dxyn when the display_wait quirk is on
+    vblank: bool,
+    // Set while halted in Fx0A, pc stays on the Fx0A until it finishes
+    key_wait: Option<KeyWait>,
+    last_access: MemoryAccess,
+    #[cfg(feature = "std")]
+    sanitizer: Option<Box<Sanitizer>>,
+    rng: R,
 }
 
 impl Chip8 {
@@ -87,25 +281,49 @@ impl Chip8 {
     pub const DISPLAY_W: u32 = 64;
     pub const DISPLAY_H: u32 = 32;
 
-    const DISPLAY_WH: usize = Chip8::DISPLAY_W as usize * Chip8::DISPLAY_H as usize;
+    pub const HIRES_DISPLAY_W: u32 = 128;
+    pub const HIRES_DISPLAY_H: u32 = 64;
+
+    pub fn new(quirks: Quirks) -> Self {
+        Chip8::with_rng(quirks, XorShift::default())
+    }
+}
 
-    pub fn new() -> Self {
+impl<R: Rng> Chip8<R> {
+
+    pub fn with_rng(quirks: Quirks, rng: R) -> Self {
         let mut c = Chip8 {
-            memory: [0 as u8; 4096],
-            v: [0 as u8; 16],
+            memory: [0; MEMORY_SIZE],
+            v: [0; 16],
             i: 0,
             pc: PROGRAM_START,
             sp: 0,
             delay_timer: 0,
             sound_timer: 0,
-            stack: [0 as u16; 16],
+            stack: [0; 16],
             keyboard: [false; 16],
-            gfx: Flat2DArray::new(Chip8::DISPLAY_W as usize, Chip8::DISPLAY_H as usize),
+            gfx: Framebuffer::new(Chip8::DISPLAY_W as usize, Chip8::DISPLAY_H as usize),
+            hires: false,
+            exited: false,
+            rpl: [0; 16],
+            planes: PLANE_1,
+            palette: [Pixel::default(); 4],
+            audio_pattern: None,
+            pitch: 64,
+            quirks,
+            vblank: false,
+            key_wait: None,
+            last_access: MemoryAccess::default(),
+            #[cfg(feature = "std")]
+            sanitizer: None,
+            rng,
         };
         // load fontset
-        for i in 0..CHIP8_FONTSET.len() {
-            c.memory[i] = CHIP8_FONTSET[i];
-        }
+        let font = FONT_MEM_OFFSET as usize;
+        c.memory[font..font + CHIP8_FONTSET.len()].copy_from_slice(&CHIP8_FONTSET);
+        let big_font = BIG_FONT_MEM_OFFSET as usize;
+        c.memory[big_font..big_font + SCHIP_BIG_FONTSET.len()].copy_from_slice(&SCHIP_BIG_FONTSET);
+        c.set_palette(DEFAULT_PALETTE);
         // unsafe {
         //     let len = CHIP8_FONTSET.len();
         //     let src = CHIP8_FONTSET.as_ptr();
@@ -117,116 +335,391 @@ impl Chip8 {
         c
     }
 
+    // Size of the framebuffer in the current display mode, render_to_pixels returns this many pixels
+    pub fn display_size(&self) -> (u32, u32) {
+        if self.hires {
+            (Chip8::HIRES_DISPLAY_W, Chip8::HIRES_DISPLAY_H)
+        } else {
+            (Chip8::DISPLAY_W, Chip8::DISPLAY_H)
+        }
+    }
+
+    // True once the program has executed 00FD (EXIT)
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    // What Fx0A is waiting for, None when not halted in one
+    pub fn key_wait(&self) -> Option<KeyWait> {
+        self.key_wait
+    }
+
+    // Raw framebuffer, one cell per pixel holding its XO-CHIP plane bits (1 for a lit CHIP-8 pixel)
+    pub fn gfx(&self) -> &Framebuffer {
+        &self.gfx
+    }
+
+    /**
+     *  Colours as 0xRRGGBB for: background, plane 1 only, plane 2 only, both planes.
+     *  Programs that never touch plane 2 only ever show the first two.
+    */
+    pub fn set_palette(&mut self, palette: [u32; 4]) {
+        for (i, rgb) in palette.iter().enumerate() {
+            self.palette[i] = Pixel::from_rgb(*rgb);
+        }
+    }
+
+    // 0xRRGGBB colour of a gfx cell, for frontends drawing the framebuffer themselves
+    pub fn cell_color(&self, cell: u8) -> u32 {
+        self.palette[(cell & (PLANE_1 | PLANE_2)) as usize].to_rgb()
+    }
+
+    // The buzzer sounds for as long as the sound timer is non zero
+    pub fn buzzer_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    // XO-CHIP audio buffer, 128 1-bit samples played MSB first
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    // XO-CHIP Fx3A pitch, 64 plays the audio pattern at 4000 samples per second
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    // Samples per second the XO-CHIP audio pattern should be played back at
+    #[cfg(feature = "std")]
+    pub fn audio_playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    // Pixels packed as 32 bit XRGB (SDL RGB888), 4 bytes per pixel
+    #[cfg(feature = "std")]
     pub fn render_to_pixels(&self) -> Vec<u8> {
 
-        let mut buff = vec![Pixel::default(); Chip8::DISPLAY_WH];
+        let mut buff = Vec::with_capacity(self.gfx.width() * self.gfx.height() * 4);
 
-        for (i, _) in self.gfx.data.iter().enumerate() {
-            if self.gfx.data[i] == 0xFF {
-                buff[i] = Pixel::white();
-            } else {
-                buff[i] = Pixel::default();
-            }
+        for cell in self.gfx.cells() {
+            let p = self.palette[(cell & (PLANE_1 | PLANE_2)) as usize];
+            buff.extend_from_slice(&[p.b, p.g, p.r, 0]);
         }
 
-        unsafe { std::mem::transmute(buff) }
+        buff
     }
 
-    /*
-        NOTE: the chip8 key symbols are also the hexidecimal position they are in 
-        in our keyboard buffer on Chip8 struct
+    // Raw machine state, used by the debugger to inspect (and while paused edit) everything
 
-        Keyboard       Chip8 Keypad     
-        +-+-+-+-+      +-+-+-+-+        
-        |1|2|3|4|      |1|2|3|C|        
-        +-+-+-+-+      +-+-+-+-+        
-        |Q|W|E|R|      |4|5|6|D|        
-        +-+-+-+-+  =>  +-+-+-+-+    
-        |A|S|D|F|      |7|8|9|E|        
-        +-+-+-+-+      +-+-+-+-+        
-        |Z|X|C|V|      |A|0|B|F|        
-        +-+-+-+-+      +-+-+-+-+        
-    */
-    pub fn process_input(&mut self, event: &sdl2::event::Event) {
-        use sdl2::event::Event;
-        use sdl2::keyboard::Keycode;
-        match event {
-            Event::KeyDown { keycode: Some(Keycode::Num1), .. } => self.keyboard[0x1] = true,
-            Event::KeyDown { keycode: Some(Keycode::Num2), .. } => self.keyboard[0x2] = true,
-            Event::KeyDown { keycode: Some(Keycode::Num3), .. } => self.keyboard[0x3] = true,
-            Event::KeyDown { keycode: Some(Keycode::Num4), .. } => self.keyboard[0xC] = true,
-            Event::KeyDown { keycode: Some(Keycode::Q),    .. } => self.keyboard[0x4] = true,
-            Event::KeyDown { keycode: Some(Keycode::W),    .. } => self.keyboard[0x5] = true,
-            Event::KeyDown { keycode: Some(Keycode::E),    .. } => self.keyboard[0x6] = true,
-            Event::KeyDown { keycode: Some(Keycode::R),    .. } => self.keyboard[0xD] = true,
-            Event::KeyDown { keycode: Some(Keycode::A),    .. } => self.keyboard[0x7] = true,
-            Event::KeyDown { keycode: Some(Keycode::S),    .. } => self.keyboard[0x8] = true,
-            Event::KeyDown { keycode: Some(Keycode::D),    .. } => self.keyboard[0x9] = true,
-            Event::KeyDown { keycode: Some(Keycode::F),    .. } => self.keyboard[0xE] = true,
-            Event::KeyDown { keycode: Some(Keycode::Z),    .. } => self.keyboard[0xA] = true,
-            Event::KeyDown { keycode: Some(Keycode::X),    .. } => self.keyboard[0x0] = true,
-            Event::KeyDown { keycode: Some(Keycode::C),    .. } => self.keyboard[0xB] = true,
-            Event::KeyDown { keycode: Some(Keycode::V),    .. } => self.keyboard[0xF] = true,
-
-
-            Event::KeyUp { keycode: Some(Keycode::Num1), .. } => self.keyboard[0x1] = false,
-            Event::KeyUp { keycode: Some(Keycode::Num2), .. } => self.keyboard[0x2] = false,
-            Event::KeyUp { keycode: Some(Keycode::Num3), .. } => self.keyboard[0x3] = false,
-            Event::KeyUp { keycode: Some(Keycode::Num4), .. } => self.keyboard[0xC] = false,
-            Event::KeyUp { keycode: Some(Keycode::Q),    .. } => self.keyboard[0x4] = false,
-            Event::KeyUp { keycode: Some(Keycode::W),    .. } => self.keyboard[0x5] = false,
-            Event::KeyUp { keycode: Some(Keycode::E),    .. } => self.keyboard[0x6] = false,
-            Event::KeyUp { keycode: Some(Keycode::R),    .. } => self.keyboard[0xD] = false,
-            Event::KeyUp { keycode: Some(Keycode::A),    .. } => self.keyboard[0x7] = false,
-            Event::KeyUp { keycode: Some(Keycode::S),    .. } => self.keyboard[0x8] = false,
-            Event::KeyUp { keycode: Some(Keycode::D),    .. } => self.keyboard[0x9] = false,
-            Event::KeyUp { keycode: Some(Keycode::F),    .. } => self.keyboard[0xE] = false,
-            Event::KeyUp { keycode: Some(Keycode::Z),    .. } => self.keyboard[0xA] = false,
-            Event::KeyUp { keycode: Some(Keycode::X),    .. } => self.keyboard[0x0] = false,
-            Event::KeyUp { keycode: Some(Keycode::C),    .. } => self.keyboard[0xB] = false,
-            Event::KeyUp { keycode: Some(Keycode::V),    .. } => self.keyboard[0xF] = false,
-            _ => {}
+    pub fn v(&self) -> &[u8; 16] { &self.v }
+    pub fn v_mut(&mut self) -> &mut [u8; 16] { &mut self.v }
+
+    pub fn i(&self) -> u16 { self.i }
+    pub fn set_i(&mut self, i: u16) { self.i = i; }
+
+    pub fn pc(&self) -> u16 { self.pc }
+    pub fn set_pc(&mut self, pc: u16) { self.pc = pc; }
+
+    pub fn sp(&self) -> u16 { self.sp }
+    pub fn set_sp(&mut self, sp: u16) { self.sp = sp.min(self.stack.len() as u16 - 1); }
+
+    pub fn stack(&self) -> &[u16; 16] { &self.stack }
+    pub fn stack_mut(&mut self) -> &mut [u16; 16] { &mut self.stack }
+
+    pub fn delay_timer(&self) -> u8 { self.delay_timer }
+    pub fn set_delay_timer(&mut self, value: u8) { self.delay_timer = value; }
+
+    pub fn sound_timer(&self) -> u8 { self.sound_timer }
+    pub fn set_sound_timer(&mut self, value: u8) { self.sound_timer = value; }
+
+    pub fn memory(&self) -> &[u8] { &self.memory }
+    pub fn memory_mut(&mut self) -> &mut [u8] { &mut self.memory }
+
+    pub fn keyboard(&self) -> &[bool; 16] { &self.keyboard }
+    pub fn keyboard_mut(&mut self) -> &mut [bool; 16] { &mut self.keyboard }
+
+    pub fn last_access(&self) -> MemoryAccess { self.last_access }
+
+    // Starts checking every instruction for undefined behaviour. Enable before load_rom so the ROM counts as loaded
+    #[cfg(feature = "std")]
+    pub fn enable_sanitizer(&mut self) {
+        let mut sanitizer = Sanitizer::new();
+        sanitizer.loaded(FONT_MEM_OFFSET as usize, CHIP8_FONTSET.len() + SCHIP_BIG_FONTSET.len());
+        self.sanitizer = Some(Box::new(sanitizer));
+    }
+
+    #[cfg(feature = "std")]
+    pub fn sanitizer(&self) -> Option<&Sanitizer> {
+        self.sanitizer.as_deref()
+    }
+
+    // Opcode at the given address, as cycle() would fetch it
+    pub fn opcode_at(&self, addr: u16) -> u16 {
+        let addr = addr as usize;
+        (self.memory[addr % MEMORY_SIZE] as u16) << 8 | self.memory[(addr + 1) % MEMORY_SIZE] as u16
+    }
+
+    // key is the hex keypad digit, 0x0-0xF
+    pub fn key_down(&mut self, key: u8) {
+        self.keyboard[(key & 0xF) as usize] = true;
+    }
+
+    pub fn key_up(&mut self, key: u8) {
+        self.keyboard[(key & 0xF) as usize] = false;
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        self.load_rom_at(PROGRAM_START, rom)
+    }
+
+    // Loads rom at addr and starts executing there, for programs written for e.g. the ETI 660 (0x600)
+    pub fn load_rom_at(&mut self, addr: u16, rom: &[u8]) -> Result<(), Chip8Error> {
+        let start = addr as usize;
+        if rom.len() > MEMORY_SIZE - start {
+            let state = self.machine_state(self.pc);
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max: MEMORY_SIZE - start, state });
         }
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        #[cfg(feature = "std")]
+        if let Some(sanitizer) = self.sanitizer.as_mut() {
+            sanitizer.loaded(start, rom.len());
+        }
+        self.pc = addr;
+        Ok(())
     }
 
-    pub fn load_program(&mut self, path: &str) -> Result<(), std::io::Error> { 
-        
-        let buffer = std::fs::read(path)?;
-        for i in 0..buffer.len() {
-            self.memory[PROGRAM_START as usize + i] = buffer[i];
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    // Everything needed to resume execution later. Quirks, the palette and the RNG are left to the caller
+    #[cfg(feature = "std")]
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.memory);
+        w.bytes(&self.v);
+        w.u16(self.i);
+        w.u16(self.pc);
+        w.u16(self.sp);
+        w.u8(self.delay_timer);
+        w.u8(self.sound_timer);
+        for addr in self.stack.iter() {
+            w.u16(*addr);
+        }
+        for key in self.keyboard.iter() {
+            w.bool(*key);
+        }
+        w.bool(self.hires);
+        w.bytes(&self.gfx.cells().collect::<Vec<u8>>());
+        w.bool(self.exited);
+        w.bytes(&self.rpl);
+        w.u8(self.planes);
+        match &self.audio_pattern {
+            Some(pattern) => {
+                w.bool(true);
+                w.bytes(pattern);
+            },
+            None => w.bool(false),
+        }
+        w.u8(self.pitch);
+        w.bool(self.vblank);
+        match self.key_wait {
+            None => w.u8(0),
+            Some(KeyWait::Press { x }) => {
+                w.u8(1);
+                w.u8(x);
+            },
+            Some(KeyWait::Release { x, key }) => {
+                w.u8(2);
+                w.u8(x);
+                w.u8(key);
+            },
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.memory.copy_from_slice(r.bytes(MEMORY_SIZE)?);
+        self.v.copy_from_slice(r.bytes(16)?);
+        self.i = r.u16()?;
+        self.pc = r.u16()?;
+        self.sp = r.u16()?;
+        if self.sp as usize >= self.stack.len() {
+            return Err(format!("Save state has an invalid stack pointer ({})", self.sp));
         }
+        self.delay_timer = r.u8()?;
+        self.sound_timer = r.u8()?;
+        for addr in self.stack.iter_mut() {
+            *addr = r.u16()?;
+        }
+        for key in self.keyboard.iter_mut() {
+            *key = r.bool()?;
+        }
+        self.set_hires(r.bool()?);
+        let (w, h) = (self.gfx.width(), self.gfx.height());
+        for (n, cell) in r.bytes(w * h)?.iter().enumerate() {
+            self.gfx.set(n % w, n / w, *cell);
+        }
+        self.exited = r.bool()?;
+        self.rpl.copy_from_slice(r.bytes(16)?);
+        self.planes = r.u8()? & (PLANE_1 | PLANE_2);
+        self.audio_pattern = if r.bool()? {
+            let mut pattern = [0; 16];
+            pattern.copy_from_slice(r.bytes(16)?);
+            Some(pattern)
+        } else {
+            None
+        };
+        self.pitch = r.u8()?;
+        self.vblank = r.bool()?;
+        self.key_wait = match r.u8()? {
+            0 => None,
+            1 => Some(KeyWait::Press { x: r.u8()? & 0xF }),
+            2 => Some(KeyWait::Release { x: r.u8()? & 0xF, key: r.u8()? & 0xF }),
+            n => return Err(format!("Save state has an invalid key wait state ({})", n))
+        };
+        self.last_access = MemoryAccess::default();
         Ok(())
     }
 
     pub fn cycle_timers(&mut self) {
         if self.delay_timer > 0 { self.delay_timer -= 1; }
         if self.sound_timer > 0 { self.sound_timer -= 1; }    
+        self.vblank = true;
+    }
+
+    /**
+     *  Ok Result true if draw was called and screen should be updated, false otherwise.
+     *  On Err nothing has changed and pc is left on the faulting instruction.
+     */
+    pub fn cycle(&mut self) -> Result<bool, Chip8Error> {
+        if self.exited {
+            return Ok(false);
+        }
+        if self.key_wait.is_some() {
+            self.poll_key_wait();
+            return Ok(false);
+        }
+        let pc = self.pc;
+        // Taken before running, the instruction might overwrite itself
+        #[cfg(feature = "std")]
+        let before = (self.opcode_at(pc), self.hires);
+        self.last_access = MemoryAccess::default();
+        let result = self.execute();
+        #[cfg(feature = "std")]
+        if let (Ok(_), Some(sanitizer)) = (&result, self.sanitizer.as_mut()) {
+            let (opcode, hires) = before;
+            sanitizer.record(pc, opcode, hires, self.sp, self.last_access);
+        }
+        result.map_err(|fault| {
+            self.pc = pc;
+            let state = self.machine_state(pc);
+            match fault {
+                Fault::InvalidOpcode => Chip8Error::InvalidOpcode(state),
+                Fault::MachineCode => Chip8Error::MachineCode(state),
+                Fault::StackOverflow => Chip8Error::StackOverflow(state),
+                Fault::StackUnderflow => Chip8Error::StackUnderflow(state),
+                Fault::OutOfBounds { addr, len } => Chip8Error::MemoryOutOfBounds { addr, len, state },
+            }
+        })
+    }
+
+    /**
+     *  One 60hz frame for frontends that don't need anything finer: runs up to instructions cycles,
+     *  then counts the timers down. Ok(true) if the screen changed along the way.
+     */
+    pub fn step_frame(&mut self, instructions: u32) -> Result<bool, Chip8Error> {
+        let mut should_draw = false;
+        for _ in 0..instructions {
+            should_draw |= self.cycle()?;
+            if self.exited {
+                break;
+            }
+        }
+        self.cycle_timers();
+        Ok(should_draw)
     }
 
-    // Ok Result true if draw was called and screen should be updated, false otherwise
-    pub fn cycle(&mut self) -> Result<bool, String> {
+    fn machine_state(&self, pc: u16) -> MachineState {
+        MachineState {
+            pc,
+            opcode: self.opcode_at(pc),
+            i: self.i,
+            sp: self.sp,
+            v: self.v,
+            stack: self.stack,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    // Runs the instruction at pc. Faults are raised before anything besides pc has been changed
+    fn execute(&mut self) -> Result<bool, Fault> {
         let mut should_draw = false;
+        check_bounds(self.pc, 2)?;
         let opcode = { 
             let pc = self.pc as usize;
             Opcode((self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16)
         };
-        self.pc += 2;
+        // Running off the top of memory wraps around to 0 like I does
+        self.pc = self.pc.wrapping_add(2);
         // <OPCODE> - <DISASSEMBLY> - <DESCRIPTION>
         match opcode.0 & 0xF000 {
             0x0000 => {
-                match opcode.0 & 0x000F {
-                    // 00E0 - CLS - Clear Screen
-                    0x0000 => { 
-                        self.gfx.clear();
+                match opcode.0 {
+                    // 00E0 - CLS - Clear Screen (only the selected XO-CHIP planes)
+                    0x00E0 => { 
+                        self.gfx.clear(self.planes);
                         should_draw = true;
                         
                     },
                     // 00EE - RET - Return from Subroutine
-                    0x000E => {
+                    0x00EE => {
+                        if self.sp == 0 {
+                            return Err(Fault::StackUnderflow);
+                        }
                         self.pc = self.stack[self.sp as usize];
                         self.sp -= 1;
                     },
-                    _ => return Err(format_err(opcode))
+                    // 00Cn - SCD nibble - Scroll display down n lines (SCHIP)
+                    0x00C0..=0x00CF => {
+                        self.scroll(0, (opcode.0 & 0x000F) as isize);
+                        should_draw = true;
+                    },
+                    // 00Dn - SCU nibble - Scroll display up n lines (XO-CHIP)
+                    0x00D0..=0x00DF => {
+                        self.scroll(0, -((opcode.0 & 0x000F) as isize));
+                        should_draw = true;
+                    },
+                    // 00FB - SCR - Scroll display right 4 pixels (SCHIP)
+                    0x00FB => {
+                        self.scroll(4, 0);
+                        should_draw = true;
+                    },
+                    // 00FC - SCL - Scroll display left 4 pixels (SCHIP)
+                    0x00FC => {
+                        self.scroll(-4, 0);
+                        should_draw = true;
+                    },
+                    // 00FD - EXIT - Exit the interpreter (SCHIP)
+                    0x00FD => {
+                        self.exited = true;
+                    },
+                    // 00FE - LOW - Disable high resolution mode (SCHIP)
+                    0x00FE => {
+                        self.set_hires(false);
+                        should_draw = true;
+                    },
+                    // 00FF - HIGH - Enable 128x64 high resolution mode (SCHIP)
+                    0x00FF => {
+                        self.set_hires(true);
+                        should_draw = true;
+                    },
+                    // 0nnn - SYS addr - Run 1802 machine code at nnn (COSMAC VIP)
+                    _ => return Err(Fault::MachineCode)
                 }
             },
             // 1nnn - JP addr - Jump to location nnn
@@ -235,6 +728,9 @@ impl Chip8 {
             },
             // 2nnn - CALL addr - Call Subroutine at nnn
             0x2000 => {
+                if self.sp as usize + 1 >= self.stack.len() {
+                    return Err(Fault::StackOverflow);
+                }
                 self.sp += 1;
                 self.stack[self.sp as usize] = self.pc;
                 self.pc = opcode.addr();
@@ -244,7 +740,7 @@ impl Chip8 {
                 let x = self.v[opcode.x()];
                 let kk = opcode.kk();
                 if x == kk {
-                    self.pc += 2;
+                    self.skip_next();
                 }
                 
             },
@@ -253,18 +749,42 @@ impl Chip8 {
                 let x = self.v[opcode.x()];
                 let kk = opcode.kk();
                 if x != kk {
-                    self.pc += 2;
+                    self.skip_next();
                 }
                 
             }
-            // 5xy0 - SE Vx, Vy - Skip next instruction if Vx == Vy
             0x5000 => {
-                let x = self.v[opcode.x()];
-                let y = self.v[opcode.y()];
-                if x == y {
-                    self.pc += 2;
+                match opcode.0 & 0x000F {
+                    // 5xy0 - SE Vx, Vy - Skip next instruction if Vx == Vy
+                    0x0000 => {
+                        let x = self.v[opcode.x()];
+                        let y = self.v[opcode.y()];
+                        if x == y {
+                            self.skip_next();
+                        }
+                    },
+                    // 5xy2 - LD [I], Vx-Vy - Store Vx through Vy in memory starting at I, I is unchanged (XO-CHIP)
+                    0x0002 => {
+                        check_bounds(self.i, register_range(opcode.x(), opcode.y()).count())?;
+                        let mut count = 0;
+                        for (n, r) in register_range(opcode.x(), opcode.y()).enumerate() {
+                            self.memory[self.i as usize + n] = self.v[r];
+                            count += 1;
+                        }
+                        self.last_access.write = Some(MemoryRange::new(self.i, count));
+                    },
+                    // 5xy3 - LD Vx-Vy, [I] - Read Vx through Vy from memory starting at I, I is unchanged (XO-CHIP)
+                    0x0003 => {
+                        check_bounds(self.i, register_range(opcode.x(), opcode.y()).count())?;
+                        let mut count = 0;
+                        for (n, r) in register_range(opcode.x(), opcode.y()).enumerate() {
+                            self.v[r] = self.memory[self.i as usize + n];
+                            count += 1;
+                        }
+                        self.last_access.read = Some(MemoryRange::new(self.i, count));
+                    },
+                    _ => return Err(Fault::InvalidOpcode)
                 }
-                
             },
             // 6xkk - LD Vx, byte - Set Vx = kk.
             0x6000 => {
@@ -296,7 +816,9 @@ impl Chip8 {
                         let y = self.v[opcode.y()];
 
                         self.v[xindex] |= y;
-
+                        if self.quirks.logic_resets_vf {
+                            self.v[0xF] = 0;
+                        }
                         
                     },
                     // 8xy2 - AND Vx, Vy - Set Vx = Vx AND Vy.
@@ -305,14 +827,19 @@ impl Chip8 {
                         let y = self.v[opcode.y()];
 
                         self.v[xindex] &= y;
-
+                        if self.quirks.logic_resets_vf {
+                            self.v[0xF] = 0;
+                        }
                         
                     },
                     // 8xy3 - XOR Vx, Vy - Set Vx = Vx XOR Vy.
                     0x0003 => {
                         let vx = opcode.x();
                         let vy = opcode.y();
-                        self.v[vx] ^= self.v[vy];                        
+                        self.v[vx] ^= self.v[vy];
+                        if self.quirks.logic_resets_vf {
+                            self.v[0xF] = 0;
+                        }
                     }
                     // 8xy4 - ADD Vx, Vy - Set Vx = Vx + Vy, set VF = carry.
                     0x0004 => {
@@ -334,21 +861,22 @@ impl Chip8 {
                         let x = Wrapping(self.v[xindex]);
                         let y = Wrapping(self.v[opcode.y()]);
 
-                        self.v[0xF] = if x > y { 1 } else { 0 };
+                        // VF is written last so it wins when it's also the destination
                         self.v[xindex] = (x - y).0;
+                        self.v[0xF] = if x >= y { 1 } else { 0 };
 
                         
                     },
                     // 8xy6 - SHR Vx {, Vy} - Set Vx = Vx SHR 1 (Shift Right)
                     0x0006 => {
                         let xindex = opcode.x();
-                        let x = self.v[xindex];
+                        let x = if self.quirks.shift_uses_vy { self.v[opcode.y()] } else { self.v[xindex] };
+
+                        self.v[xindex] = x >> 1;
 
                         // set VF to 1 if least significant bit of x is 1. otherwise 0
                         self.v[0xF] = x & 1;
 
-                        self.v[xindex] = x >> 1;
-
                         
                     },
                     // 8xy7 - SUBN Vx, Vy - Set Vx = Vy - Vx, set VF = NOT borrow.
@@ -357,25 +885,25 @@ impl Chip8 {
                         let x = Wrapping(self.v[xindex]);
                         let y = Wrapping(self.v[opcode.y()]);
 
-                        self.v[0xF] = if y > x { 1 } else { 0 };
                         self.v[xindex] = (y - x).0;
+                        self.v[0xF] = if y >= x { 1 } else { 0 };
 
                         
                     },
                     // 8xyE - SHL Vx {, Vy} - Set Vx = Vx SHL 1. (Shift Left)
                     0x000E => {
                         let xindex = opcode.x();
-                        let x = self.v[xindex];
+                        let x = if self.quirks.shift_uses_vy { self.v[opcode.y()] } else { self.v[xindex] };
+
+                        self.v[xindex] = x << 1;
 
                         // set VF to 1 if most significant bit of x is 1. otherwise 0
                         self.v[0xF] = (x & 0x80) >> 7;
 
-                        self.v[xindex] = x << 1;
-
                         
                     }
 
-                    _ => return Err(format_err(opcode))
+                    _ => return Err(Fault::InvalidOpcode)
                 }
             },
             // 9xy0 - SNE Vx, Vy - Skip next instruction if Vx != Vy.
@@ -383,7 +911,7 @@ impl Chip8 {
                let x = self.v[opcode.x()];
                let y = self.v[opcode.y()];
                if x != y {
-                   self.pc += 2;
+                   self.skip_next();
                } 
                
             },
@@ -393,21 +921,44 @@ impl Chip8 {
                 
             },
             // Bnnn - JP V0, addr - Jump to location nnn + V0.
+            // (BxNN - JP Vx, addr - Jump to location xNN + Vx with the jump_uses_vx quirk)
             0xB000 => {
-                self.pc = opcode.addr() + self.v[0] as u16;
+                let offset = if self.quirks.jump_uses_vx { self.v[opcode.x()] } else { self.v[0] };
+                self.pc = opcode.addr() + offset as u16;
             },
             // Cxkk - RND Vx, byte - Set Vx = random byte AND kk.
             0xC000 => {
-                self.v[opcode.x()] = random_8bit() & opcode.kk();
+                self.v[opcode.x()] = self.rng.next_u8() & opcode.kk();
                 
             }
             // Dxyn - DRW Vx, Vy, nibble - Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
             0xD000 => {
-                let x = self.v[opcode.x()];
-                let y = self.v[opcode.y()];
-                let height = opcode.0 & 0x000F;
+                // Original hardware only drew once per frame, so hold this instruction until the next vblank
+                if self.quirks.display_wait {
+                    if !self.vblank {
+                        self.pc = self.pc.wrapping_sub(2);
+                        return Ok(false);
+                    }
+                    self.vblank = false;
+                }
+
+                let width = self.gfx.width();
+                let height_px = self.gfx.height();
+                // Starting position always wraps, only the pixels that run off the edge get clipped
+                let x = self.v[opcode.x()] as usize % width;
+                let y = self.v[opcode.y()] as usize % height_px;
+                // Dxy0 draws a 16x16 sprite made of 2 bytes per row (SCHIP)
+                let (sprite_w, height) = match opcode.0 & 0x000F {
+                    0 => (16, 16),
+                    n => (8, n as usize)
+                };
+                // With both XO-CHIP planes selected the plane 2 sprite data directly follows plane 1's
+                let sprite_bytes = height * sprite_w / 8;
+                check_bounds(self.i, sprite_bytes * self.planes.count_ones() as usize)?;
                 self.v[0xF] = 0;
 
+                let mut sprite_addr = self.i as usize;
+
                 // Each row in sprite is byte. Each pixel in sprite is a Bit. 
                 // Example: Sprite of the number 0
                 // from: http://www.multigesture.net/articles/how-to-write-an-emulator-chip-8-interpreter/
@@ -420,26 +971,41 @@ impl Chip8 {
                  *  144   0x90   1001 0000    *  *
                  *  240   0xF0   1111 0000    ****
                  */
-                for row in 0..height as usize {
+                for &plane in [PLANE_1, PLANE_2].iter() {
+                    if self.planes & plane == 0 {
+                        continue;
+                    }
+                    for row in 0..height {
 
-                    let sprite_byte = self.memory[self.i as usize + row];
-                    for col in 0..8 {
+                        let sprite_row = if sprite_w == 16 {
+                            let addr = sprite_addr + row * 2;
+                            (self.memory[addr] as u16) << 8 | self.memory[addr + 1] as u16
+                        } else {
+                            (self.memory[sprite_addr + row] as u16) << 8
+                        };
+                        for col in 0..sprite_w {
 
-                        let xoffset = (x as usize + col) % Chip8::DISPLAY_W as usize;
-                        let yoffset = (y as usize + row) % Chip8::DISPLAY_H as usize;
+                            if self.quirks.clip_sprites && (x + col >= width || y + row >= height_px) {
+                                continue;
+                            }
+                            let xoffset = (x + col) % width;
+                            let yoffset = (y + row) % height_px;
                         
-                        let sprite_bit = sprite_byte & (0x80 >> col);
+                            let sprite_bit = sprite_row & (0x8000 >> col);
 
-                        let gfx_byte = *self.gfx.get(xoffset, yoffset);
-                        if sprite_bit != 0 {
-                            if gfx_byte == 0xFF {
-                                self.v[0xF] = 1;
+                            let gfx_byte = self.gfx.get(xoffset, yoffset);
+                            if sprite_bit != 0 {
+                                if gfx_byte & plane != 0 {
+                                    self.v[0xF] = 1;
+                                }
+                                self.gfx.set(xoffset, yoffset, gfx_byte ^ plane);
                             }
-                            self.gfx.set(xoffset, yoffset, gfx_byte ^ 0xFF);
-                        }
                         
+                        }
                     }
+                    sprite_addr += sprite_bytes;
                 }
+                self.last_access.read = Some(MemoryRange::new(self.i, sprite_addr - self.i as usize));
 
                 should_draw = true;
                 
@@ -450,41 +1016,54 @@ impl Chip8 {
                     // Ex9E - SKP Vx - Skip next instruction if key with the value of Vx is pressed.
                     0x009E => {
                         let x = self.v[opcode.x()];
-                        if self.keyboard[x as usize] {
-                            self.pc += 2;
+                        if self.keyboard[(x & 0xF) as usize] {
+                            self.skip_next();
                         }
                         
                     },
                     // ExA1 - SKNP Vx - Skip next instruction if key with the value of Vx is not pressed.
                     0x00A1 => {
                         let x = self.v[opcode.x()];
-                        if !self.keyboard[x as usize] {
-                            self.pc += 2;
+                        if !self.keyboard[(x & 0xF) as usize] {
+                            self.skip_next();
                         }
                         
                     }
-                    _ => return Err(format_err(opcode))
+                    _ => return Err(Fault::InvalidOpcode)
                 }
             },
             // F000
             0xF000 => {
                 match opcode.0 & 0x00FF {
+                    // F000 nnnn - LD I, long addr - Set I = the 16 bit address in the next 2 bytes (XO-CHIP)
+                    0x0000 if opcode.x() == 0 => {
+                        check_bounds(self.pc, 2)?;
+                        let pc = self.pc as usize;
+                        self.i = (self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16;
+                        self.pc = self.pc.wrapping_add(2);
+                    },
+                    // Fn01 - PLANE n - Select bitplanes n for drawing, clearing and scrolling (XO-CHIP)
+                    0x0001 => {
+                        self.planes = opcode.x() as u8 & (PLANE_1 | PLANE_2);
+                    },
+                    // F002 - AUDIO - Load 16 bytes from I into the audio pattern buffer (XO-CHIP)
+                    0x0002 if opcode.x() == 0 => {
+                        check_bounds(self.i, 16)?;
+                        let mut pattern = [0; 16];
+                        let i = self.i as usize;
+                        pattern.copy_from_slice(&self.memory[i..i + 16]);
+                        self.audio_pattern = Some(pattern);
+                        self.last_access.read = Some(MemoryRange::new(self.i, 16));
+                    },
                     // Fx07 - LD Vx, DT - Set Vx = delay timer value.
                     0x0007 => {
                         self.v[opcode.x()] = self.delay_timer;                        
                     },
                     // Fx0A - LD Vx, K - Wait for a key press, store the value of the key in Vx.
+                    // Halts on this instruction, cycle() polls the keyboard until poll_key_wait lets it go
                     0x000A => {
-                        for (i, state) in self.keyboard.iter().enumerate() {
-                            if *state {
-                                self.v[opcode.x()] = i as u8;
-                                // Keep decrementing program counter by 2 to 
-                                // simulate 'waiting' for a keypress. We just run this instruction over and over until we have a key press
-                                // from: https://austinmorlan.com/posts/chip8_emulator/
-                                self.pc -= 2;
-                                break;
-                            }
-                        }
+                        self.key_wait = Some(KeyWait::Press { x: opcode.x() as u8 });
+                        self.pc = self.pc.wrapping_sub(2);
                     },
                     // Fx15 - LD DT, Vx - Set delay timer = Vx.
                     0x0015 => {
@@ -496,21 +1075,32 @@ impl Chip8 {
                     },
                     // Fx1E - ADD I, Vx - Set I = I + Vx.
                     0x001E => {
-                        self.i = self.i + self.v[opcode.x()] as u16;
+                        self.i = self.i.wrapping_add(self.v[opcode.x()] as u16);
                     },
                     // Fx29 - LD F, Vx - Set I = location of sprite for digit Vx.
                     0x0029 => {
-                        let x = self.v[opcode.x()] as u16;
+                        // Only the low nibble picks the digit, anything higher would point past the font
+                        let x = (self.v[opcode.x()] & 0xF) as u16;
                         // Each font is 5 bytes long in memory, so we take the 
                         // given value x from register and multiply it by 5 to get the position of the font requested
                         self.i = FONT_MEM_OFFSET + (x * 5);
                     },
+                    // Fx30 - LD HF, Vx - Set I = location of 10 byte big font sprite for digit Vx (SCHIP)
+                    0x0030 => {
+                        let x = (self.v[opcode.x()] & 0xF) as u16;
+                        self.i = BIG_FONT_MEM_OFFSET + (x * 10);
+                    },
+                    // Fx3A - PITCH Vx - Set the audio pattern playback pitch = Vx (XO-CHIP)
+                    0x003A => {
+                        self.pitch = self.v[opcode.x()];
+                    },
                     // Fx33 - LD B, Vx - Store BCD representation of Vx in memory locations I, I+1, and I+2.
                     0x0033 => {
                         // The interpreter takes the decimal value of Vx, 
                         // and places the hundreds digit in memory at location in I,
                         // the tens digit at location I+1, and the ones digit at location I+2.
 
+                        check_bounds(self.i, 3)?;
                         let mut x = self.v[opcode.x()];
                         let i = self.i as usize;
                         // Ones
@@ -523,14 +1113,18 @@ impl Chip8 {
 
                         // Hundreds
                         self.memory[i] = x % 10;
+                        self.last_access.write = Some(MemoryRange::new(self.i, 3));
                     },
                     // Fx55 - LD [I], Vx - Store registers V0 through Vx in memory starting at location I.
                     0x0055 => {
-                        let xindex = opcode.x() as usize + 1;
+                        let xindex = opcode.x() + 1;
+                        check_bounds(self.i, xindex)?;
 
                         for i in 0..xindex {
                             self.memory[self.i as usize + i] = self.v[i];
                         }
+                        self.last_access.write = Some(MemoryRange::new(self.i, xindex));
+                        self.increment_i_after_load_store(opcode.x() as u16);
                         // unsafe {
                         //     let src = self.v[0..xindex].as_ptr();
                         //     let dst = self.memory.as_mut_ptr().offset(self.i as isize);
@@ -539,34 +1133,136 @@ impl Chip8 {
                     },
                     // Fx65 - LD Vx, [I] - Read registers V0 through Vx from memory starting at location I.
                     0x0065 => {
-                        let xindex = opcode.x() as usize + 1;
+                        let xindex = opcode.x() + 1;
+                        check_bounds(self.i, xindex)?;
 
                         for i in 0..xindex {
                             self.v[i] = self.memory[self.i as usize + i];
                         }
+                        self.last_access.read = Some(MemoryRange::new(self.i, xindex));
+                        self.increment_i_after_load_store(opcode.x() as u16);
                         // unsafe {
                         //     let src = self.memory.as_ptr().offset(self.i as isize);
                         //     let dst = self.v.as_mut_ptr();
                         //     std::ptr::copy_nonoverlapping(src, dst, xindex);
                         // }
+                    },
+                    // Fx75 - LD R, Vx - Store V0 through Vx in the RPL user flags (SCHIP, all 16 on XO-CHIP)
+                    0x0075 => {
+                        let count = (opcode.x() + 1).min(self.rpl.len());
+                        self.rpl[..count].copy_from_slice(&self.v[..count]);
+                    },
+                    // Fx85 - LD Vx, R - Read V0 through Vx from the RPL user flags (SCHIP, all 16 on XO-CHIP)
+                    0x0085 => {
+                        let count = (opcode.x() + 1).min(self.rpl.len());
+                        self.v[..count].copy_from_slice(&self.rpl[..count]);
                     }
-                    _ => return Err(format_err(opcode))
+                    _ => return Err(Fault::InvalidOpcode)
                 }               
             },
-            _ => return Err(format_err(opcode))
+            _ => return Err(Fault::InvalidOpcode)
         }
 
         Ok(should_draw)
     }
+
+    // Moves an Fx0A halt along as keys go down and up, stepping past the Fx0A once it's done
+    fn poll_key_wait(&mut self) {
+        let (x, key) = match self.key_wait {
+            Some(KeyWait::Press { x }) => match self.keyboard.iter().position(|down| *down) {
+                Some(key) if self.quirks.key_wait_release => {
+                    self.key_wait = Some(KeyWait::Release { x, key: key as u8 });
+                    return;
+                },
+                Some(key) => (x, key as u8),
+                None => return,
+            },
+            Some(KeyWait::Release { x, key }) if !self.keyboard[key as usize] => (x, key),
+            _ => return,
+        };
+        self.v[x as usize] = key;
+        self.key_wait = None;
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    // Skips the next instruction, which is 4 bytes long if it is XO-CHIP's F000 nnnn
+    fn skip_next(&mut self) {
+        self.pc = self.pc.wrapping_add(Opcode(self.opcode_at(self.pc)).size());
+    }
+
+    // Scrolls only the selected XO-CHIP planes, leaving the others where they are
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        self.gfx.scroll(self.planes, dx, dy);
+    }
+
+    // Switching resolution also clears the screen, as Octo and XO-CHIP do. SCHIP 1.1 on the HP-48 left
+    // the old picture in place, but programs written for it clear the screen after switching anyway
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        let (w, h) = self.display_size();
+        self.gfx = Framebuffer::new(w as usize, h as usize);
+    }
+
+    fn increment_i_after_load_store(&mut self, x: u16) {
+        match self.quirks.memory_increment {
+            MemoryIncrement::Unchanged => {},
+            MemoryIncrement::X => self.i = self.i.wrapping_add(x),
+            MemoryIncrement::XPlusOne => self.i = self.i.wrapping_add(x + 1),
+        }
+    }
 }
   
 
-fn random_8bit() -> u8 {         
-    let n: u32 = thread_rng().gen_range(0, 256);
-    n as u8
+// Registers x through y for 5xy2/5xy3, walking backwards when x > y
+fn register_range(x: usize, y: usize) -> impl Iterator<Item = usize> {
+    let count = x.abs_diff(y) + 1;
+    (0..count).map(move |n| if x <= y { x + n } else { x - n })
 }
 
-fn format_err(Opcode(c): Opcode) -> String {
-    format!("OPCODE: {} NOT VALID", c)
-} 
+// Faults unless all len bytes starting at addr are inside memory
+fn check_bounds(addr: u16, len: usize) -> Result<(), Fault> {
+    if addr as usize + len > MEMORY_SIZE {
+        Err(Fault::OutOfBounds { addr, len })
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs a few instructions of rom from PROGRAM_START
+    fn run(rom: &[u8], quirks: Quirks, instructions: usize) -> Chip8 {
+        let mut chip8 = Chip8::new(quirks);
+        chip8.load_rom(rom).unwrap();
+        for _ in 0..instructions {
+            chip8.cycle().unwrap();
+        }
+        chip8
+    }
 
+    #[test]
+    fn font_digit_ignores_the_high_nibble() {
+        // LD V0, 0x3B; LD F, V0
+        let chip8 = run(&[0x60, 0x3B, 0xF0, 0x29], Quirks::default(), 2);
+        assert_eq!(chip8.i(), FONT_MEM_OFFSET + 0xB * 5);
+    }
+
+    #[test]
+    fn scroll_up() {
+        // LD V0, 0; LD F, V0; DRW V0, V0, 5; SCU 2
+        let chip8 = run(&[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x00, 0xD2], Quirks::xochip(), 4);
+        // The 0 is F0 90 90 90 F0, two rows of it scrolled off the top
+        let rows: Vec<u8> = chip8.gfx().plane(0).chunks(8).take(4).map(|row| row[0]).collect();
+        assert_eq!(rows, [0x90, 0x90, 0xF0, 0x00]);
+    }
+
+    #[test]
+    fn big_font_digit_ignores_the_high_nibble() {
+        // LD V0, 0x27; LD HF, V0
+        let chip8 = run(&[0x60, 0x27, 0xF0, 0x30], Quirks::schip(), 2);
+        assert_eq!(chip8.i(), BIG_FONT_MEM_OFFSET + 7 * 10);
+        assert_eq!(&chip8.memory()[chip8.i() as usize..][..10], &SCHIP_BIG_FONTSET[70..80]);
+    }
+}
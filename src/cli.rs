@@ -0,0 +1,599 @@
+use std::str::FromStr;
+
+use rusty_chip8_emu::chip8::PROGRAM_START;
+use rusty_chip8_emu::headless::HeadlessOptions;
+use rusty_chip8_emu::quirks::Quirks;
+use rusty_chip8_emu::{asm, disasm};
+use crate::config::{self, Settings};
+
+const COMMANDS: [&str; 6] = ["run", "headless", "disasm", "asm", "info", "help"];
+
+pub const USAGE: &str = "\
+Usage: rusty-chip8-emu [run] <program> [options]     run a program in a window
+       rusty-chip8-emu headless <program> [options]  run without a window and print the final state
+       rusty-chip8-emu disasm <rom> [options]        print a listing of a ROM
+       rusty-chip8-emu asm <source> [options]        assemble a ROM
+       rusty-chip8-emu info <program>                describe a ROM
+       rusty-chip8-emu help [command]
+
+Programs are raw ROMs, or Octo source (.8o) compiled on load.
+Run 'rusty-chip8-emu help <command>' for a command's options.";
+
+const RUN_USAGE: &str = "\
+Usage: rusty-chip8-emu [run] <program> [options]
+
+Machine:
+  -q, --quirks PRESET       vip, chip48, schip or xochip
+      --speed N             instructions per second with --timing fixed (200)
+      --timer-hz N          rate the delay and sound timers count down at (60)
+      --timing MODEL        fixed or vip
+      --load-address ADDR   where the program is loaded and starts running (0x200)
+      --seed N              seed for Cxkk's random numbers, the same seed replays the same numbers
+      --state FILE          resume from a save state taken of this program
+      --sanitize            report undefined behaviour the program relies on when it stops
+      --config FILE         read settings from FILE instead of the default config.toml
+      --no-config           ignore the config file
+      --no-database         don't look the program up in the ROM database for its quirks, speed and colours
+Display and sound:
+      --palette COLOURS     up to 4 comma separated hex colours: off, on, XO-CHIP plane 2, both planes
+      --scale N             window size as a multiple of the 64x32 display (1280x720 by default)
+      --waveform WAVE       square, sine or triangle
+      --tone HZ             beep frequency (440)
+      --volume N            0 to 100 (25)
+Rewind:
+      --rewind SECONDS      history to keep, 0 turns rewinding off (60)
+      --rewind-interval N   only snapshot every n-th frame
+COSMAC VIP:
+      --vip FILE            run on an emulated COSMAC VIP with this CHIP-8 interpreter image
+      --vip-monitor FILE    the VIP monitor ROM";
+
+const HEADLESS_USAGE: &str = "\
+Usage: rusty-chip8-emu headless <program> [options]
+
+Runs without a window until a limit or a halt (self jump, 00FD, error), then prints why it stopped,
+the registers and the screen. Exits with an error if the program itself failed.
+
+      --frames N            stop after N 60 Hz frames (600)
+      --instructions N      stop after N instructions
+      --press FRAME:KEY[:FRAMES]
+                            hold hex key KEY down from frame FRAME, for FRAMES frames (4)
+  -q, --quirks PRESET       vip, chip48, schip or xochip
+      --speed N             instructions per second with --timing fixed (200)
+      --timing MODEL        fixed or vip
+      --load-address ADDR   where the program is loaded and starts running (0x200)
+      --seed N              seed for Cxkk's random numbers
+      --state FILE          start from a save state taken of this program
+      --sanitize            report undefined behaviour the program relies on
+      --config FILE         read settings from FILE instead of the default config.toml
+      --no-config           ignore the config file, e.g. for scripts that have to run the same anywhere
+      --no-database         don't look the program up in the ROM database";
+
+const DISASM_USAGE: &str = "\
+Usage: rusty-chip8-emu disasm <rom> [options]
+
+      --syntax SYNTAX       cowgod (the default) or octo
+      --linear              decode every word instead of tracing the code from 0x200";
+
+const ASM_USAGE: &str = "\
+Usage: rusty-chip8-emu asm <source> [options]
+
+  -o, --output FILE         where to write the ROM (the source with a .ch8 extension)
+      --target TARGET       instruction set: chip8, schip or xochip (the default)";
+
+const INFO_USAGE: &str = "\
+Usage: rusty-chip8-emu info <program>
+
+Prints a ROM's size, SHA-1, what the ROM database knows about it, the platform its code looks written
+for and why, how much of it a trace reaches, and any save slots kept for it.";
+
+const MACHINE_OPTIONS: [&str; 12] = ["-q", "--quirks", "--speed", "--ips", "--timing", "--load-address", "--seed", "--state",
+    "--sanitize", "--config", "--no-config", "--no-database"];
+const RUN_OPTIONS: [&str; 10] = ["--timer-hz", "--palette", "--scale", "--waveform", "--tone", "--volume",
+    "--rewind", "--rewind-interval", "--vip", "--vip-monitor"];
+const HEADLESS_OPTIONS: [&str; 3] = ["--frames", "--instructions", "--press"];
+const DISASM_OPTIONS: [&str; 2] = ["--syntax", "--linear"];
+const ASM_OPTIONS: [&str; 3] = ["-o", "--output", "--target"];
+
+pub enum Command {
+    Run(RunArgs),
+    Headless(HeadlessArgs),
+    Disasm(DisasmArgs),
+    Asm(AsmArgs),
+    Info(InfoArgs),
+    // Usage text to print
+    Help(&'static str),
+}
+
+// How to set up the Chip8 a program runs on, shared by run and headless
+#[derive(Default)]
+pub struct MachineArgs {
+    pub program: String,
+    pub seed: Option<u32>,
+    pub state: Option<String>,
+    // Another config file, or none at all
+    pub config: Option<String>,
+    pub no_config: bool,
+    // Skip the ROM database's recommendations
+    pub no_database: bool,
+    // Settings given on the command line, they win over the config file's
+    pub settings: Settings,
+}
+
+#[derive(Default)]
+pub struct RunArgs {
+    pub machine: MachineArgs,
+    // CHIP-8 interpreter and monitor ROM images to run the program on an emulated COSMAC VIP
+    pub vip: Option<String>,
+    pub vip_monitor: Option<String>,
+}
+
+pub struct HeadlessArgs {
+    pub machine: MachineArgs,
+    pub options: HeadlessOptions,
+}
+
+pub struct DisasmArgs {
+    pub program: String,
+    pub syntax: disasm::Syntax,
+    pub linear: bool,
+}
+
+pub struct AsmArgs {
+    pub source: String,
+    pub output: Option<String>,
+    pub target: asm::Target,
+}
+
+pub struct InfoArgs {
+    pub program: String,
+}
+
+/**
+ *  Works out what to do from the command line (without the executable name).
+ *  A program with no command in front of it runs, as it always has.
+ */
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let (first, rest) = match args.split_first() {
+        Some((first, rest)) => (first.as_str(), rest),
+        None => return Err(format!("No program given\n\n{}", USAGE)),
+    };
+    match first {
+        "run" => parse_run(rest),
+        "headless" => parse_headless(rest),
+        "disasm" => parse_disasm(rest),
+        "asm" => parse_asm(rest),
+        "info" => parse_info(rest),
+        "help" | "-h" | "--help" => match rest.first().map(String::as_str) {
+            None => Ok(Command::Help(USAGE)),
+            Some(command) => command_usage(command).map(Command::Help).ok_or_else(|| unknown_command(command)),
+        },
+        // Most likely a mistyped command rather than a program, if there's no such file
+        _ if !first.starts_with('-') && !std::path::Path::new(first).exists() && closest(first, &COMMANDS).is_some() => {
+            Err(unknown_command(first))
+        },
+        _ => parse_run(args),
+    }
+}
+
+fn command_usage(command: &str) -> Option<&'static str> {
+    match command {
+        "run" => Some(RUN_USAGE),
+        "headless" => Some(HEADLESS_USAGE),
+        "disasm" => Some(DISASM_USAGE),
+        "asm" => Some(ASM_USAGE),
+        "info" => Some(INFO_USAGE),
+        "help" => Some(USAGE),
+        _ => None,
+    }
+}
+
+fn unknown_command(command: &str) -> String {
+    let mut message = format!("Unknown command '{}'.", command);
+    if let Some(close) = closest(command, &COMMANDS) {
+        message += &format!(" Did you mean '{}'?", close);
+    }
+    format!("{} Commands are: {}", message, COMMANDS.join(", "))
+}
+
+/**
+ *  One command's arguments, with errors for options missing their value or given a bad one.
+ *  Options may come before or after the program.
+ */
+struct Parser<'a> {
+    command: &'static str,
+    usage: &'static str,
+    args: std::slice::Iter<'a, String>,
+    positional: Option<&'a str>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(command: &'static str, args: &'a [String]) -> Self {
+        Parser {
+            command,
+            usage: command_usage(command).unwrap_or(USAGE),
+            args: args.iter(),
+            positional: None,
+        }
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.args.next().map(String::as_str)
+    }
+
+    // expects describes the value for the error message, e.g. "a number of frames"
+    fn value(&mut self, flag: &str, expects: &str) -> Result<&'a str, String> {
+        match self.next() {
+            Some(value) if !value.starts_with("--") => Ok(value),
+            _ => Err(format!("{} expects {}", flag, expects)),
+        }
+    }
+
+    fn number<T: FromStr>(&mut self, flag: &str, expects: &str) -> Result<T, String> {
+        let value = self.value(flag, expects)?;
+        value.parse().map_err(|_| format!("{} expects {}, got '{}'", flag, expects, value))
+    }
+
+    // Decimal, or hex with 0x in front
+    fn address(&mut self, flag: &str, expects: &str) -> Result<u32, String> {
+        let value = self.value(flag, expects)?;
+        let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => value.parse(),
+        };
+        parsed.map_err(|_| format!("{} expects {}, got '{}'", flag, expects, value))
+    }
+
+    /**
+     *  Anything that isn't one of the command's options: the program the first time, an error after.
+     *  known lists the command's options, to suggest the one that was probably meant.
+     */
+    fn other(&mut self, arg: &'a str, known: &[&[&str]]) -> Result<(), String> {
+        if arg.starts_with('-') && arg.len() > 1 {
+            let known: Vec<&str> = known.iter().flat_map(|options| options.iter().copied()).collect();
+            let mut message = format!("Unknown option '{}' for {}.", arg, self.command);
+            if let Some(close) = closest(arg, &known) {
+                message += &format!(" Did you mean '{}'?", close);
+            }
+            return Err(format!("{} Run 'rusty-chip8-emu help {}' to list them.", message, self.command));
+        }
+        match self.positional {
+            None => {
+                self.positional = Some(arg);
+                Ok(())
+            },
+            Some(first) => Err(format!("Unexpected argument '{}', {} takes a single file and already has '{}'\n\n{}",
+                arg, self.command, first, self.usage)),
+        }
+    }
+
+    fn positional(&self, what: &str) -> Result<String, String> {
+        self.positional.map(String::from).ok_or_else(|| format!("No {} given\n\n{}", what, self.usage))
+    }
+}
+
+/**
+ *  Handles the options shared by run and headless, returning false for anything else.
+ */
+fn machine_option(p: &mut Parser, arg: &str, m: &mut MachineArgs) -> Result<bool, String> {
+    let s = &mut m.settings;
+    match arg {
+        "-q" | "--quirks" => {
+            let expects = format!("a preset name. One of: {}", Quirks::PRESET_NAMES.join(", "));
+            s.quirks = Some(p.value(arg, &expects)?.parse()?);
+        },
+        // --ips is what headless called it first
+        "--speed" | "--ips" => {
+            let speed = p.number(arg, "a number of instructions per second")?;
+            if speed == 0 {
+                return Err(format!("{} has to be at least 1 instruction per second", arg));
+            }
+            s.speed = Some(speed);
+        },
+        "--timing" => s.timing = Some(p.value(arg, "one of: fixed, vip")?.parse()?),
+        "--load-address" => {
+            let addr = p.address(arg, "an address, e.g. 0x200 or 0x600")?;
+            if addr < PROGRAM_START as u32 || addr > 0xFFFF {
+                return Err(format!("{} has to be from 0x{:X} (below is reserved for the interpreter and font) to 0xFFFF, got 0x{:X}", arg, PROGRAM_START, addr));
+            }
+            s.load_address = Some(addr as u16);
+        },
+        "--sanitize" => s.sanitize = Some(true),
+        "--seed" => m.seed = Some(p.address(arg, "a number")?),
+        "--state" => m.state = Some(p.value(arg, "the path of a save state file")?.to_string()),
+        "--config" => m.config = Some(p.value(arg, "the path of a config file")?.to_string()),
+        "--no-config" => m.no_config = true,
+        "--no-database" => m.no_database = true,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn parse_run(args: &[String]) -> Result<Command, String> {
+    let mut p = Parser::new("run", args);
+    let mut run = RunArgs::default();
+
+    while let Some(arg) = p.next() {
+        if machine_option(&mut p, arg, &mut run.machine)? {
+            continue;
+        }
+        let settings = &mut run.machine.settings;
+        match arg {
+            "-h" | "--help" => return Ok(Command::Help(RUN_USAGE)),
+            "--timer-hz" => {
+                let hz: f32 = p.number(arg, "a rate in Hz")?;
+                if !hz.is_finite() || hz <= 0.0 {
+                    return Err(format!("{} has to be above 0 Hz", arg));
+                }
+                settings.timer_hz = Some(hz);
+            },
+            "--palette" => {
+                let colours: Vec<&str> = p.value(arg, "comma separated hex colours, e.g. 000000,FFFFFF")?.split(',').collect();
+                settings.palette = Some(config::parse_palette(&colours).map_err(|e| format!("--{}", e))?);
+            },
+            "--scale" => {
+                let scale = p.number(arg, "a whole number, e.g. 10 for a 640x320 window")?;
+                if scale == 0 || scale > config::MAX_SCALE {
+                    return Err(format!("{} has to be from 1 to {}, got {}", arg, config::MAX_SCALE, scale));
+                }
+                settings.scale = Some(scale);
+            },
+            "--waveform" => settings.waveform = Some(p.value(arg, "one of: square, sine, triangle")?.parse()?),
+            "--tone" => {
+                let tone: f32 = p.number(arg, "a frequency in Hz")?;
+                if !tone.is_finite() || tone <= 0.0 {
+                    return Err(format!("{} has to be above 0 Hz", arg));
+                }
+                settings.tone = Some(tone);
+            },
+            "--volume" => {
+                let volume: f32 = p.number(arg, "a value from 0 to 100")?;
+                if !(0.0..=100.0).contains(&volume) {
+                    return Err(format!("{} has to be from 0 to 100, got {}", arg, volume));
+                }
+                settings.volume = Some(volume);
+            },
+            "--rewind" => settings.rewind = Some(p.number(arg, "a number of seconds, 0 disables rewinding")?),
+            "--rewind-interval" => settings.rewind_interval = Some(p.number(arg, "a number of frames")?),
+            "--vip" => run.vip = Some(p.value(arg, "the path of a VIP CHIP-8 interpreter image")?.to_string()),
+            "--vip-monitor" => run.vip_monitor = Some(p.value(arg, "the path of the VIP monitor ROM")?.to_string()),
+            _ => p.other(arg, &[&MACHINE_OPTIONS, &RUN_OPTIONS])?,
+        }
+    }
+
+    if run.vip_monitor.is_some() && run.vip.is_none() {
+        return Err("--vip-monitor only applies with --vip".to_string());
+    }
+    run.machine.program = p.positional("program")?;
+    Ok(Command::Run(run))
+}
+
+fn parse_headless(args: &[String]) -> Result<Command, String> {
+    let mut p = Parser::new("headless", args);
+    let mut machine = MachineArgs::default();
+    let mut options = HeadlessOptions::default();
+
+    while let Some(arg) = p.next() {
+        if machine_option(&mut p, arg, &mut machine)? {
+            continue;
+        }
+        match arg {
+            "-h" | "--help" => return Ok(Command::Help(HEADLESS_USAGE)),
+            "--frames" => options.max_frames = Some(p.number(arg, "a number of frames")?),
+            "--instructions" => {
+                options.max_instructions = Some(p.number(arg, "a number of instructions")?);
+                // An instruction limit on its own shouldn't be cut short by the default frame limit
+                if !args.iter().any(|a| a == "--frames") {
+                    options.max_frames = None;
+                }
+            },
+            "--press" => options.keys.push(p.value(arg, "FRAME:KEY[:FRAMES]")?.parse()?),
+            _ => p.other(arg, &[&MACHINE_OPTIONS, &HEADLESS_OPTIONS])?,
+        }
+    }
+
+    machine.program = p.positional("program")?;
+    Ok(Command::Headless(HeadlessArgs { machine, options }))
+}
+
+fn parse_disasm(args: &[String]) -> Result<Command, String> {
+    let mut p = Parser::new("disasm", args);
+    let mut syntax = disasm::Syntax::Cowgod;
+    let mut linear = false;
+
+    while let Some(arg) = p.next() {
+        match arg {
+            "-h" | "--help" => return Ok(Command::Help(DISASM_USAGE)),
+            "--syntax" => syntax = p.value(arg, "one of: cowgod, octo")?.parse()?,
+            // Skip tracing and decode every word, for ROMs that jump through computed addresses
+            "--linear" => linear = true,
+            _ => p.other(arg, &[&DISASM_OPTIONS])?,
+        }
+    }
+
+    Ok(Command::Disasm(DisasmArgs { program: p.positional("ROM")?, syntax, linear }))
+}
+
+fn parse_asm(args: &[String]) -> Result<Command, String> {
+    let mut p = Parser::new("asm", args);
+    let mut target = asm::Target::XoChip;
+    let mut output = None;
+
+    while let Some(arg) = p.next() {
+        match arg {
+            "-h" | "--help" => return Ok(Command::Help(ASM_USAGE)),
+            "--target" => target = p.value(arg, "one of: chip8, schip, xochip")?.parse()?,
+            "-o" | "--output" => output = Some(p.value(arg, "the path to write the ROM to")?.to_string()),
+            _ => p.other(arg, &[&ASM_OPTIONS])?,
+        }
+    }
+
+    Ok(Command::Asm(AsmArgs { source: p.positional("source file")?, output, target }))
+}
+
+fn parse_info(args: &[String]) -> Result<Command, String> {
+    let mut p = Parser::new("info", args);
+    while let Some(arg) = p.next() {
+        match arg {
+            "-h" | "--help" => return Ok(Command::Help(INFO_USAGE)),
+            _ => p.other(arg, &[])?,
+        }
+    }
+    Ok(Command::Info(InfoArgs { program: p.positional("program")? }))
+}
+
+// The candidate within a couple of typos of word, if there is one
+pub fn closest<'a>(word: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates.iter()
+        .map(|c| (edit_distance(word, c), *c))
+        .filter(|(distance, c)| *distance <= 2 && *distance < c.len() / 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = diagonal + (ca != *cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusty_chip8_emu::headless::DEFAULT_MAX_FRAMES;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn error(line: &str) -> String {
+        match parse(&args(line)) {
+            Err(e) => e,
+            Ok(_) => panic!("'{}' parsed", line),
+        }
+    }
+
+    fn run(line: &str) -> RunArgs {
+        match parse(&args(line)) {
+            Ok(Command::Run(run)) => run,
+            Ok(_) => panic!("'{}' isn't a run command", line),
+            Err(e) => panic!("'{}' :: {}", line, e),
+        }
+    }
+
+    fn headless(line: &str) -> HeadlessOptions {
+        match parse(&args(line)) {
+            Ok(Command::Headless(headless)) => headless.options,
+            Ok(_) => panic!("'{}' isn't a headless command", line),
+            Err(e) => panic!("'{}' :: {}", line, e),
+        }
+    }
+
+    #[test]
+    fn a_program_on_its_own_runs() {
+        let run = run("game.ch8 --speed 500");
+        assert_eq!(run.machine.program, "game.ch8");
+        assert_eq!(run.machine.settings.speed, Some(500));
+        // Options can come first
+        assert_eq!(self::run("run --scale 5 game.ch8").machine.settings.scale, Some(5));
+    }
+
+    #[test]
+    fn suggests_the_closest_command() {
+        assert_eq!(error("hedless"), "Unknown command 'hedless'. Did you mean 'headless'? \
+            Commands are: run, headless, disasm, asm, info, help");
+        assert!(error("help disasn").contains("Did you mean 'disasm'?"));
+    }
+
+    #[test]
+    fn suggests_the_closest_option() {
+        let e = error("run game.ch8 --sped 500");
+        assert!(e.starts_with("Unknown option '--sped' for run. Did you mean '--speed'?"), "{}", e);
+        // Only the command's own options
+        let e = error("headless game.ch8 --scael 2");
+        assert!(e.starts_with("Unknown option '--scael' for headless. Run"), "{}", e);
+        let e = error("disasm game.ch8 --linaer");
+        assert!(e.contains("Did you mean '--linear'?"), "{}", e);
+    }
+
+    #[test]
+    fn closest_needs_to_be_close() {
+        assert_eq!(closest("--frmaes", &HEADLESS_OPTIONS), Some("--frames"));
+        assert_eq!(closest("--foo", &HEADLESS_OPTIONS), None);
+        // Two typos in a short word is a different word
+        assert_eq!(closest("ab", &COMMANDS), None);
+    }
+
+    #[test]
+    fn missing_values() {
+        assert_eq!(error("run game.ch8 --speed"), "--speed expects a number of instructions per second");
+        // The next option isn't taken as the value
+        assert_eq!(error("headless game.ch8 --frames --press 1:5"), "--frames expects a number of frames");
+        assert_eq!(error("run game.ch8 --speed fast"), "--speed expects a number of instructions per second, got 'fast'");
+        assert!(error("run --speed 500").starts_with("No program given"));
+        assert!(error("").starts_with("No program given"));
+        assert!(error("asm").starts_with("No source file given"));
+    }
+
+    #[test]
+    fn a_second_program_is_an_error() {
+        assert!(error("run a.ch8 b.ch8").starts_with("Unexpected argument 'b.ch8', run takes a single file and already has 'a.ch8'"));
+    }
+
+    #[test]
+    fn load_address_range() {
+        assert_eq!(run("game.ch8 --load-address 0x600").machine.settings.load_address, Some(0x600));
+        assert_eq!(run("game.ch8 --load-address 512").machine.settings.load_address, Some(0x200));
+        assert_eq!(run("game.ch8 --load-address 0xFFFF").machine.settings.load_address, Some(0xFFFF));
+        assert_eq!(error("game.ch8 --load-address 0x1FF"),
+            "--load-address has to be from 0x200 (below is reserved for the interpreter and font) to 0xFFFF, got 0x1FF");
+        assert!(error("game.ch8 --load-address 0x10000").ends_with("got 0x10000"));
+        assert_eq!(error("game.ch8 --load-address 0xZZ"),
+            "--load-address expects an address, e.g. 0x200 or 0x600, got '0xZZ'");
+    }
+
+    #[test]
+    fn instructions_clear_the_frame_limit() {
+        assert_eq!(headless("headless game.ch8").max_frames, Some(DEFAULT_MAX_FRAMES));
+        let options = headless("headless game.ch8 --instructions 1000");
+        assert_eq!((options.max_instructions, options.max_frames), (Some(1000), None));
+        // Unless there's a frame limit too, whichever order they come in
+        let options = headless("headless game.ch8 --instructions 1000 --frames 30");
+        assert_eq!((options.max_instructions, options.max_frames), (Some(1000), Some(30)));
+        let options = headless("headless game.ch8 --frames 30 --instructions 1000");
+        assert_eq!(options.max_frames, Some(30));
+    }
+
+    #[test]
+    fn vip_monitor_needs_vip() {
+        assert_eq!(error("game.ch8 --vip-monitor monitor.bin"), "--vip-monitor only applies with --vip");
+        let run = run("game.ch8 --vip chip8.bin --vip-monitor monitor.bin");
+        assert_eq!(run.vip.as_deref(), Some("chip8.bin"));
+    }
+
+    #[test]
+    fn help() {
+        assert!(matches!(parse(&args("help")), Ok(Command::Help(USAGE))));
+        assert!(matches!(parse(&args("help asm")), Ok(Command::Help(ASM_USAGE))));
+        assert!(matches!(parse(&args("headless --help")), Ok(Command::Help(HEADLESS_USAGE))));
+    }
+
+    #[test]
+    fn tone_and_volume_ranges() {
+        assert_eq!(run("game.ch8 --tone 261.5 --volume 0").machine.settings.tone, Some(261.5));
+        assert_eq!(error("game.ch8 --tone 0"), "--tone has to be above 0 Hz");
+        assert_eq!(error("game.ch8 --tone -440"), "--tone has to be above 0 Hz");
+        assert_eq!(error("game.ch8 --tone NaN"), "--tone has to be above 0 Hz");
+        assert_eq!(error("game.ch8 --volume 150"), "--volume has to be from 0 to 100, got 150");
+        assert_eq!(error("game.ch8 --volume -1"), "--volume has to be from 0 to 100, got -1");
+        assert_eq!(error("game.ch8 --volume NaN"), "--volume has to be from 0 to 100, got NaN");
+    }
+}
@@ -0,0 +1,668 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use toml_edit::{value, Array, DocumentMut, InlineTable, Item, Table, TableLike, Value};
+
+use rusty_chip8_emu::chip8::{Chip8, DEFAULT_PALETTE, PROGRAM_START};
+use rusty_chip8_emu::database::RomInfo;
+use rusty_chip8_emu::quirks::{MemoryIncrement, Quirks};
+use rusty_chip8_emu::rewind;
+use rusty_chip8_emu::savestate::{hash_to_hex, RomHash};
+use rusty_chip8_emu::timing::Timing;
+use crate::audio::{BeeperSettings, Waveform};
+use crate::input::{self, Keymap};
+use sdl2::keyboard::Scancode;
+
+pub const DEFAULT_SPEED: u32 = 200;
+pub const DEFAULT_TIMER_HZ: f32 = 60.0;
+// Without a scale setting the window opens at this size
+const DEFAULT_WINDOW_SIZE: (u32, u32) = (1280, 720);
+pub const MAX_SCALE: u32 = 64;
+
+const KEYS: [&str; 14] = ["quirks", "speed", "timer_hz", "timing", "load_address", "sanitize", "palette", "scale",
+    "waveform", "tone", "volume", "rewind", "rewind_interval", "keys"];
+const QUIRK_KEYS: [&str; 7] = ["shift_uses_vy", "memory_increment", "jump_uses_vx", "logic_resets_vf", "clip_sprites",
+    "display_wait", "key_wait_release"];
+
+/**
+ *  Everything that can be set from the config file or the command line, None where it wasn't.
+ *  Layers are stacked with or(), the getters fill in the built in defaults for anything left unset.
+ */
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Settings {
+    pub quirks: Option<Quirks>,
+    // Instructions per second with Timing::Fixed
+    pub speed: Option<u32>,
+    // How fast the delay and sound timers count down
+    pub timer_hz: Option<f32>,
+    pub timing: Option<Timing>,
+    pub load_address: Option<u16>,
+    pub sanitize: Option<bool>,
+    pub palette: Option<[u32; 4]>,
+    pub scale: Option<u32>,
+    pub waveform: Option<Waveform>,
+    pub tone: Option<f32>,
+    // 0 - 100
+    pub volume: Option<f32>,
+    pub rewind: Option<u32>,
+    pub rewind_interval: Option<u32>,
+    // Keypad keys left unset are taken from the layer below, key by key
+    pub keys: Option<Keymap>,
+}
+
+impl Settings {
+
+    // These settings, with anything unset taken from fallback
+    pub fn or(&self, fallback: &Settings) -> Settings {
+        Settings {
+            quirks: self.quirks.or(fallback.quirks),
+            speed: self.speed.or(fallback.speed),
+            timer_hz: self.timer_hz.or(fallback.timer_hz),
+            timing: self.timing.or(fallback.timing),
+            load_address: self.load_address.or(fallback.load_address),
+            sanitize: self.sanitize.or(fallback.sanitize),
+            palette: self.palette.or(fallback.palette),
+            scale: self.scale.or(fallback.scale),
+            waveform: self.waveform.or(fallback.waveform),
+            tone: self.tone.or(fallback.tone),
+            volume: self.volume.or(fallback.volume),
+            rewind: self.rewind.or(fallback.rewind),
+            rewind_interval: self.rewind_interval.or(fallback.rewind_interval),
+            keys: match (self.keys, fallback.keys) {
+                (Some(keys), Some(fallback)) => Some(keys.or(&fallback)),
+                (keys, fallback) => keys.or(fallback),
+            },
+        }
+    }
+
+    // What the ROM database recommends for a ROM it knows
+    pub fn known(info: &RomInfo) -> Settings {
+        Settings {
+            quirks: Some(info.quirks),
+            speed: Some(info.instructions_per_second()),
+            load_address: info.start_address,
+            palette: info.palette,
+            ..Settings::default()
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks.unwrap_or_default()
+    }
+
+    pub fn speed(&self) -> u32 {
+        self.speed.unwrap_or(DEFAULT_SPEED)
+    }
+
+    pub fn timer_hz(&self) -> f32 {
+        self.timer_hz.unwrap_or(DEFAULT_TIMER_HZ)
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing.unwrap_or_default()
+    }
+
+    pub fn load_address(&self) -> u16 {
+        self.load_address.unwrap_or(PROGRAM_START)
+    }
+
+    pub fn sanitize(&self) -> bool {
+        self.sanitize.unwrap_or(false)
+    }
+
+    pub fn palette(&self) -> [u32; 4] {
+        self.palette.unwrap_or(DEFAULT_PALETTE)
+    }
+
+    pub fn window_size(&self) -> (u32, u32) {
+        self.scale.map_or(DEFAULT_WINDOW_SIZE, |scale| (Chip8::DISPLAY_W * scale, Chip8::DISPLAY_H * scale))
+    }
+
+    pub fn beeper(&self) -> BeeperSettings {
+        let default = BeeperSettings::default();
+        BeeperSettings {
+            waveform: self.waveform.unwrap_or(default.waveform),
+            frequency: self.tone.unwrap_or(default.frequency),
+            volume: self.volume.map_or(default.volume, |volume| volume / 100.0),
+        }
+    }
+
+    pub fn rewind_seconds(&self) -> u32 {
+        self.rewind.unwrap_or(rewind::DEFAULT_SECONDS)
+    }
+
+    pub fn rewind_interval(&self) -> u32 {
+        self.rewind_interval.unwrap_or(rewind::DEFAULT_INTERVAL)
+    }
+
+    // Keys nobody set stay on the QWERTY block
+    pub fn keymap(&self) -> Keymap {
+        self.keys.unwrap_or_default().or(&Keymap::qwerty())
+    }
+
+    // section is for error messages, e.g. "[defaults]". Keys in skip belong to someone else
+    fn read(table: &dyn TableLike, section: &str, skip: &[&str]) -> Result<Settings, String> {
+        let mut s = Settings::default();
+        for (key, item) in table.iter().filter(|(key, _)| !skip.contains(key)) {
+            let bad = |expects: &str| format!("{} {} should be {}", section, key, expects);
+            let integer = |min: i64, max: i64| {
+                item.as_integer().filter(|n| (min..=max).contains(n))
+                    .ok_or_else(|| bad(&format!("a whole number from {} to {}", min, max)))
+            };
+            let float = || item.as_float().or_else(|| item.as_integer().map(|n| n as f64)).map(|n| n as f32);
+            match key {
+                "quirks" => s.quirks = Some(read_quirks(item, section)?),
+                "speed" => s.speed = Some(integer(1, u32::MAX as i64)? as u32),
+                "timer_hz" => s.timer_hz = Some(float().filter(|hz| hz.is_finite() && *hz > 0.0).ok_or_else(|| bad("a rate in Hz above 0"))?),
+                "timing" => s.timing = Some(item.as_str().ok_or_else(|| bad("\"fixed\" or \"vip\""))?.parse()?),
+                "load_address" => s.load_address = Some(integer(PROGRAM_START as i64, 0xFFFF)? as u16),
+                "sanitize" => s.sanitize = Some(item.as_bool().ok_or_else(|| bad("true or false"))?),
+                "palette" => {
+                    let colours = item.as_array()
+                        .and_then(|colours| colours.iter().map(Value::as_str).collect::<Option<Vec<&str>>>())
+                        .ok_or_else(|| bad("a list of hex colours, e.g. [\"000000\", \"FFFFFF\"]"))?;
+                    s.palette = Some(parse_palette(&colours).map_err(|e| format!("{} {}", section, e))?);
+                },
+                "scale" => s.scale = Some(integer(1, MAX_SCALE as i64)? as u32),
+                "waveform" => s.waveform = Some(item.as_str().ok_or_else(|| bad("\"square\", \"sine\" or \"triangle\""))?.parse()?),
+                "tone" => s.tone = Some(float().filter(|hz| hz.is_finite() && *hz > 0.0).ok_or_else(|| bad("a frequency in Hz"))?),
+                "volume" => s.volume = Some(float().filter(|v| (0.0..=100.0).contains(v)).ok_or_else(|| bad("from 0 to 100"))?),
+                "rewind" => s.rewind = Some(integer(0, u32::MAX as i64)? as u32),
+                "rewind_interval" => s.rewind_interval = Some(integer(0, u32::MAX as i64)? as u32),
+                "keys" => s.keys = Some(read_keys(item, section)?),
+                _ => return Err(unknown_key(section, key, &KEYS)),
+            }
+        }
+        Ok(s)
+    }
+
+    // The settings that are set, as a TOML table
+    fn write(&self, table: &mut Table) {
+        let mut set = |key: &str, v: Option<Value>| if let Some(v) = v { table[key] = Item::Value(v) };
+        set("quirks", self.quirks.map(write_quirks));
+        set("speed", self.speed.map(|n| (n as i64).into()));
+        set("timer_hz", self.timer_hz.map(number));
+        set("timing", self.timing.map(|t| match t {
+            Timing::Fixed => "fixed",
+            Timing::Vip => "vip",
+        }.into()));
+        // In hex, like everywhere else addresses show up
+        set("load_address", self.load_address.map(|addr| format!("0x{:X}", addr).parse().unwrap()));
+        set("sanitize", self.sanitize.map(Value::from));
+        set("palette", self.palette.map(|p| p.iter().map(|rgb| format!("{:06X}", rgb)).collect::<Array>().into()));
+        set("scale", self.scale.map(|n| (n as i64).into()));
+        set("waveform", self.waveform.map(|w| match w {
+            Waveform::Square => "square",
+            Waveform::Sine => "sine",
+            Waveform::Triangle => "triangle",
+        }.into()));
+        set("tone", self.tone.map(number));
+        set("volume", self.volume.map(number));
+        set("rewind", self.rewind.map(|n| (n as i64).into()));
+        set("rewind_interval", self.rewind_interval.map(|n| (n as i64).into()));
+        set("keys", self.keys.map(write_keys));
+    }
+}
+
+// Whole numbers as integers, so 60.0 is written as 60
+fn number(n: f32) -> Value {
+    if n.fract() == 0.0 {
+        (n as i64).into()
+    } else {
+        // Through the shortest f32 text, f64 would write 0.3 as 0.30000001192092896
+        n.to_string().parse::<f64>().unwrap_or(n as f64).into()
+    }
+}
+
+// A preset name, or a table of the individual quirks with the rest as the default
+fn read_quirks(item: &Item, section: &str) -> Result<Quirks, String> {
+    if let Some(name) = item.as_str() {
+        return name.parse();
+    }
+    let table = item.as_table_like()
+        .ok_or_else(|| format!("{} quirks should be a preset name or a table of quirks", section))?;
+    let section = format!("{} quirks", section);
+    let mut quirks = Quirks::default();
+    for (key, item) in table.iter() {
+        let flag = || item.as_bool().ok_or_else(|| format!("{} {} should be true or false", section, key));
+        match key {
+            "shift_uses_vy" => quirks.shift_uses_vy = flag()?,
+            "memory_increment" => quirks.memory_increment = match item.as_str() {
+                Some("unchanged") => MemoryIncrement::Unchanged,
+                Some("x") => MemoryIncrement::X,
+                Some("x+1") => MemoryIncrement::XPlusOne,
+                _ => return Err(format!("{} memory_increment should be \"unchanged\", \"x\" or \"x+1\"", section)),
+            },
+            "jump_uses_vx" => quirks.jump_uses_vx = flag()?,
+            "logic_resets_vf" => quirks.logic_resets_vf = flag()?,
+            "clip_sprites" => quirks.clip_sprites = flag()?,
+            "display_wait" => quirks.display_wait = flag()?,
+            "key_wait_release" => quirks.key_wait_release = flag()?,
+            _ => return Err(unknown_key(&section, key, &QUIRK_KEYS)),
+        }
+    }
+    Ok(quirks)
+}
+
+fn write_quirks(quirks: Quirks) -> Value {
+    if let Some(name) = Quirks::PRESET_NAMES.iter().find(|name| Quirks::preset(name) == Some(quirks)) {
+        return (*name).into();
+    }
+    let mut table = InlineTable::new();
+    table.insert("shift_uses_vy", quirks.shift_uses_vy.into());
+    table.insert("memory_increment", match quirks.memory_increment {
+        MemoryIncrement::Unchanged => "unchanged",
+        MemoryIncrement::X => "x",
+        MemoryIncrement::XPlusOne => "x+1",
+    }.into());
+    table.insert("jump_uses_vx", quirks.jump_uses_vx.into());
+    table.insert("logic_resets_vf", quirks.logic_resets_vf.into());
+    table.insert("clip_sprites", quirks.clip_sprites.into());
+    table.insert("display_wait", quirks.display_wait.into());
+    table.insert("key_wait_release", quirks.key_wait_release.into());
+    table.into()
+}
+
+// Keypad keys to the names of the physical keys pressing them, e.g. { 5 = ["W", "Up"], A = "Z" }
+fn read_keys(item: &Item, section: &str) -> Result<Keymap, String> {
+    let table = item.as_table_like()
+        .ok_or_else(|| format!("{} keys should be a table of keypad keys, e.g. {{ 5 = [\"W\", \"Up\"] }}", section))?;
+    let mut keymap = Keymap::default();
+    for (key, item) in table.iter() {
+        let hex = u8::from_str_radix(key, 16).ok().filter(|_| key.len() == 1)
+            .ok_or_else(|| format!("{} keys has '{}', keypad keys are 0 to F", section, key))?;
+        let names = match item.as_str() {
+            Some(name) => vec![name],
+            None => item.as_array().and_then(|names| names.iter().map(Value::as_str).collect())
+                .ok_or_else(|| format!("{} keys {} should be a key name or a list of them, e.g. [\"W\", \"Up\"]", section, key))?,
+        };
+        let scancodes = names.iter().map(|name| input::scancode_from_name(name)).collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{} keys {}: {}", section, key, e))?;
+        keymap.set(hex, &scancodes).map_err(|e| format!("{} {}", section, e))?;
+    }
+    Ok(keymap)
+}
+
+fn write_keys(keymap: Keymap) -> Value {
+    let mut table = InlineTable::new();
+    for key in keymap.set_keys() {
+        let names: Array = keymap.bindings(key).map(Scancode::name).collect();
+        table.insert(format!("{:X}", key), names.into());
+    }
+    table.into()
+}
+
+fn unknown_key(section: &str, key: &str, known: &[&str]) -> String {
+    let mut message = format!("Unknown setting '{}' in {}.", key, section);
+    if let Some(close) = crate::cli::closest(key, known) {
+        message += &format!(" Did you mean '{}'?", close);
+    }
+    message
+}
+
+/**
+ *  Up to 4 colours as hex RGB, optionally with a leading # or 0x: off, on, XO-CHIP plane 2 and both planes.
+ *  Colours left off keep their defaults, so ["000080", "FFFF00"] only recolours a CHIP-8 game.
+ */
+pub fn parse_palette(colours: &[&str]) -> Result<[u32; 4], String> {
+    let mut palette = DEFAULT_PALETTE;
+    if colours.len() > palette.len() {
+        return Err(format!("palette takes at most {} colours, got {}", palette.len(), colours.len()));
+    }
+    for (slot, colour) in palette.iter_mut().zip(colours) {
+        let hex = colour.trim().trim_start_matches('#').trim_start_matches("0x");
+        *slot = match u32::from_str_radix(hex, 16) {
+            Ok(rgb) if hex.len() == 6 => rgb,
+            _ => return Err(format!("palette colours are 6 digit hex like FF8000, got '{}'", colour)),
+        };
+    }
+    Ok(palette)
+}
+
+/**
+ *  config.toml, global defaults under [defaults] and per-ROM overrides under [rom.<sha1 of the ROM>]:
+ *
+ *      [defaults]
+ *      quirks = "schip"
+ *      scale = 10
+ *
+ *      [rom.0f5ad4eb4b7d0dce2c2d2e1f5c8ab0e6b8fd0b1e]
+ *      name = "INVADERS"    # only there for people reading the file
+ *      speed = 1000
+ *      quirks = { shift_uses_vy = true, clip_sprites = true }
+ *      keys = { 4 = ["Q", "Left"], 6 = ["E", "Right"], 5 = "Space" }
+ *
+ *  The document is kept as read so writing settings back out leaves the rest of it, comments included, alone.
+ */
+pub struct Config {
+    path: PathBuf,
+    document: DocumentMut,
+    defaults: Settings,
+    // Keyed by the ROM's SHA-1 in hex
+    roms: HashMap<String, Settings>,
+}
+
+impl Config {
+
+    /**
+     *  Reads the config from path, or config_path() when None. A missing file at the default
+     *  location is an empty config, one that was asked for by name has to exist.
+     */
+    pub fn load(path: Option<&str>) -> Result<Config, String> {
+        let (path, required) = match path {
+            Some(path) => (PathBuf::from(path), true),
+            None => (config_path(), false),
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Unable to read config '{}' :: {}", path.display(), e)),
+        };
+        Config::parse(path, &text)
+    }
+
+    fn parse(path: PathBuf, text: &str) -> Result<Config, String> {
+        let error = |e: String| format!("Error in config '{}' :: {}", path.display(), e);
+        let document: DocumentMut = text.parse().map_err(|e: toml_edit::TomlError| error(e.to_string()))?;
+
+        let mut defaults = Settings::default();
+        let mut roms = HashMap::new();
+        for (key, item) in document.iter() {
+            match key {
+                "defaults" => {
+                    let table = item.as_table_like().ok_or_else(|| error("[defaults] should be a table".to_string()))?;
+                    defaults = Settings::read(table, "[defaults]", &[]).map_err(error)?;
+                },
+                "rom" => {
+                    let table = item.as_table_like().ok_or_else(|| error("[rom] should be a table".to_string()))?;
+                    for (hash, item) in table.iter() {
+                        let section = format!("[rom.{}]", hash);
+                        let table = item.as_table_like().ok_or_else(|| error(format!("{} should be a table", section)))?;
+                        roms.insert(hash.to_ascii_lowercase(), Settings::read(table, &section, &["name"]).map_err(error)?);
+                    }
+                },
+                _ => return Err(error(format!("Unknown section [{}], expected [defaults] or [rom.<sha1>]", key))),
+            }
+        }
+        Ok(Config { path, document, defaults, roms })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn defaults(&self) -> &Settings {
+        &self.defaults
+    }
+
+    // Just the overrides in the ROM's own section, empty if it has none
+    pub fn rom(&self, rom: &RomHash) -> Settings {
+        self.roms.get(&hash_to_hex(rom)).copied().unwrap_or_default()
+    }
+
+    pub fn set_defaults(&mut self, settings: Settings) {
+        let mut table = section_like(self.document.get("defaults"));
+        if table.position().is_none() {
+            // A new [defaults] goes at the top, ahead of the ROMs
+            table.set_position(0);
+        }
+        settings.write(&mut table);
+        self.document["defaults"] = Item::Table(table);
+        self.defaults = settings;
+    }
+
+    // name is written alongside to tell people reading the file which ROM the hash is
+    pub fn set_rom(&mut self, rom: &RomHash, name: &str, settings: Settings) {
+        let hash = hash_to_hex(rom);
+        let existing = self.document.get("rom").and_then(|roms| roms.get(&hash));
+        let mut table = section_like(existing);
+        table["name"] = existing.and_then(|section| section.get("name")).cloned().unwrap_or_else(|| value(name));
+        if table.position().is_none() {
+            // New ROMs go at the end
+            table.set_position(usize::MAX);
+        }
+        settings.write(&mut table);
+
+        if !self.document.contains_key("rom") {
+            let mut roms = Table::new();
+            roms.set_implicit(true);
+            self.document["rom"] = Item::Table(roms);
+        }
+        self.document["rom"][&hash] = Item::Table(table);
+        self.roms.insert(hash, settings);
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Unable to create config directory '{}' :: {}", dir.display(), e))?;
+        }
+        std::fs::write(&self.path, self.document.to_string())
+            .map_err(|e| format!("Unable to write config '{}' :: {}", self.path.display(), e))
+    }
+}
+
+// An empty table to replace a section with, keeping the comments above it and its place in the file
+fn section_like(existing: Option<&Item>) -> Table {
+    let mut table = Table::new();
+    if let Some(existing) = existing.and_then(Item::as_table) {
+        *table.decor_mut() = existing.decor().clone();
+        if let Some(position) = existing.position() {
+            table.set_position(position);
+        }
+    }
+    table
+}
+
+// $XDG_CONFIG_HOME/rusty-chip8-emu/config.toml, falling back to the platform's usual spot
+pub fn config_path() -> PathBuf {
+    let base = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("rusty-chip8-emu").join("config.toml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: RomHash = [0xAB; 20];
+
+    fn parse(text: &str) -> Result<Config, String> {
+        Config::parse(PathBuf::from("config.toml"), text)
+    }
+
+    fn error(text: &str) -> String {
+        match parse(text) {
+            Err(e) => e.trim_start_matches("Error in config 'config.toml' :: ").to_string(),
+            Ok(_) => panic!("parsed {}", text),
+        }
+    }
+
+    #[test]
+    fn reads_defaults() {
+        let config = parse("\
+# Comments are fine
+[defaults]
+quirks = \"schip\"
+speed = 700
+timer_hz = 60
+timing = \"vip\"
+load_address = 0x600
+sanitize = true
+palette = [\"000080\", \"#FFFF00\"]
+scale = 10
+waveform = \"sine\"
+tone = 261.5
+volume = 50
+rewind = 0
+rewind_interval = 4
+").unwrap();
+        let palette = [0x000080, 0xFFFF00, DEFAULT_PALETTE[2], DEFAULT_PALETTE[3]];
+        assert_eq!(*config.defaults(), Settings {
+            quirks: Quirks::preset("schip"),
+            speed: Some(700),
+            timer_hz: Some(60.0),
+            timing: Some(Timing::Vip),
+            load_address: Some(0x600),
+            sanitize: Some(true),
+            palette: Some(palette),
+            scale: Some(10),
+            waveform: Some(Waveform::Sine),
+            tone: Some(261.5),
+            volume: Some(50.0),
+            rewind: Some(0),
+            rewind_interval: Some(4),
+            keys: None,
+        });
+        assert_eq!(parse("").unwrap().defaults(), &Settings::default());
+    }
+
+    #[test]
+    fn reads_quirks_tables() {
+        let config = parse("[defaults]\nquirks = { shift_uses_vy = true, memory_increment = \"x+1\" }").unwrap();
+        let quirks = Quirks { shift_uses_vy: true, memory_increment: MemoryIncrement::XPlusOne, ..Quirks::default() };
+        assert_eq!(config.defaults().quirks, Some(quirks));
+    }
+
+    #[test]
+    fn rom_sections_by_sha1() {
+        let config = parse(&format!("\
+[defaults]
+speed = 100
+
+[rom.{}]
+name = \"GAME\"
+speed = 1000
+", hash_to_hex(&HASH).to_uppercase())).unwrap();
+        // The name is only there for people, and the hash matches whatever its case
+        assert_eq!(config.rom(&HASH), Settings { speed: Some(1000), ..Settings::default() });
+        assert_eq!(config.rom(&[0; 20]), Settings::default());
+        assert!(error("[rom.abab]\nscale = \"big\"").starts_with("[rom.abab] scale should be a whole number"));
+    }
+
+    #[test]
+    fn writes_rom_sections_back() {
+        let mut config = parse("# Mine\n[defaults]\nscale = 4 # Big\n").unwrap();
+        config.set_rom(&HASH, "GAME", Settings { speed: Some(1000), load_address: Some(0x600), ..Settings::default() });
+        let text = config.document.to_string();
+        assert_eq!(text, format!("\
+# Mine
+[defaults]
+scale = 4 # Big
+
+[rom.{}]
+name = \"GAME\"
+speed = 1000
+load_address = 0x600
+", hash_to_hex(&HASH)));
+        assert_eq!(parse(&text).unwrap().rom(&HASH), config.rom(&HASH));
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert_eq!(error("[defaults]\nsped = 5"), "Unknown setting 'sped' in [defaults]. Did you mean 'speed'?");
+        assert_eq!(error("[defaults]\nfoo = 5"), "Unknown setting 'foo' in [defaults].");
+        // name is only for ROM sections
+        assert_eq!(error("[defaults]\nname = \"x\""), "Unknown setting 'name' in [defaults].");
+        assert_eq!(error("[rom.abab]\nname = \"x\"\nvolme = 5"), "Unknown setting 'volme' in [rom.abab]. Did you mean 'volume'?");
+        assert_eq!(error("[defaults]\nquirks = { clip_sprite = true }"),
+            "Unknown setting 'clip_sprite' in [defaults] quirks. Did you mean 'clip_sprites'?");
+        assert_eq!(error("[default]\nspeed = 5"), "Unknown section [default], expected [defaults] or [rom.<sha1>]");
+    }
+
+    #[test]
+    fn bad_values() {
+        assert_eq!(error("[defaults]\nscale = 0"), "[defaults] scale should be a whole number from 1 to 64");
+        assert_eq!(error("[defaults]\nload_address = 0x100"), "[defaults] load_address should be a whole number from 512 to 65535");
+        assert_eq!(error("[defaults]\nsanitize = 1"), "[defaults] sanitize should be true or false");
+        assert_eq!(error("[defaults]\nvolume = 101"), "[defaults] volume should be from 0 to 100");
+        assert_eq!(error("[defaults]\ntone = inf"), "[defaults] tone should be a frequency in Hz");
+        assert_eq!(error("[defaults]\nquirks = { clip_sprites = 1 }"), "[defaults] quirks clip_sprites should be true or false");
+        assert!(error("[defaults]\nquirks = \"chip-9\"").starts_with("Unknown quirks preset 'chip-9'"));
+        // Not TOML at all
+        assert!(parse("[defaults").is_err());
+    }
+
+    #[test]
+    fn or_precedence() {
+        let config = parse(&format!("\
+[defaults]
+speed = 100
+volume = 50
+
+[rom.{}]
+speed = 500
+scale = 4
+", hash_to_hex(&HASH))).unwrap();
+        let cli = Settings { speed: Some(1000), ..Settings::default() };
+        let recommended = Settings { speed: Some(700), scale: Some(8), volume: Some(20.0), tone: Some(300.0), ..Settings::default() };
+
+        // The command line, then the ROM's section, then [defaults], then the database for whatever's left
+        let settings = cli.or(&config.rom(&HASH)).or(config.defaults()).or(&recommended);
+        assert_eq!((settings.speed, settings.scale, settings.volume, settings.tone), (Some(1000), Some(4), Some(50.0), Some(300.0)));
+        let settings = Settings::default().or(&config.rom(&HASH)).or(config.defaults()).or(&recommended);
+        assert_eq!(settings.speed, Some(500));
+        let settings = Settings::default().or(&config.rom(&[0; 20])).or(config.defaults()).or(&recommended);
+        assert_eq!((settings.speed, settings.scale), (Some(100), Some(8)));
+        // Built in defaults under all of them
+        assert_eq!(Settings::default().or(config.defaults()).timer_hz(), DEFAULT_TIMER_HZ);
+    }
+
+    #[test]
+    fn or_merges_keys_key_by_key() {
+        let mut cli = Keymap::default();
+        cli.set(5, &[Scancode::Space]).unwrap();
+        let mut rom = Keymap::default();
+        rom.set(5, &[Scancode::W]).unwrap();
+        rom.set(4, &[Scancode::Left]).unwrap();
+        let cli = Settings { keys: Some(cli), ..Settings::default() };
+        let rom = Settings { keys: Some(rom), ..Settings::default() };
+
+        let keymap = cli.or(&rom).keymap();
+        assert_eq!(keymap.keypad_key(Scancode::Space), Some(5));
+        assert_eq!(keymap.keypad_key(Scancode::W), None);
+        assert_eq!(keymap.keypad_key(Scancode::Left), Some(4));
+        // Keys nobody set are still on the QWERTY block
+        assert_eq!(keymap.keypad_key(Scancode::Num1), Some(1));
+        assert_eq!(keymap.keypad_key(Scancode::Q), None);
+    }
+
+    #[test]
+    fn reads_keys() {
+        let config = parse("[defaults]\nkeys = { 5 = [\"W\", \"Up\"], a = \"Left Shift\", F = [] }").unwrap();
+        let keys = config.defaults().keys.unwrap();
+        assert_eq!(keys.bindings(5).collect::<Vec<_>>(), [Scancode::W, Scancode::Up]);
+        assert_eq!(keys.bindings(0xA).collect::<Vec<_>>(), [Scancode::LShift]);
+        assert_eq!(keys.bindings(0xF).count(), 0);
+        assert_eq!(keys.set_keys().collect::<Vec<_>>(), [5, 0xA, 0xF]);
+    }
+
+    #[test]
+    fn writes_keys_back() {
+        let mut keys = Keymap::default();
+        keys.set(5, &[Scancode::W, Scancode::Up]).unwrap();
+        keys.set(0xA, &[Scancode::Space]).unwrap();
+        let mut config = parse("").unwrap();
+        config.set_defaults(Settings { keys: Some(keys), ..Settings::default() });
+        let text = config.document.to_string();
+        assert_eq!(text, "[defaults]\nkeys = { 5 = [\"W\", \"Up\"], A = [\"Space\"] }\n");
+        assert_eq!(parse(&text).unwrap().defaults().keys, Some(keys));
+    }
+
+    #[test]
+    fn bad_keys() {
+        assert_eq!(error("[defaults]\nkeys = { G = \"W\" }"), "[defaults] keys has 'G', keypad keys are 0 to F");
+        assert_eq!(error("[defaults]\nkeys = { 10 = \"W\" }"), "[defaults] keys has '10', keypad keys are 0 to F");
+        assert_eq!(error("[defaults]\nkeys = \"W\""),
+            "[defaults] keys should be a table of keypad keys, e.g. { 5 = [\"W\", \"Up\"] }");
+        assert_eq!(error("[defaults]\nkeys = { 5 = 5 }"),
+            "[defaults] keys 5 should be a key name or a list of them, e.g. [\"W\", \"Up\"]");
+        assert!(error("[defaults]\nkeys = { 5 = \"Nope\" }").starts_with("[defaults] keys 5: Unknown key 'Nope'"));
+        assert_eq!(error("[defaults]\nkeys = { 5 = [\"A\", \"B\", \"C\", \"D\", \"E\"] }"),
+            "[defaults] key 5 can have at most 4 keys bound to it, got 5");
+        // There has to be a way to quit
+        assert_eq!(error("[defaults]\nkeys = { 5 = \"Escape\" }"),
+            "[defaults] key 5 can't be bound to Escape, the emulator keeps it for itself");
+    }
+}
@@ -0,0 +1,335 @@
+use crate::chip8::{DEFAULT_PALETTE, PROGRAM_START};
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::savestate::{self, RomHash};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// data/chip-8-database, see the README there
+const PROGRAMS: &str = include_str!("../data/chip-8-database/programs.json");
+const HASHES: &str = include_str!("../data/chip-8-database/sha1-hashes.json");
+const PLATFORMS: &str = include_str!("../data/chip-8-database/platforms.json");
+
+// The platforms in the database the emulator runs. hybridVIP ROMs only run properly with --vip,
+// their CHIP-8 parts at least run as on the VIP without it
+const SUPPORTED: [&str; 7] = ["originalChip8", "hybridVIP", "modernChip8", "chip48", "superchip1", "superchip", "xochip"];
+
+/**
+ *  A machine ROMs are written for, with the quirks it has and how many instructions it runs a frame.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Platform {
+    // e.g. "superchip", what programs.json refers to it by
+    pub id: String,
+    pub name: String,
+    pub tickrate: u32,
+    pub quirks: Quirks,
+}
+
+/**
+ *  What the database knows about a ROM, with its platform already picked.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub release: Option<String>,
+    pub platform: Platform,
+    // Quirks of the platform, with any the ROM is known to need changed
+    pub quirks: Quirks,
+    // Instructions per 60 Hz frame
+    pub tickrate: u32,
+    pub start_address: Option<u16>,
+    // Hex keypad keys for the database's names: up, down, left, right, a, b, player2Up, ...
+    pub keys: Vec<(String, u8)>,
+    pub palette: Option<[u32; 4]>,
+}
+
+impl RomInfo {
+    pub fn instructions_per_second(&self) -> u32 {
+        self.tickrate * 60
+    }
+
+    pub fn key(&self, name: &str) -> Option<u8> {
+        self.keys.iter().find(|(n, _)| n == name).map(|(_, key)| *key)
+    }
+}
+
+// e.g. "'Space Invaders' (1978) by David Winter, SUPER-CHIP 1.1 at 30 instructions a frame"
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}'", self.title)?;
+        if let Some(release) = &self.release {
+            write!(f, " ({})", release)?;
+        }
+        if !self.authors.is_empty() {
+            write!(f, " by {}", self.authors.join(", "))?;
+        }
+        write!(f, ", {} at {} instructions a frame", self.platform.name, self.tickrate)
+    }
+}
+
+/**
+ *  ROM metadata in the format of the community chip-8-database (https://github.com/chip-8/chip-8-database),
+ *  looked up by the SHA-1 of a ROM's bytes.
+ */
+pub struct Database {
+    programs: Vec<Value>,
+    // SHA-1 in lower case hex to an index into programs
+    hashes: HashMap<String, usize>,
+    // The ones the emulator can run, anything else in the files is skipped
+    platforms: Vec<Platform>,
+}
+
+impl Database {
+
+    // The copy built into the emulator
+    pub fn embedded() -> Database {
+        Database::parse(PROGRAMS, HASHES, PLATFORMS).unwrap_or_else(|e| panic!("Built in ROM database :: {}", e))
+    }
+
+    /**
+     *  Reads programs.json and sha1-hashes.json from dir, and platforms.json if it's there too
+     *  (the built in platforms otherwise).
+     */
+    pub fn load_dir(dir: &Path) -> Result<Database, String> {
+        let read = |name: &str| {
+            let path = dir.join(name);
+            std::fs::read_to_string(&path).map_err(|e| format!("Unable to read '{}' :: {}", path.display(), e))
+        };
+        let platforms = if dir.join("platforms.json").exists() { read("platforms.json")? } else { PLATFORMS.to_string() };
+        Database::parse(&read("programs.json")?, &read("sha1-hashes.json")?, &platforms)
+            .map_err(|e| format!("Error in ROM database '{}' :: {}", dir.display(), e))
+    }
+
+    // The one in user_dir() when there is one, so a newer database can be dropped in without a rebuild
+    pub fn open() -> Result<Database, String> {
+        let dir = user_dir();
+        if dir.join("programs.json").exists() {
+            Database::load_dir(&dir)
+        } else {
+            Ok(Database::embedded())
+        }
+    }
+
+    fn parse(programs: &str, hashes: &str, platforms: &str) -> Result<Database, String> {
+        let json = |name: &str, text: &str| serde_json::from_str::<Value>(text).map_err(|e| format!("{} :: {}", name, e));
+
+        let programs = match json("programs.json", programs)? {
+            Value::Array(programs) => programs,
+            _ => return Err("programs.json should be a list of programs".to_string()),
+        };
+        let hashes = json("sha1-hashes.json", hashes)?.as_object()
+            .ok_or("sha1-hashes.json should map SHA-1 hashes to program indices")?
+            .iter()
+            .map(|(hash, index)| match index.as_u64() {
+                Some(index) if (index as usize) < programs.len() => Ok((hash.to_ascii_lowercase(), index as usize)),
+                _ => Err(format!("sha1-hashes.json {} isn't the index of a program", hash)),
+            })
+            .collect::<Result<_, _>>()?;
+        let platforms = json("platforms.json", platforms)?.as_array()
+            .ok_or("platforms.json should be a list of platforms")?
+            .iter()
+            .filter_map(read_platform)
+            .collect();
+        Ok(Database { programs, hashes, platforms })
+    }
+
+    pub fn platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.iter().find(|p| p.id == id)
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        self.lookup_hash(&savestate::rom_hash(rom))
+    }
+
+    /**
+     *  The ROM's entry, on the first of its platforms the emulator runs. None for ROMs the database
+     *  doesn't have, or only knows on platforms the emulator can't run.
+     */
+    pub fn lookup_hash(&self, hash: &RomHash) -> Option<RomInfo> {
+        let hex = savestate::hash_to_hex(hash);
+        let program = &self.programs[*self.hashes.get(&hex)?];
+        let rom = &program["roms"][&hex];
+
+        let platform = rom["platforms"].as_array()?.iter()
+            .filter_map(Value::as_str)
+            .find_map(|id| self.platform(id))?
+            .clone();
+        let mut quirks = platform.quirks;
+        if let Some(changed) = rom["quirkyPlatforms"][&platform.id].as_object() {
+            for (quirk, on) in changed {
+                if let Some(on) = on.as_bool() {
+                    set_quirk(&mut quirks, quirk, on);
+                }
+            }
+        }
+
+        let text = |v: &Value| v.as_str().map(str::to_string);
+        Some(RomInfo {
+            title: text(&program["title"]).unwrap_or_else(|| hex.clone()),
+            authors: program["authors"].as_array().map_or(Vec::new(), |a| a.iter().filter_map(text).collect()),
+            release: text(&program["release"]),
+            tickrate: rom["tickrate"].as_u64().filter(|n| *n > 0).map_or(platform.tickrate, |n| n as u32),
+            start_address: rom["startAddress"].as_u64().filter(|addr| (PROGRAM_START as u64..=0xFFFF).contains(addr)).map(|addr| addr as u16),
+            keys: rom["keys"].as_object().map_or(Vec::new(), |keys| keys.iter()
+                .filter_map(|(name, key)| Some((name.clone(), key.as_u64().filter(|k| *k <= 0xF)? as u8)))
+                .collect()),
+            palette: read_palette(&rom["colors"]["pixels"]),
+            platform,
+            quirks,
+        })
+    }
+}
+
+// $XDG_DATA_HOME/rusty-chip8-emu/chip-8-database, read instead of the built in database when it's there
+pub fn user_dir() -> PathBuf {
+    savestate::data_dir().join("chip-8-database")
+}
+
+// None for platforms the emulator doesn't run
+fn read_platform(platform: &Value) -> Option<Platform> {
+    if !SUPPORTED.contains(&platform["id"].as_str()?) {
+        return None;
+    }
+    let mut quirks = Quirks::vip();
+    for (quirk, on) in platform["quirks"].as_object()? {
+        set_quirk(&mut quirks, quirk, on.as_bool()?);
+    }
+    // Only Octo finishes Fx0A on the key press, every interpreter before it waited for the release
+    quirks.key_wait_release = platform["id"] != "xochip";
+    Some(Platform {
+        id: platform["id"].as_str()?.to_string(),
+        name: platform["name"].as_str()?.to_string(),
+        tickrate: platform["defaultTickrate"].as_u64().filter(|n| *n > 0)? as u32,
+        quirks,
+    })
+}
+
+// The database's names for the quirks, on meaning the platform has it
+fn set_quirk(quirks: &mut Quirks, quirk: &str, on: bool) {
+    match quirk {
+        "shift" => quirks.shift_uses_vy = !on,
+        "memoryIncrementByX" => set_memory_increment(quirks, MemoryIncrement::X, on),
+        "memoryLeaveIUnchanged" => set_memory_increment(quirks, MemoryIncrement::Unchanged, on),
+        "wrap" => quirks.clip_sprites = !on,
+        "jump" => quirks.jump_uses_vx = on,
+        "vblank" => quirks.display_wait = on,
+        "logic" => quirks.logic_resets_vf = on,
+        _ => {},
+    }
+}
+
+// Turning one of the two memory quirks off goes back to the VIP's I = I + x + 1, unless the other one is on
+fn set_memory_increment(quirks: &mut Quirks, increment: MemoryIncrement, on: bool) {
+    if on {
+        quirks.memory_increment = increment;
+    } else if quirks.memory_increment == increment {
+        quirks.memory_increment = MemoryIncrement::XPlusOne;
+    }
+}
+
+// "#RRGGBB" colours: off, on, then the XO-CHIP planes. Any left off keep their defaults
+fn read_palette(pixels: &Value) -> Option<[u32; 4]> {
+    let mut palette = DEFAULT_PALETTE;
+    let pixels = pixels.as_array()?;
+    for (slot, colour) in palette.iter_mut().zip(pixels) {
+        *slot = u32::from_str_radix(colour.as_str()?.trim_start_matches('#'), 16).ok()?;
+    }
+    Some(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three ROMs in the upstream format: one with everything set, a plain one and one only on a platform we don't run
+    fn database() -> Database {
+        let programs = format!(r##"[
+            {{
+                "title": "Game",
+                "authors": ["Someone", "Someone Else"],
+                "release": "1991",
+                "roms": {{
+                    "{}": {{
+                        "platforms": ["megachip8", "superchip"],
+                        "tickrate": 50,
+                        "startAddress": 1536,
+                        "quirkyPlatforms": {{ "superchip": {{ "shift": false, "wrap": true }} }},
+                        "keys": {{ "up": 5, "a": 6, "b": 16 }},
+                        "colors": {{ "pixels": ["#000080", "#FFFF00"] }}
+                    }}
+                }}
+            }},
+            {{
+                "title": "Old",
+                "roms": {{
+                    "{}": {{ "platforms": ["originalChip8"] }},
+                    "{}": {{ "platforms": ["megachip8"] }}
+                }}
+            }}
+        ]"##, hex(b"game"), hex(b"old"), hex(b"mega"));
+        let hashes = format!(r#"{{ "{}": 0, "{}": 1, "{}": 1 }}"#, hex(b"game").to_uppercase(), hex(b"old"), hex(b"mega"));
+        Database::parse(&programs, &hashes, PLATFORMS).unwrap()
+    }
+
+    fn hex(rom: &[u8]) -> String {
+        savestate::hash_to_hex(&savestate::rom_hash(rom))
+    }
+
+    #[test]
+    fn looks_up_everything_a_rom_has() {
+        let database = database();
+        let info = database.lookup(b"game").unwrap();
+        assert_eq!(info.to_string(), "'Game' (1991) by Someone, Someone Else, SUPER-CHIP 1.1 at 50 instructions a frame");
+        // megachip8 is skipped for the next platform along
+        assert_eq!(info.platform.id, "superchip");
+        let mut quirks = info.platform.quirks;
+        quirks.shift_uses_vy = true;
+        quirks.clip_sprites = false;
+        assert_eq!(info.quirks, quirks);
+        assert_eq!(info.instructions_per_second(), 3000);
+        assert_eq!(info.start_address, Some(0x600));
+        assert_eq!((info.key("up"), info.key("a"), info.key("b"), info.key("down")), (Some(5), Some(6), None, None));
+        assert_eq!(info.palette, Some([0x000080, 0xFFFF00, DEFAULT_PALETTE[2], DEFAULT_PALETTE[3]]));
+    }
+
+    #[test]
+    fn platform_defaults() {
+        let info = database().lookup(b"old").unwrap();
+        assert_eq!(info.to_string(), "'Old', Cosmac VIP CHIP-8 at 15 instructions a frame");
+        assert_eq!(info.quirks, info.platform.quirks);
+        assert_eq!((info.start_address, info.palette), (None, None));
+    }
+
+    #[test]
+    fn unknown_and_unsupported_roms() {
+        let database = database();
+        assert_eq!(database.lookup(b"mega"), None);
+        assert_eq!(database.lookup(b"nothing"), None);
+        assert!(database.platform("megachip8").is_none());
+    }
+
+    // The database's platforms have the same quirks as our presets for them
+    #[test]
+    fn platforms_match_the_presets() {
+        let database = Database::embedded();
+        for &(id, preset) in &[("originalChip8", "vip"), ("chip48", "chip48"), ("superchip", "schip"), ("xochip", "xochip")] {
+            assert_eq!(database.platform(id).unwrap().quirks, Quirks::preset(preset).unwrap(), "{}", id);
+        }
+        for id in &SUPPORTED {
+            assert!(database.platform(id).is_some(), "{} is missing from platforms.json", id);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let error = |programs: &str, hashes: &str| Database::parse(programs, hashes, PLATFORMS).err().unwrap();
+        assert_eq!(error("[{}]", r#"{ "abc": 1 }"#), "sha1-hashes.json abc isn't the index of a program");
+        assert_eq!(error("{}", "{}"), "programs.json should be a list of programs");
+        assert!(error("[", "{}").starts_with("programs.json :: "));
+        let missing = Database::load_dir(Path::new("no such directory")).err().unwrap();
+        assert!(missing.starts_with("Unable to read 'no such directory"), "{}", missing);
+    }
+}
@@ -0,0 +1,537 @@
+use crate::chip8::{Chip8, Chip8Error};
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+impl Register {
+    fn read(self, chip8: &Chip8) -> u16 {
+        match self {
+            Register::V(x) => chip8.v()[x as usize] as u16,
+            Register::I => chip8.i(),
+            Register::Pc => chip8.pc(),
+            Register::Sp => chip8.sp(),
+            Register::Dt => chip8.delay_timer() as u16,
+            Register::St => chip8.sound_timer() as u16,
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.to_ascii_uppercase();
+        match s.as_str() {
+            "I" => Some(Register::I),
+            "PC" => Some(Register::Pc),
+            "SP" => Some(Register::Sp),
+            "DT" => Some(Register::Dt),
+            "ST" => Some(Register::St),
+            _ if s.len() == 2 && s.starts_with('V') => u8::from_str_radix(&s[1..], 16).ok().map(Register::V),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::Sp => write!(f, "SP"),
+            Register::Dt => write!(f, "DT"),
+            Register::St => write!(f, "ST"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq, Ne, Lt, Le, Gt, Ge,
+}
+
+impl CompareOp {
+    const ALL: [(&'static str, CompareOp); 6] = [
+        ("==", CompareOp::Eq), ("!=", CompareOp::Ne), ("<=", CompareOp::Le),
+        (">=", CompareOp::Ge), ("<", CompareOp::Lt), (">", CompareOp::Gt),
+    ];
+
+    fn eval(self, a: u16, b: u16) -> bool {
+        match self {
+            CompareOp::Eq => a == b,
+            CompareOp::Ne => a != b,
+            CompareOp::Lt => a < b,
+            CompareOp::Le => a <= b,
+            CompareOp::Gt => a > b,
+            CompareOp::Ge => a >= b,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        CompareOp::ALL.iter().find(|(_, op)| *op == self).map(|(s, _)| *s).unwrap()
+    }
+}
+
+/**
+ *  Breaks when a register comparison such as `V3 == 10` becomes true.
+ *  Only the transition from false to true breaks, so resuming doesn't immediately stop again.
+ */
+#[derive(Debug, Clone)]
+pub struct RegisterCondition {
+    pub register: Register,
+    pub op: CompareOp,
+    pub value: u16,
+    was_true: bool,
+}
+
+impl fmt::Display for RegisterCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {:X}", self.register, self.op.symbol(), self.value)
+    }
+}
+
+/**
+ *  Opcode pattern like "Dxyn" or "8xy6": upper case hex digits must match, anything else is a wildcard
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodePattern {
+    mask: u16,
+    value: u16,
+    text: String,
+}
+
+impl OpcodePattern {
+    pub fn parse(s: &str) -> Option<Self> {
+        if s.chars().count() != 4 {
+            return None;
+        }
+        let mut mask = 0;
+        let mut value = 0;
+        for c in s.chars() {
+            mask <<= 4;
+            value <<= 4;
+            match c {
+                // Only upper case hex is literal, so the usual lower case x/y/n/k placeholders are wildcards
+                '0'..='9' | 'A'..='F' => {
+                    mask |= 0xF;
+                    value |= c.to_digit(16).unwrap() as u16;
+                },
+                _ if c.is_ascii_alphabetic() || c == '?' || c == '_' => {},
+                _ => return None
+            }
+        }
+        Some(OpcodePattern { mask, value, text: s.to_string() })
+    }
+
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    // inclusive
+    pub end: u16,
+    pub on_read: bool,
+    pub on_write: bool,
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match (self.on_read, self.on_write) {
+            (true, true) => "rw",
+            (true, false) => "r",
+            _ => "w",
+        };
+        if self.start == self.end {
+            write!(f, "{} {:04X}", kind, self.start)
+        } else {
+            write!(f, "{} {:04X}-{:04X}", kind, self.start, self.end)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BreakReason {
+    Paused,
+    Step,
+    Breakpoint(u16),
+    Watchpoint { watch: Watchpoint, write: bool, pc: u16 },
+    Condition(String),
+    Opcode { pattern: String, opcode: u16 },
+    Error(String),
+    // The program crashed, chip8 is left on the faulting instruction
+    Fault(Chip8Error),
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakReason::Paused => write!(f, "Paused"),
+            BreakReason::Step => write!(f, "Step"),
+            BreakReason::Breakpoint(addr) => write!(f, "Breakpoint at {:04X}", addr),
+            BreakReason::Watchpoint { watch, write, pc } => {
+                write!(f, "Watchpoint [{}] {} by instruction at {:04X}", watch, if *write { "written" } else { "read" }, pc)
+            },
+            BreakReason::Condition(c) => write!(f, "Condition {}", c),
+            BreakReason::Opcode { pattern, opcode } => write!(f, "Opcode {:04X} matched {}", opcode, pattern),
+            BreakReason::Error(e) => write!(f, "Error: {}", e),
+            BreakReason::Fault(e) => write!(f, "Crashed: {}", e),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum RunMode {
+    Running,
+    Paused,
+    StepInto,
+    // Run until execution is back at return_pc with the stack as deep as it was
+    StepOver { return_pc: u16, sp: u16 },
+    // Run until the current subroutine returns
+    StepOut { sp: u16 },
+}
+
+/**
+ *  Wraps Chip8::cycle with breakpoints, watchpoints, register conditions, opcode breaks
+ *  and stepping. The main loop calls Debugger::cycle instead of Chip8::cycle.
+ */
+pub struct Debugger {
+    pub breakpoints: BTreeSet<u16>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub conditions: Vec<RegisterCondition>,
+    pub opcode_breaks: Vec<OpcodePattern>,
+    mode: RunMode,
+    last_break: Option<BreakReason>,
+    // Set by resuming/stepping, the next cycle notes its pc in resumed_at
+    resuming: bool,
+    /**
+     *  Where execution carried on from. Nothing breaks until the pc moves off it, an Fx0A waiting
+     *  for a key or a Dxyn waiting for vblank stays on the instruction it stopped at for many cycles.
+     */
+    resumed_at: Option<u16>,
+}
+
+impl Debugger {
+
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+            opcode_breaks: Vec::new(),
+            mode: RunMode::Running,
+            last_break: None,
+            resuming: false,
+            resumed_at: None,
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.mode == RunMode::Paused
+    }
+
+    pub fn last_break(&self) -> Option<&BreakReason> {
+        self.last_break.as_ref()
+    }
+
+    // The error we're stopped on, if the last break was the program crashing
+    pub fn fault(&self) -> Option<&Chip8Error> {
+        match &self.last_break {
+            Some(BreakReason::Fault(e)) => Some(e),
+            _ => None
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if !self.paused() {
+            self.break_with(BreakReason::Paused);
+        }
+    }
+
+    pub fn resume(&mut self) {
+        self.run(RunMode::Running);
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if self.paused() { self.resume() } else { self.pause() }
+    }
+
+    pub fn step_into(&mut self) {
+        self.run(RunMode::StepInto);
+    }
+
+    // Steps over a 2nnn CALL by running the whole subroutine, anything else is a single step
+    pub fn step_over(&mut self, chip8: &Chip8) {
+        let pc = chip8.pc();
+        if chip8.opcode_at(pc) & 0xF000 == 0x2000 {
+            self.run(RunMode::StepOver { return_pc: pc.wrapping_add(2), sp: chip8.sp() });
+        } else {
+            self.run(RunMode::StepInto);
+        }
+    }
+
+    // Runs until the current subroutine executes its 00EE RET
+    pub fn step_out(&mut self, chip8: &Chip8) {
+        if chip8.sp() == 0 {
+            self.last_break = Some(BreakReason::Error("Not inside a subroutine".to_string()));
+            return;
+        }
+        self.run(RunMode::StepOut { sp: chip8.sp() });
+    }
+
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    /**
+     *  Adds a break from text typed into the debugger. Numbers are hex.
+     *    200             PC breakpoint
+     *    V3 == 10        register condition (==, !=, <, <=, >, >= on V0-VF, I, PC, SP, DT, ST)
+     *    op Dxyn         opcode pattern, upper case hex digits match, anything else is a wildcard
+     *    w 300-30F       watch writes to a range (r for reads, rw for both)
+     *  Returns a description of what was added.
+     */
+    pub fn add_from_str(&mut self, input: &str) -> Result<String, String> {
+        let input = input.trim();
+        let mut words = input.split_whitespace();
+        let first = words.next().ok_or("Nothing to add")?;
+        let rest: Vec<&str> = words.collect();
+
+        match first.to_ascii_lowercase().as_str() {
+            "op" => {
+                let pattern = rest.first().and_then(|p| OpcodePattern::parse(p))
+                    .ok_or("Expected a 4 character opcode pattern like 'op Dxyn'")?;
+                let text = format!("op {}", pattern.text);
+                self.opcode_breaks.push(pattern);
+                return Ok(text);
+            },
+            kind @ "r" | kind @ "w" | kind @ "rw" => {
+                let range = rest.first().ok_or("Expected an address or range like 'w 300-30F'")?;
+                let (start, end) = match range.find('-') {
+                    Some(n) => (parse_hex(&range[..n])?, parse_hex(&range[n + 1..])?),
+                    None => { let a = parse_hex(range)?; (a, a) }
+                };
+                let watch = Watchpoint {
+                    start: start.min(end),
+                    end: start.max(end),
+                    on_read: kind.contains('r'),
+                    on_write: kind.contains('w'),
+                };
+                self.watchpoints.push(watch);
+                return Ok(watch.to_string());
+            },
+            _ => {}
+        }
+
+        if let Some((op_str, op)) = CompareOp::ALL.iter().find(|(s, _)| input.contains(s)) {
+            let n = input.find(op_str).unwrap();
+            let register = Register::parse(input[..n].trim())
+                .ok_or_else(|| format!("Unknown register '{}'", input[..n].trim()))?;
+            let value = parse_hex(input[n + op_str.len()..].trim())?;
+            let condition = RegisterCondition { register, op: *op, value, was_true: false };
+            let text = condition.to_string();
+            self.conditions.push(condition);
+            return Ok(text);
+        }
+
+        let addr = parse_hex(input)?;
+        self.breakpoints.insert(addr);
+        Ok(format!("{:04X}", addr))
+    }
+
+    // The first condition to turn true since it was last checked, remembering where all of them are now
+    fn condition_hit(&mut self, chip8: &Chip8) -> Option<String> {
+        let mut hit = None;
+        for c in self.conditions.iter_mut() {
+            let now = c.op.eval(c.register.read(chip8), c.value);
+            if now && !c.was_true && hit.is_none() {
+                hit = Some(c.to_string());
+            }
+            c.was_true = now;
+        }
+        hit
+    }
+
+    /**
+     *  Runs one instruction unless paused or a break triggers first.
+     *  Same return value as Chip8::cycle.
+     */
+    pub fn cycle(&mut self, chip8: &mut Chip8) -> Result<bool, Chip8Error> {
+        if self.paused() {
+            return Ok(false);
+        }
+
+        let pc = chip8.pc();
+        let opcode = chip8.opcode_at(pc);
+        if std::mem::replace(&mut self.resuming, false) {
+            self.resumed_at = Some(pc);
+        } else if self.resumed_at != Some(pc) {
+            self.resumed_at = None;
+        }
+
+        if self.resumed_at.is_none() {
+            if self.breakpoints.contains(&pc) {
+                self.break_with(BreakReason::Breakpoint(pc));
+                return Ok(false);
+            }
+            if let Some(pattern) = self.opcode_breaks.iter().find(|p| p.matches(opcode)) {
+                let reason = BreakReason::Opcode { pattern: pattern.text.clone(), opcode };
+                self.break_with(reason);
+                return Ok(false);
+            }
+            // Only looked at once nothing else broke, a condition turning true on the same
+            // instruction as a breakpoint still breaks when execution carries on
+            if let Some(c) = self.condition_hit(chip8) {
+                self.break_with(BreakReason::Condition(c));
+                return Ok(false);
+            }
+        }
+
+        let result = chip8.cycle();
+        if let Err(e) = &result {
+            self.break_with(BreakReason::Fault(e.clone()));
+            return result;
+        }
+
+        let access = chip8.last_access();
+        for watch in self.watchpoints.iter() {
+            let read = watch.on_read && access.read.is_some_and(|r| r.overlaps(watch.start, watch.end));
+            let write = watch.on_write && access.write.is_some_and(|r| r.overlaps(watch.start, watch.end));
+            if read || write {
+                let reason = BreakReason::Watchpoint { watch: *watch, write, pc };
+                self.break_with(reason);
+                return result;
+            }
+        }
+
+        let stop = match self.mode {
+            RunMode::StepInto => true,
+            RunMode::StepOver { return_pc, sp } => chip8.pc() == return_pc && chip8.sp() == sp,
+            RunMode::StepOut { sp } => chip8.sp() < sp,
+            RunMode::Running | RunMode::Paused => false,
+        };
+        if stop {
+            self.break_with(BreakReason::Step);
+        }
+
+        result
+    }
+
+    fn run(&mut self, mode: RunMode) {
+        self.mode = mode;
+        self.resuming = true;
+    }
+
+    fn break_with(&mut self, reason: BreakReason) {
+        self.mode = RunMode::Paused;
+        self.last_break = Some(reason);
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let trimmed = s.trim();
+    let digits = trimmed.trim_start_matches("0x").trim_start_matches("0X").trim_start_matches('#');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not a hex number", trimmed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // LD V0, 5; LD V1, 1; LD V2, 2; JP 206
+    const ROM: &[u8] = &[0x60, 0x05, 0x61, 0x01, 0x62, 0x02, 0x12, 0x06];
+
+    fn run(debugger: &mut Debugger, chip8: &mut Chip8, instructions: usize) {
+        for _ in 0..instructions {
+            debugger.cycle(chip8).unwrap();
+        }
+    }
+
+    #[test]
+    fn condition_breaks_once_when_it_turns_true() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(ROM).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.add_from_str("V0 == 5").unwrap();
+
+        run(&mut debugger, &mut chip8, 2);
+        assert!(debugger.paused());
+        assert_eq!(chip8.pc(), 0x202);
+        assert!(matches!(debugger.last_break(), Some(BreakReason::Condition(c)) if c == "V0 == 5"));
+
+        debugger.resume();
+        run(&mut debugger, &mut chip8, 4);
+        assert!(!debugger.paused());
+    }
+
+    #[test]
+    fn condition_turning_true_on_a_breakpoint_breaks_after_it() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(ROM).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.add_from_str("V0 == 5").unwrap();
+        debugger.toggle_breakpoint(0x202);
+
+        run(&mut debugger, &mut chip8, 2);
+        assert!(matches!(debugger.last_break(), Some(BreakReason::Breakpoint(0x202))));
+
+        debugger.resume();
+        run(&mut debugger, &mut chip8, 2);
+        assert!(debugger.paused());
+        assert_eq!(chip8.pc(), 0x204);
+        assert!(matches!(debugger.last_break(), Some(BreakReason::Condition(_))));
+    }
+
+    #[test]
+    fn resuming_on_a_waiting_instruction_runs_until_it_finishes() {
+        // LD V0, K; JP 200
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(&[0xF0, 0x0A, 0x12, 0x00]).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.toggle_breakpoint(0x200);
+        debugger.add_from_str("op Fx0A").unwrap();
+
+        run(&mut debugger, &mut chip8, 1);
+        assert!(matches!(debugger.last_break(), Some(BreakReason::Breakpoint(0x200))));
+
+        // Fx0A stays on 0x200 until a key goes down and up again
+        debugger.toggle_pause();
+        run(&mut debugger, &mut chip8, 10);
+        assert!(!debugger.paused());
+        assert_eq!(chip8.pc(), 0x200);
+        chip8.key_down(5);
+        run(&mut debugger, &mut chip8, 2);
+        chip8.key_up(5);
+        run(&mut debugger, &mut chip8, 1);
+        assert!(!debugger.paused());
+        assert_eq!(chip8.pc(), 0x202);
+
+        // Coming back round it breaks again
+        run(&mut debugger, &mut chip8, 2);
+        assert!(debugger.paused());
+        assert!(matches!(debugger.last_break(), Some(BreakReason::Breakpoint(0x200))));
+    }
+}
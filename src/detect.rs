@@ -0,0 +1,320 @@
+use crate::chip8::{Opcode, PROGRAM_START};
+use crate::disasm::Analysis;
+use crate::quirks::Quirks;
+use std::fmt;
+
+/**
+ *  The interpreters detect tells apart. SCHIP and XO-CHIP programs give themselves away with
+ *  instructions CHIP-8 doesn't have, the original CHIP-8 only by leaning on the VIP's quirks.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Platform {
+    Chip8,
+    // CHIP-8 calling its own 1802 machine code with 0nnn, needs the COSMAC VIP emulation
+    HybridVip,
+    Schip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::HybridVip => "hybrid CHIP-8 with 1802 machine code",
+            Platform::Schip => "SCHIP",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 | Platform::HybridVip => Quirks::vip(),
+            Platform::Schip => Quirks::schip(),
+            Platform::XoChip => Quirks::xochip(),
+        }
+    }
+
+    // Whether evidence for other is also evidence for self, XO-CHIP being SCHIP with the VIP's quirks
+    fn agrees_with(self, other: Platform) -> bool {
+        self == other || match self {
+            Platform::XoChip => other != Platform::HybridVip,
+            Platform::HybridVip => other == Platform::Chip8,
+            Platform::Chip8 | Platform::Schip => false,
+        }
+    }
+}
+
+/**
+ *  One kind of thing found in the code, with everywhere it was found.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evidence {
+    pub platform: Platform,
+    pub what: &'static str,
+    // How much it says on its own, 3 for instructions only that platform has
+    pub weight: u32,
+    pub addrs: Vec<u16>,
+}
+
+// e.g. "SCHIP: 00FE/00FF switch resolution (0x202, 0x2A4)"
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const SHOWN: usize = 4;
+        let addrs: Vec<String> = self.addrs.iter().take(SHOWN).map(|addr| format!("{:#05X}", addr)).collect();
+        write!(f, "{}: {} ({}", self.platform.name(), self.what, addrs.join(", "))?;
+        if self.addrs.len() > SHOWN {
+            write!(f, " and {} more", self.addrs.len() - SHOWN)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/**
+ *  What the code of a ROM suggests it was written for. platform is None when nothing in it gave the
+ *  platform away, i.e. plain CHIP-8 that runs the same with any quirks as far as can be told.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    pub platform: Option<Platform>,
+    // 0 - 100, how much of the evidence agrees with platform and how much of it there is
+    pub confidence: u32,
+    pub evidence: Vec<Evidence>,
+}
+
+impl Detection {
+    pub fn quirks(&self) -> Option<Quirks> {
+        self.platform.map(Platform::quirks)
+    }
+}
+
+// e.g. "SCHIP, 88% confident"
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.platform {
+            Some(platform) => write!(f, "{}, {}% confident", platform.name(), self.confidence),
+            None => write!(f, "nothing platform specific found"),
+        }
+    }
+}
+
+/**
+ *  Guesses the platform a ROM loaded at PROGRAM_START was written for from the code a trace of it
+ *  reaches, data is never looked at. Any XO-CHIP instruction makes it XO-CHIP, then any SCHIP one SCHIP,
+ *  then 0nnn calls a hybrid VIP program, and code relying on the VIP's shift or Fx55/Fx65 behaviour
+ *  CHIP-8 with the VIP's quirks.
+ */
+pub fn detect(rom: &[u8]) -> Detection {
+    let analysis = Analysis::trace(rom);
+    let word = |addr: u16| {
+        let offset = addr.wrapping_sub(PROGRAM_START) as usize;
+        let byte = |n: usize| rom.get(n).copied().unwrap_or(0) as u16;
+        Opcode(byte(offset) << 8 | byte(offset + 1))
+    };
+    let end = PROGRAM_START as usize + rom.len();
+    let code = (PROGRAM_START as usize..end.min(0x10000)).map(|addr| addr as u16).filter(|addr| analysis.is_code(*addr));
+
+    let mut found = Found::default();
+    // For Fx55/Fx65 relying on I: where the current run of straight line code started, where I was
+    // last set in it and the last Fx55/Fx65 since then
+    let (mut block, mut i_set, mut memory_op): (u16, Option<u16>, Option<(u16, u8)>) = (PROGRAM_START, None, None);
+    let mut expected = PROGRAM_START;
+
+    for addr in code {
+        let op = word(addr);
+        let next = addr.wrapping_add(op.size());
+        if addr != expected {
+            block = addr;
+            i_set = None;
+            memory_op = None;
+        }
+        expected = next;
+        let (x, y, n, kk) = (op.x(), op.y(), op.n(), op.kk());
+
+        match op.0 & 0xF000 {
+            0x0000 => match op.0 {
+                0x00FE | 0x00FF => found.add(Platform::Schip, "00FE/00FF switch resolution", 3, addr),
+                0x00C0..=0x00CF | 0x00FB | 0x00FC => found.add(Platform::Schip, "00Cn/00FB/00FC scroll", 2, addr),
+                0x00FD => found.add(Platform::Schip, "00FD exit", 1, addr),
+                0x00D0..=0x00DF => found.add(Platform::XoChip, "00Dn scroll up", 3, addr),
+                _ => {},
+            },
+            0x1000 => {
+                // Jumping back over an Fx55/Fx65 with nothing setting I in between walks it through memory
+                let target = op.addr();
+                if let Some((memory_op, _)) = memory_op {
+                    if target >= block && target <= memory_op && i_set.is_none_or(|i_set| target > i_set) {
+                        found.add(Platform::Chip8, "Fx55/Fx65 loop relying on I moving on", 2, memory_op);
+                    }
+                }
+            },
+            0x5000 if n == 2 || n == 3 => found.add(Platform::XoChip, "5xy2/5xy3 save or load a register range", 3, addr),
+            0x8000 if (n == 0x6 || n == 0xE) && x != y => found.add(Platform::Chip8, "8xy6/8xyE shifting Vy into Vx", 1, addr),
+            0xA000 => i_set = Some(addr),
+            0xD000 if n == 0 => found.add(Platform::Schip, "Dxy0 16x16 sprite", 1, addr),
+            0xF000 => match kk {
+                0x00 => {
+                    found.add(Platform::XoChip, "F000 nnnn long I", 3, addr);
+                    i_set = Some(addr);
+                },
+                0x01 | 0x02 | 0x3A => found.add(Platform::XoChip, "Fn01/F002/Fx3A plane select or audio", 3, addr),
+                0x30 => {
+                    found.add(Platform::Schip, "Fx30 big font", 2, addr);
+                    i_set = Some(addr);
+                },
+                0x75 | 0x85 => found.add(Platform::Schip, "Fx75/Fx85 flag registers", 2, addr),
+                0x1E | 0x29 => i_set = Some(addr),
+                0x55 | 0x65 => {
+                    // Two saves or two loads in a row only make sense if the second carries on from the first.
+                    // A load after a save reads back what was saved, relying on I being left alone if anything
+                    if let Some((previous, _)) = memory_op.filter(|(_, previous_kk)| *previous_kk == kk) {
+                        found.add(Platform::Chip8, "Fx55/Fx65 following another without setting I", 2, previous);
+                    }
+                    memory_op = Some((addr, kk));
+                },
+                _ => {},
+            },
+            _ => {},
+        }
+        // Fx55/Fx65 before I was set again don't carry on into the ones after
+        if i_set == Some(addr) {
+            memory_op = None;
+        }
+
+        // The trace stops at 0nnn, so machine code calls are found just past the code running into them
+        let ends_block = matches!(op.0 & 0xF000, 0x1000 | 0xB000) || op.0 == 0x00EE || op.0 == 0x00FD;
+        let call = word(next);
+        if !ends_block && (next as usize) < end && !analysis.is_code(next) && is_machine_code_call(call) {
+            found.add(Platform::HybridVip, "0nnn machine code call", 3, next);
+        }
+    }
+    found.detection()
+}
+
+fn is_machine_code_call(op: Opcode) -> bool {
+    // 0000 is zero padding, the rest of 00xx are SCHIP/XO-CHIP instructions
+    op.0 & 0xF000 == 0 && op.0 > 0x00FF
+}
+
+#[derive(Default)]
+struct Found {
+    evidence: Vec<Evidence>,
+}
+
+impl Found {
+    fn add(&mut self, platform: Platform, what: &'static str, weight: u32, addr: u16) {
+        match self.evidence.iter_mut().find(|e| e.what == what) {
+            Some(e) if !e.addrs.contains(&addr) => e.addrs.push(addr),
+            Some(_) => {},
+            None => self.evidence.push(Evidence { platform, what, weight, addrs: vec![addr] }),
+        }
+    }
+
+    fn detection(self) -> Detection {
+        let seen = |platform| self.evidence.iter().any(|e| e.platform == platform);
+        let platform = [Platform::XoChip, Platform::Schip, Platform::HybridVip, Platform::Chip8].iter()
+            .copied()
+            .find(|platform| seen(*platform));
+        // Each kind of evidence counts once, a ROM full of 00FF is no surer than one with a single 00FF
+        let confidence = platform.map_or(0, |platform| {
+            let (support, against) = self.evidence.iter().fold((0, 0), |(support, against), e| {
+                if platform.agrees_with(e.platform) { (support + e.weight, against) } else { (support, against + e.weight) }
+            });
+            100 * support / (support + against + 1)
+        });
+        Detection { platform, confidence, evidence: self.evidence }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octo;
+    use std::path::Path;
+
+    fn test_rom(name: &str) -> Vec<u8> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms").join(name);
+        octo::compile_file(path.to_str().unwrap()).unwrap_or_else(|e| panic!("{}", e)).rom
+    }
+
+    // The one kind of evidence in rom, as (platform, what, addrs), with the confidence
+    fn only(rom: &[u8]) -> ((Platform, &'static str, Vec<u16>), u32) {
+        let detection = detect(rom);
+        assert_eq!(detection.evidence.len(), 1, "{:?}", detection.evidence);
+        let e = &detection.evidence[0];
+        assert_eq!(detection.platform, Some(e.platform));
+        ((e.platform, e.what, e.addrs.clone()), detection.confidence)
+    }
+
+    // The SCHIP test gives itself away, the others are plain CHIP-8 that runs the same with any quirks
+    #[test]
+    fn detects_the_test_roms() {
+        for &(rom, platform) in &[("font.8o", None), ("flags.8o", None), ("hires.8o", Some(Platform::Schip)), ("keywait.8o", None)] {
+            let detection = detect(&test_rom(rom));
+            assert_eq!(detection.platform, platform, "{} {:?}", rom, detection.evidence);
+        }
+    }
+
+    #[test]
+    fn long_i_is_xochip() {
+        // F000 1234, then a jump to itself
+        assert_eq!(only(&[0xF0, 0x00, 0x12, 0x34, 0x12, 0x04]),
+            ((Platform::XoChip, "F000 nnnn long I", vec![0x200]), 75));
+    }
+
+    #[test]
+    fn register_ranges_are_xochip() {
+        assert_eq!(only(&[0x60, 0x01, 0x50, 0x12, 0x12, 0x04]),
+            ((Platform::XoChip, "5xy2/5xy3 save or load a register range", vec![0x202]), 75));
+    }
+
+    #[test]
+    fn machine_code_calls_are_hybrid() {
+        // The trace stops at the 0123, the call is found from the 6001 running into it
+        assert_eq!(only(&[0x60, 0x01, 0x01, 0x23]),
+            ((Platform::HybridVip, "0nnn machine code call", vec![0x202]), 75));
+        // Padding isn't a call
+        assert_eq!(detect(&[0x60, 0x01, 0x00, 0x00]).platform, None);
+    }
+
+    #[test]
+    fn memory_loops_relying_on_i_moving_on() {
+        // I = 300, then a loop saving V0 over and over without setting I again
+        assert_eq!(only(&[0xA3, 0x00, 0xF0, 0x55, 0x12, 0x02]),
+            ((Platform::Chip8, "Fx55/Fx65 loop relying on I moving on", vec![0x202]), 66));
+        // Going back round through the I = 300 saves to the same place every time
+        assert_eq!(detect(&[0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00]).platform, None);
+        // As does setting I inside the loop
+        assert_eq!(detect(&[0xF0, 0x55, 0xA3, 0x00, 0x12, 0x00]).platform, None);
+    }
+
+    #[test]
+    fn memory_ops_carrying_on_from_each_other() {
+        assert_eq!(only(&[0xA3, 0x00, 0xF1, 0x65, 0xF1, 0x65, 0x12, 0x06]),
+            ((Platform::Chip8, "Fx55/Fx65 following another without setting I", vec![0x202]), 66));
+        // Loading back what was just saved works either way
+        assert_eq!(detect(&[0xA3, 0x00, 0xF1, 0x55, 0xF1, 0x65, 0x12, 0x06]).platform, None);
+        assert_eq!(detect(&[0xA3, 0x00, 0xF1, 0x55, 0xA3, 0x00, 0xF1, 0x55, 0x12, 0x08]).platform, None);
+    }
+
+    #[test]
+    fn shifts_of_another_register() {
+        assert_eq!(only(&[0x81, 0x26, 0x81, 0x2E, 0x12, 0x04]),
+            ((Platform::Chip8, "8xy6/8xyE shifting Vy into Vx", vec![0x200, 0x202]), 50));
+        // 8xx6 shifts the same whichever register it takes
+        assert_eq!(detect(&[0x81, 0x16, 0x12, 0x02]).platform, None);
+    }
+
+    #[test]
+    fn confidence() {
+        // Each kind of evidence counts once however often it's seen
+        assert_eq!(only(&[0x00, 0xFF, 0x00, 0xFF, 0x12, 0x04]).1, 75);
+        // A VIP style shift in a SCHIP program counts against it
+        let detection = detect(&[0x00, 0xFF, 0x81, 0x26, 0x12, 0x04]);
+        assert_eq!((detection.platform, detection.confidence), (Some(Platform::Schip), 60));
+        // XO-CHIP has all the SCHIP instructions, so those count for it
+        let detection = detect(&[0xF0, 0x00, 0x12, 0x34, 0x00, 0xFF, 0x12, 0x06]);
+        assert_eq!((detection.platform, detection.confidence), (Some(Platform::XoChip), 85));
+        assert_eq!(detection.to_string(), "XO-CHIP, 85% confident");
+        assert_eq!(detect(&[0x12, 0x00]).confidence, 0);
+    }
+}
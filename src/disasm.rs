@@ -0,0 +1,619 @@
+use crate::chip8::{Opcode, PROGRAM_START};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Syntax {
+    // Mnemonics from Cowgod's Chip-8 technical reference (LD, SE, DRW...), as read by the asm module
+    Cowgod,
+    // Octo assembly (v1 := 0x05, if v1 == 3 then, sprite v0 v1 5...)
+    Octo,
+}
+
+impl std::str::FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cowgod" => Ok(Syntax::Cowgod),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(format!("Unknown disassembly syntax '{}'. One of: cowgod, octo", s))
+        }
+    }
+}
+
+// Ordered so a call target beats a jump target beats a data reference when one address is all three
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Jump,
+    Sub,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Sprite {
+    rows: u16,
+    // 16x16 SCHIP sprites (Dxy0) take two bytes per row
+    wide: bool,
+}
+
+impl Sprite {
+    fn len(&self) -> u16 {
+        if self.wide { self.rows * 2 } else { self.rows }
+    }
+}
+
+/**
+ *  What a trace from the program entry point found out about a ROM: which bytes start
+ *  instructions, which addresses deserve a label and where sprites are drawn from.
+ *  Anything never reached is treated as data.
+ */
+pub struct Analysis {
+    origin: u16,
+    len: usize,
+    code: BTreeSet<u16>,
+    labels: BTreeMap<u16, LabelKind>,
+    sprites: BTreeMap<u16, Sprite>,
+}
+
+impl Analysis {
+
+    /**
+     *  Follows every path from PROGRAM_START. Bnnn jump tables can't be resolved statically,
+     *  so their base is traced as if it were a plain jump, which covers the usual table of 1nnn's.
+     */
+    pub fn trace(rom: &[u8]) -> Self {
+        let mut a = Analysis::empty(rom);
+        let mut pending: Vec<(u16, Option<u16>)> = vec![(PROGRAM_START, None)];
+
+        while let Some((start, mut i)) = pending.pop() {
+            let mut addr = start;
+            while a.contains(addr) && !a.code.contains(&addr) {
+                let op = Opcode(word_at(rom, addr - PROGRAM_START));
+                let long = word_at(rom, addr - PROGRAM_START + 2);
+                // 0nnn machine code calls aren't run by this interpreter, in practice they're zero padding
+                if mnemonic(op, long, Syntax::Cowgod, &|_| None).is_none() || is_sys(op) {
+                    break;
+                }
+                a.code.insert(addr);
+                let next = addr.wrapping_add(op.size());
+
+                match op.0 & 0xF000 {
+                    0x0000 if op.0 == 0x00EE || op.0 == 0x00FD => break,
+                    0x1000 => {
+                        a.label(op.addr(), LabelKind::Jump);
+                        pending.push((op.addr(), i));
+                        break;
+                    },
+                    0x2000 => {
+                        a.label(op.addr(), LabelKind::Sub);
+                        // Subroutines often draw whatever the caller pointed I at
+                        pending.push((op.addr(), i));
+                    },
+                    0xB000 => {
+                        a.label(op.addr(), LabelKind::Jump);
+                        pending.push((op.addr(), i));
+                        break;
+                    },
+                    0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xE000 if is_skip(op) => {
+                        let skipped = Opcode(word_at(rom, next.wrapping_sub(PROGRAM_START)));
+                        pending.push((next.wrapping_add(skipped.size()), i));
+                    },
+                    0xA000 => {
+                        i = Some(op.addr());
+                        a.label(op.addr(), LabelKind::Data);
+                    },
+                    0xD000 => {
+                        if let Some(i) = i {
+                            let sprite = match op.n() {
+                                0 => Sprite { rows: 16, wide: true },
+                                n => Sprite { rows: n as u16, wide: false },
+                            };
+                            a.sprites.entry(i).or_insert(sprite);
+                        }
+                    },
+                    0xF000 => match op.kk() {
+                        0x00 => {
+                            i = Some(long);
+                            a.label(long, LabelKind::Data);
+                        },
+                        // Anything else that moves I leaves us not knowing where it points
+                        0x1E | 0x29 | 0x30 | 0x55 | 0x65 => i = None,
+                        _ => {}
+                    },
+                    _ => {}
+                }
+                addr = next;
+            }
+        }
+
+        // A sprite that runs into code is more likely a guess gone wrong than real data
+        let code = &a.code;
+        a.sprites.retain(|addr, s| code.range(*addr..addr.saturating_add(s.len())).next().is_none());
+        a
+    }
+
+    // No tracing, every word is taken to be an instruction
+    pub fn linear(rom: &[u8]) -> Self {
+        let mut a = Analysis::empty(rom);
+        let mut addr = PROGRAM_START;
+        while a.contains(addr) {
+            a.code.insert(addr);
+            addr = addr.wrapping_add(2);
+            if addr < PROGRAM_START {
+                break;
+            }
+        }
+        a
+    }
+
+    fn empty(rom: &[u8]) -> Self {
+        Analysis {
+            origin: PROGRAM_START,
+            len: rom.len(),
+            code: BTreeSet::new(),
+            labels: BTreeMap::new(),
+            sprites: BTreeMap::new(),
+        }
+    }
+
+    fn contains(&self, addr: u16) -> bool {
+        addr >= self.origin && ((addr - self.origin) as usize) < self.len
+    }
+
+    fn label(&mut self, addr: u16, kind: LabelKind) {
+        if self.contains(addr) {
+            let entry = self.labels.entry(addr).or_insert(kind);
+            *entry = (*entry).max(kind);
+        }
+    }
+
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.code.contains(&addr)
+    }
+
+    pub fn label_name(&self, addr: u16) -> Option<String> {
+        self.labels.get(&addr).map(|kind| {
+            let prefix = match kind {
+                LabelKind::Sub => "sub",
+                LabelKind::Jump => "label",
+                LabelKind::Data if self.sprites.contains_key(&addr) => "sprite",
+                LabelKind::Data => "data",
+            };
+            format!("{}_{:03X}", prefix, addr)
+        })
+    }
+}
+
+fn word_at(rom: &[u8], offset: u16) -> u16 {
+    let byte = |n: usize| rom.get(n).copied().unwrap_or(0) as u16;
+    byte(offset as usize) << 8 | byte(offset as usize + 1)
+}
+
+fn is_sys(op: Opcode) -> bool {
+    op.0 & 0xF000 == 0 && !matches!(op.0, 0x00E0 | 0x00EE | 0x00C0..=0x00DF | 0x00FB..=0x00FF)
+}
+
+fn is_skip(op: Opcode) -> bool {
+    match op.0 & 0xF000 {
+        0x3000 | 0x4000 => true,
+        0x5000 | 0x9000 => op.n() == 0,
+        0xE000 => op.kk() == 0x9E || op.kk() == 0xA1,
+        _ => false
+    }
+}
+
+/**
+ *  One instruction as text, None if op isn't a valid instruction.
+ *  long is the word following op, only used by F000 nnnn. label turns an address into a name if it has one.
+ */
+pub fn mnemonic(op: Opcode, long: u16, syntax: Syntax, label: &dyn Fn(u16) -> Option<String>) -> Option<String> {
+    match syntax {
+        Syntax::Cowgod => cowgod(op, long, label),
+        Syntax::Octo => octo(op, long, label),
+    }
+}
+
+fn cowgod(op: Opcode, long: u16, label: &dyn Fn(u16) -> Option<String>) -> Option<String> {
+    let (x, y, n, kk, nnn) = (op.x(), op.y(), op.n(), op.kk(), op.addr());
+    let addr = |a: u16| label(a).unwrap_or_else(|| format!("#{:03X}", a));
+    let s = match op.0 & 0xF000 {
+        0x0000 => match op.0 {
+            0x00E0 => "CLS".to_string(),
+            0x00EE => "RET".to_string(),
+            0x00C0..=0x00CF => format!("SCD {}", n),
+            0x00D0..=0x00DF => format!("SCU {}", n),
+            0x00FB => "SCR".to_string(),
+            0x00FC => "SCL".to_string(),
+            0x00FD => "EXIT".to_string(),
+            0x00FE => "LOW".to_string(),
+            0x00FF => "HIGH".to_string(),
+            _ => format!("SYS #{:03X}", nnn),
+        },
+        0x1000 => format!("JP {}", addr(nnn)),
+        0x2000 => format!("CALL {}", addr(nnn)),
+        0x3000 => format!("SE V{:X}, #{:02X}", x, kk),
+        0x4000 => format!("SNE V{:X}, #{:02X}", x, kk),
+        0x5000 => match n {
+            0x0 => format!("SE V{:X}, V{:X}", x, y),
+            0x2 => format!("LD [I], V{:X}-V{:X}", x, y),
+            0x3 => format!("LD V{:X}-V{:X}, [I]", x, y),
+            _ => return None,
+        },
+        0x6000 => format!("LD V{:X}, #{:02X}", x, kk),
+        0x7000 => format!("ADD V{:X}, #{:02X}", x, kk),
+        0x8000 => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => return None,
+        },
+        0x9000 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA000 => format!("LD I, {}", addr(nnn)),
+        0xB000 => format!("JP V0, {}", addr(nnn)),
+        0xC000 => format!("RND V{:X}, #{:02X}", x, kk),
+        0xD000 => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE000 => match kk {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => return None,
+        },
+        0xF000 => match kk {
+            0x00 if x == 0 => format!("LD I, long {}", label(long).unwrap_or_else(|| format!("#{:04X}", long))),
+            0x01 => format!("PLANE {}", x),
+            0x02 if x == 0 => "AUDIO".to_string(),
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x30 => format!("LD HF, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x3A => format!("PITCH V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            0x75 => format!("LD R, V{:X}", x),
+            0x85 => format!("LD V{:X}, R", x),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(s)
+}
+
+fn octo(op: Opcode, long: u16, label: &dyn Fn(u16) -> Option<String>) -> Option<String> {
+    let (x, y, n, kk, nnn) = (op.x(), op.y(), op.n(), op.kk(), op.addr());
+    let addr = |a: u16| label(a).unwrap_or_else(|| format!("0x{:03X}", a));
+    let s = match op.0 & 0xF000 {
+        0x0000 => match op.0 {
+            0x00E0 => "clear".to_string(),
+            0x00EE => "return".to_string(),
+            0x00C0..=0x00CF => format!("scroll-down {}", n),
+            0x00D0..=0x00DF => format!("scroll-up {}", n),
+            0x00FB => "scroll-right".to_string(),
+            0x00FC => "scroll-left".to_string(),
+            0x00FD => "exit".to_string(),
+            0x00FE => "lores".to_string(),
+            0x00FF => "hires".to_string(),
+            // Octo has no machine code call, emit the raw word
+            _ => format!("0x{:02X} 0x{:02X}", op.0 >> 8, kk),
+        },
+        0x1000 => format!("jump {}", addr(nnn)),
+        0x2000 => match label(nnn) {
+            Some(name) => name,
+            None => format!(":call 0x{:03X}", nnn),
+        },
+        // Octo conditions say when the next instruction runs, so they read inverted from the skip
+        0x3000 => format!("if v{:x} != 0x{:02X} then", x, kk),
+        0x4000 => format!("if v{:x} == 0x{:02X} then", x, kk),
+        0x5000 => match n {
+            0x0 => format!("if v{:x} != v{:x} then", x, y),
+            0x2 => format!("save v{:x} - v{:x}", x, y),
+            0x3 => format!("load v{:x} - v{:x}", x, y),
+            _ => return None,
+        },
+        0x6000 => format!("v{:x} := 0x{:02X}", x, kk),
+        0x7000 => format!("v{:x} += 0x{:02X}", x, kk),
+        0x8000 => {
+            let operator = match n {
+                0x0 => ":=",
+                0x1 => "|=",
+                0x2 => "&=",
+                0x3 => "^=",
+                0x4 => "+=",
+                0x5 => "-=",
+                0x6 => ">>=",
+                0x7 => "=-",
+                0xE => "<<=",
+                _ => return None,
+            };
+            format!("v{:x} {} v{:x}", x, operator, y)
+        },
+        0x9000 if n == 0 => format!("if v{:x} == v{:x} then", x, y),
+        0xA000 => format!("i := {}", addr(nnn)),
+        0xB000 => format!("jump0 {}", addr(nnn)),
+        0xC000 => format!("v{:x} := random 0x{:02X}", x, kk),
+        0xD000 => format!("sprite v{:x} v{:x} {}", x, y, n),
+        0xE000 => match kk {
+            0x9E => format!("if v{:x} -key then", x),
+            0xA1 => format!("if v{:x} key then", x),
+            _ => return None,
+        },
+        0xF000 => match kk {
+            0x00 if x == 0 => format!("i := long {}", label(long).unwrap_or_else(|| format!("0x{:04X}", long))),
+            0x01 => format!("plane {}", x),
+            0x02 if x == 0 => "audio".to_string(),
+            0x07 => format!("v{:x} := delay", x),
+            0x0A => format!("v{:x} := key", x),
+            0x15 => format!("delay := v{:x}", x),
+            0x18 => format!("buzzer := v{:x}", x),
+            0x1E => format!("i += v{:x}", x),
+            0x29 => format!("i := hex v{:x}", x),
+            0x30 => format!("i := bighex v{:x}", x),
+            0x33 => format!("bcd v{:x}", x),
+            0x3A => format!("pitch := v{:x}", x),
+            0x55 => format!("save v{:x}", x),
+            0x65 => format!("load v{:x}", x),
+            0x75 => format!("saveflags v{:x}", x),
+            0x85 => format!("loadflags v{:x}", x),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(s)
+}
+
+// "..####.." for one row of sprite data, most significant bit on the left
+fn bit_art(bits: u16, width: u32) -> String {
+    (0..width).rev().map(|b| if bits & (1 << b) != 0 { '#' } else { '.' }).collect()
+}
+
+/**
+ *  Full listing of a ROM loaded at PROGRAM_START.
+ *  Code is shown one instruction per line with labels on their own line, sprites the trace
+ *  found are drawn as bit-art and any other data is dumped 8 bytes to a line.
+ *  Either syntax is valid source that assembles back to the same ROM, Cowgod through the asm module.
+ */
+pub fn listing(rom: &[u8], analysis: &Analysis, syntax: Syntax) -> String {
+    let mut out = String::new();
+    let label = |a: u16| analysis.label_name(a);
+    let end = PROGRAM_START as usize + rom.len();
+    let mut addr = PROGRAM_START as usize;
+
+    if syntax == Syntax::Octo {
+        out.push_str(": main\n");
+    }
+
+    while addr < end {
+        if let Some(name) = analysis.label_name(addr as u16) {
+            match syntax {
+                Syntax::Cowgod => writeln!(out, "{}:", name),
+                Syntax::Octo => writeln!(out, ": {}", name),
+            }.unwrap();
+        }
+        let offset = (addr - PROGRAM_START as usize) as u16;
+
+        if analysis.is_code(addr as u16) {
+            let op = Opcode(word_at(rom, offset));
+            let long = word_at(rom, offset + 2);
+            let mut len = (op.size() as usize).min(end - addr);
+            // A jump into the middle of this instruction needs its label, so show the bytes before it as data
+            if let Some(split) = (addr + 1..addr + len).find(|a| analysis.labels.contains_key(&(*a as u16))) {
+                len = split - addr;
+            }
+            let text = if len == op.size() as usize { mnemonic(op, long, syntax, &label) } else { None };
+            match text {
+                Some(text) => match syntax {
+                    Syntax::Cowgod => {
+                        let bytes: Vec<String> = rom[offset as usize..offset as usize + len].iter()
+                            .map(|b| format!("{:02X}", b)).collect();
+                        writeln!(out, "    {:<28} ; {:03X}  {}", text, addr, bytes.join(""))
+                    },
+                    Syntax::Octo => writeln!(out, "\t{:<28} # {:03X}", text, addr),
+                }.unwrap(),
+                None => write_data(&mut out, syntax, addr, &rom[offset as usize..offset as usize + len]),
+            }
+            addr += len;
+            continue;
+        }
+
+        // Data runs until the next instruction or label, whichever comes first
+        let mut run_end = addr + 1;
+        while run_end < end && !analysis.is_code(run_end as u16) && !analysis.labels.contains_key(&(run_end as u16)) {
+            run_end += 1;
+        }
+
+        if let Some(sprite) = analysis.sprites.get(&(addr as u16)) {
+            let sprite_end = (addr + sprite.len() as usize).min(run_end);
+            let step = if sprite.wide { 2 } else { 1 };
+            let mut row = addr;
+            while row + step <= sprite_end {
+                let bytes = &rom[row - PROGRAM_START as usize..row - PROGRAM_START as usize + step];
+                let bits = bytes.iter().fold(0u16, |acc, b| acc << 8 | *b as u16);
+                let art = bit_art(bits, step as u32 * 8);
+                match syntax {
+                    Syntax::Cowgod => {
+                        let directive = if sprite.wide { "DW" } else { "DB" };
+                        writeln!(out, "    {} %{:<24} ; {:03X}", directive, art, row)
+                    },
+                    Syntax::Octo => {
+                        let hex: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+                        writeln!(out, "\t{:<28} # {}", hex.join(" "), art)
+                    },
+                }.unwrap();
+                row += step;
+            }
+            addr = row;
+            continue;
+        }
+
+        while addr < run_end {
+            let chunk_end = (addr + 8).min(run_end);
+            let start = addr - PROGRAM_START as usize;
+            write_data(&mut out, syntax, addr, &rom[start..start + (chunk_end - addr)]);
+            addr = chunk_end;
+        }
+    }
+    out
+}
+
+fn write_data(out: &mut String, syntax: Syntax, addr: usize, bytes: &[u8]) {
+    match syntax {
+        Syntax::Cowgod => {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("#{:02X}", b)).collect();
+            writeln!(out, "    {:<28} ; {:03X}", format!("DB {}", hex.join(", ")), addr)
+        },
+        Syntax::Octo => {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+            writeln!(out, "\t{:<28} # {:03X}", hex.join(" "), addr)
+        },
+    }.unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: &[u8] = &[
+        0xA2, 0x0C,                     // LD I, 20C
+        0xD0, 0x15,                     // DRW V0, V1, 5
+        0x22, 0x0A,                     // CALL 20A
+        0x30, 0x00,                     // SE V0, 0
+        0x12, 0x06,                     // JP 206
+        0x00, 0xEE,                     // RET
+        0xF0, 0x90, 0x90, 0x90, 0xF0,   // a 0, drawn by the DRW
+        0x12, 0x34, 0x56,               // never reached
+    ];
+
+    #[test]
+    fn trace_splits_code_from_data() {
+        let analysis = Analysis::trace(ROM);
+        let code: Vec<u16> = (0x200..0x214).filter(|a| analysis.is_code(*a)).collect();
+        assert_eq!(code, [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A]);
+        assert_eq!(analysis.label_name(0x20A).as_deref(), Some("sub_20A"));
+        assert_eq!(analysis.label_name(0x206).as_deref(), Some("label_206"));
+        assert_eq!(analysis.label_name(0x20C).as_deref(), Some("sprite_20C"));
+        assert_eq!(analysis.label_name(0x211), None);
+    }
+
+    #[test]
+    fn trace_follows_both_sides_of_a_skip() {
+        let rom = &[
+            0x30, 0x00,                 // SE V0, 0
+            0xF0, 0x00, 0x02, 0x40,     // LD I, long 240, skipped as a whole
+            0x00, 0xFD,                 // EXIT
+            0xFF, 0xFF,
+        ];
+        let analysis = Analysis::trace(rom);
+        assert!(analysis.is_code(0x202) && analysis.is_code(0x206));
+        assert!(!analysis.is_code(0x204) && !analysis.is_code(0x208));
+    }
+
+    #[test]
+    fn trace_takes_bnnn_as_a_jump_to_its_table() {
+        let rom = &[
+            0xB2, 0x04,     // JP V0, 204
+            0xFF, 0xFF,     // data after the jump
+            0x12, 0x08,     // table: JP 208
+            0x12, 0x08,     // JP 208, only reachable through the table
+            0x12, 0x08,     // JP 208
+        ];
+        let analysis = Analysis::trace(rom);
+        assert!(analysis.is_code(0x204) && analysis.is_code(0x208));
+        assert!(!analysis.is_code(0x202) && !analysis.is_code(0x206));
+        assert_eq!(analysis.label_name(0x204).as_deref(), Some("label_204"));
+    }
+
+    #[test]
+    fn trace_stops_at_machine_code_calls() {
+        // CLS; SYS 123; CLS
+        let analysis = Analysis::trace(&[0x00, 0xE0, 0x01, 0x23, 0x00, 0xE0]);
+        assert!(analysis.is_code(0x200));
+        assert!(!analysis.is_code(0x202) && !analysis.is_code(0x204));
+    }
+
+    #[test]
+    fn linear_takes_every_word_as_code() {
+        let analysis = Analysis::linear(ROM);
+        assert!((0x200..0x214).step_by(2).all(|a| analysis.is_code(a)));
+        assert_eq!(analysis.label_name(0x206), None);
+    }
+
+    #[test]
+    fn cowgod_listing() {
+        let expected = "    LD I, sprite_20C             ; 200  A20C
+    DRW V0, V1, 5                ; 202  D015
+    CALL sub_20A                 ; 204  220A
+label_206:
+    SE V0, #00                   ; 206  3000
+    JP label_206                 ; 208  1206
+sub_20A:
+    RET                          ; 20A  00EE
+sprite_20C:
+    DB %####....                 ; 20C
+    DB %#..#....                 ; 20D
+    DB %#..#....                 ; 20E
+    DB %#..#....                 ; 20F
+    DB %####....                 ; 210
+    DB #12, #34, #56             ; 211
+";
+        assert_eq!(listing(ROM, &Analysis::trace(ROM), Syntax::Cowgod), expected);
+    }
+
+    #[test]
+    fn octo_listing() {
+        let expected = "\
+: main
+\ti := sprite_20C              # 200
+\tsprite v0 v1 5               # 202
+\tsub_20A                      # 204
+: label_206
+\tif v0 != 0x00 then           # 206
+\tjump label_206               # 208
+: sub_20A
+\treturn                       # 20A
+: sprite_20C
+\t0xF0                         # ####....
+\t0x90                         # #..#....
+\t0x90                         # #..#....
+\t0x90                         # #..#....
+\t0xF0                         # ####....
+\t0x12 0x34 0x56               # 211
+";
+        assert_eq!(listing(ROM, &Analysis::trace(ROM), Syntax::Octo), expected);
+    }
+
+    #[test]
+    fn every_instruction_in_both_syntaxes() {
+        let none = |_| None;
+        let both = |op: u16| (mnemonic(Opcode(op), 0x1234, Syntax::Cowgod, &none), mnemonic(Opcode(op), 0x1234, Syntax::Octo, &none));
+        let cases: &[(u16, &str, &str)] = &[
+            (0x00C3, "SCD 3", "scroll-down 3"),
+            (0x00D3, "SCU 3", "scroll-up 3"),
+            (0x0123, "SYS #123", "0x01 0x23"),
+            (0x4A12, "SNE VA, #12", "if va == 0x12 then"),
+            (0x5122, "LD [I], V1-V2", "save v1 - v2"),
+            (0x8AB7, "SUBN VA, VB", "va =- vb"),
+            (0x8ABE, "SHL VA, VB", "va <<= vb"),
+            (0xB300, "JP V0, #300", "jump0 0x300"),
+            (0xE3A1, "SKNP V3", "if v3 key then"),
+            (0xF000, "LD I, long #1234", "i := long 0x1234"),
+            (0xF201, "PLANE 2", "plane 2"),
+            (0xF530, "LD HF, V5", "i := bighex v5"),
+            (0xF785, "LD V7, R", "loadflags v7"),
+        ];
+        for (op, cowgod, octo) in cases {
+            assert_eq!(both(*op), (Some(cowgod.to_string()), Some(octo.to_string())), "{:04X}", op);
+        }
+        for op in &[0x5121, 0x8AB8, 0x9AB1, 0xE3A2, 0xF0FF] {
+            assert_eq!(both(*op), (None, None), "{:04X}", op);
+        }
+    }
+}
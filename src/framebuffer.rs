@@ -0,0 +1,111 @@
+// Big enough for SCHIP/XO-CHIP hires, lowres only uses the start of each plane
+pub const MAX_WIDTH: usize = 128;
+pub const MAX_HEIGHT: usize = 64;
+const PLANE_BYTES: usize = MAX_WIDTH * MAX_HEIGHT / 8;
+const PLANE_COUNT: usize = 2;
+
+/**
+ *  The CHIP-8 display, bit packed and fixed size so it needs no allocation.
+ *  Each XO-CHIP bitplane is stored on its own, rows of width / 8 bytes with the leftmost pixel in
+ *  the top bit, the same layout as sprites, ready to be copied out to a monochrome display.
+ *  Cells are read and written as their plane bits: 0 off, 1 plane 1 (a lit CHIP-8 pixel), 2 plane 2, 3 both.
+ */
+#[derive(Clone)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    planes: [[u8; PLANE_BYTES]; PLANE_COUNT],
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width <= MAX_WIDTH && height <= MAX_HEIGHT && width.is_multiple_of(8), "Unsupported display size {}x{}", width, height);
+        Framebuffer {
+            width,
+            height,
+            planes: [[0; PLANE_BYTES]; PLANE_COUNT],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Packed rows of plane n (0 or 1), width / 8 bytes each
+    pub fn plane(&self, n: usize) -> &[u8] {
+        &self.planes[n][..self.width / 8 * self.height]
+    }
+
+    fn index(&self, x: usize, y: usize) -> (usize, u8) {
+        (y * self.width / 8 + x / 8, 0x80 >> (x % 8))
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        let (byte, bit) = self.index(x, y);
+        let mut cell = 0;
+        for (n, plane) in self.planes.iter().enumerate() {
+            if plane[byte] & bit != 0 {
+                cell |= 1 << n;
+            }
+        }
+        cell
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, cell: u8) {
+        let (byte, bit) = self.index(x, y);
+        for (n, plane) in self.planes.iter_mut().enumerate() {
+            if cell & 1 << n != 0 {
+                plane[byte] |= bit;
+            } else {
+                plane[byte] &= !bit;
+            }
+        }
+    }
+
+    // Every cell row by row, top left first
+    pub fn cells(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| self.get(x, y)))
+    }
+
+    // Turns off every pixel in the given planes (bit 0 plane 1, bit 1 plane 2)
+    pub fn clear(&mut self, planes: u8) {
+        for (n, plane) in self.planes.iter_mut().enumerate() {
+            if planes & 1 << n != 0 {
+                plane.iter_mut().for_each(|b| *b = 0);
+            }
+        }
+    }
+
+    /**
+     *  Moves the pixels of the given planes by (dx, dy). Pixels shifted off the edge are lost and
+     *  the ones uncovered on the other side are turned off.
+     */
+    pub fn scroll(&mut self, planes: u8, dx: isize, dy: isize) {
+        let (w, h) = (self.width as isize, self.height as isize);
+        let row_bytes = self.width / 8;
+        for (n, plane) in self.planes.iter_mut().enumerate() {
+            if planes & 1 << n == 0 {
+                continue;
+            }
+            let mut scrolled = [0u8; PLANE_BYTES];
+            for y in 0..h {
+                for x in 0..w {
+                    let (sx, sy) = (x - dx, y - dy);
+                    if sx < 0 || sx >= w || sy < 0 || sy >= h {
+                        continue;
+                    }
+                    let (sx, sy) = (sx as usize, sy as usize);
+                    if plane[sy * row_bytes + sx / 8] & 0x80 >> (sx % 8) != 0 {
+                        let (x, y) = (x as usize, y as usize);
+                        scrolled[y * row_bytes + x / 8] |= 0x80 >> (x % 8);
+                    }
+                }
+            }
+            *plane = scrolled;
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::chip8::{Chip8, Chip8Error};
+use crate::timing::{Timing, VipClock};
+use sha1::{Digest, Sha1};
+use std::fmt::{self, Write};
+
+// Same rates the windowed loop runs at
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u64 = 200;
+pub const DEFAULT_MAX_FRAMES: u64 = 600;
+const FRAMES_PER_SECOND: u64 = 60;
+// How long a scripted key stays down when no duration is given
+const DEFAULT_PRESS_FRAMES: u64 = 4;
+
+/**
+ *  Key held down from frame to frame + frames. Written on the command line as
+ *  FRAME:KEY or FRAME:KEY:FRAMES, KEY being the hex keypad digit
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyPress {
+    pub frame: u64,
+    pub key: u8,
+    pub frames: u64,
+}
+
+impl std::str::FromStr for KeyPress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Key presses are written FRAME:KEY[:FRAMES] with KEY a hex digit, got '{}'", s);
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(err());
+        }
+        let frame = parts[0].parse().map_err(|_| err())?;
+        let key = u8::from_str_radix(parts[1], 16).ok().filter(|k| *k < 16).ok_or_else(err)?;
+        let frames = match parts.get(2) {
+            Some(n) => n.parse().map_err(|_| err())?,
+            None => DEFAULT_PRESS_FRAMES,
+        };
+        Ok(KeyPress { frame, key, frames })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HeadlessOptions {
+    // Stop after this many 60hz frames / instructions, whichever comes first
+    pub max_frames: Option<u64>,
+    pub max_instructions: Option<u64>,
+    // Only used with Timing::Fixed
+    pub instructions_per_second: u64,
+    pub timing: Timing,
+    pub keys: Vec<KeyPress>,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        HeadlessOptions {
+            max_frames: Some(DEFAULT_MAX_FRAMES),
+            max_instructions: None,
+            instructions_per_second: DEFAULT_INSTRUCTIONS_PER_SECOND,
+            timing: Timing::default(),
+            keys: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Halt {
+    FrameLimit,
+    InstructionLimit,
+    // 1nnn jumping to itself, how programs (and most test ROMs) stop once they're done
+    SelfJump(u16),
+    Exited,
+    Error(Chip8Error),
+}
+
+impl fmt::Display for Halt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Halt::FrameLimit => write!(f, "frame limit reached"),
+            Halt::InstructionLimit => write!(f, "instruction limit reached"),
+            Halt::SelfJump(addr) => write!(f, "self jump at {:#05X}", addr),
+            Halt::Exited => write!(f, "program exited (00FD)"),
+            Halt::Error(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub halt: Halt,
+    pub frames: u64,
+    pub instructions: u64,
+}
+
+/**
+ *  Runs chip8 with no window or audio until one of the halt conditions hits.
+ *  Frames are simulated rather than timed, so the same ROM and options always give the same result.
+ */
+pub fn run(chip8: &mut Chip8, options: &HeadlessOptions) -> Report {
+    let mut frames = 0;
+    let mut instructions = 0;
+    let ips = options.instructions_per_second.max(1);
+    let mut clock = VipClock::new();
+
+    // Runs one instruction, or says why we have to stop
+    let step = |chip8: &mut Chip8, instructions: &mut u64| -> Option<Halt> {
+        if options.max_instructions.is_some_and(|max| *instructions >= max) {
+            return Some(Halt::InstructionLimit);
+        }
+        let pc = chip8.pc();
+        if pc <= 0x0FFF && chip8.opcode_at(pc) == 0x1000 | pc {
+            return Some(Halt::SelfJump(pc));
+        }
+        if let Err(e) = chip8.cycle() {
+            return Some(Halt::Error(e));
+        }
+        *instructions += 1;
+        if chip8.exited() {
+            return Some(Halt::Exited);
+        }
+        None
+    };
+
+    let halt = 'frames: loop {
+        if options.max_frames.is_some_and(|max| frames >= max) {
+            break Halt::FrameLimit;
+        }
+
+        let keyboard = chip8.keyboard_mut();
+        for (key, down) in keyboard.iter_mut().enumerate() {
+            *down = options.keys.iter().any(|p| {
+                p.key as usize == key && frames >= p.frame && frames < p.frame + p.frames
+            });
+        }
+
+        match options.timing {
+            Timing::Fixed => {
+                // Spread instructions evenly so e.g. 200/s runs 3, 3, 4, 3, 3, 4...
+                let budget = (frames + 1) * ips / FRAMES_PER_SECOND - frames * ips / FRAMES_PER_SECOND;
+                for _ in 0..budget {
+                    if let Some(halt) = step(chip8, &mut instructions) {
+                        break 'frames halt;
+                    }
+                }
+            },
+            Timing::Vip => {
+                clock.start_frame();
+                while clock.admit(chip8) {
+                    if let Some(halt) = step(chip8, &mut instructions) {
+                        break 'frames halt;
+                    }
+                }
+            },
+        }
+
+        chip8.cycle_timers();
+        frames += 1;
+    };
+
+    Report { halt, frames, instructions }
+}
+
+// SHA-1 over the display size and every framebuffer cell, so equal hashes mean identical screens
+pub fn framebuffer_hash(chip8: &Chip8) -> String {
+    let gfx = chip8.gfx();
+    let mut hasher = Sha1::new();
+    hasher.update((gfx.width() as u16).to_le_bytes());
+    hasher.update((gfx.height() as u16).to_le_bytes());
+    hasher.update(gfx.cells().collect::<Vec<u8>>());
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// One character per pixel: '.' off, '#' plane 1, '+' plane 2, '%' both
+pub fn framebuffer_text(chip8: &Chip8) -> String {
+    let gfx = chip8.gfx();
+    let mut out = String::with_capacity((gfx.width() + 1) * gfx.height());
+    for y in 0..gfx.height() {
+        out.extend((0..gfx.width()).map(|x| match gfx.get(x, y) & 0b11 {
+            0 => '.',
+            1 => '#',
+            2 => '+',
+            _ => '%',
+        }));
+        out.push('\n');
+    }
+    out
+}
+
+// Everything a regression script wants to compare: why we stopped, registers, and the screen
+pub fn dump(chip8: &Chip8, report: &Report) -> String {
+    let mut out = String::new();
+    writeln!(out, "halt: {} after {} frames, {} instructions", report.halt, report.frames, report.instructions).unwrap();
+    let v: Vec<String> = chip8.v().iter().enumerate().map(|(n, v)| format!("V{:X}={:02X}", n, v)).collect();
+    writeln!(out, "{}", v.join(" ")).unwrap();
+    writeln!(out, "I={:04X} PC={:04X} SP={:X} DT={:02X} ST={:02X}",
+        chip8.i(), chip8.pc(), chip8.sp(), chip8.delay_timer(), chip8.sound_timer()).unwrap();
+    // sp is incremented before storing, so slot 0 is never used
+    let stack: Vec<String> = chip8.stack()[1..=chip8.sp() as usize].iter().map(|a| format!("{:04X}", a)).collect();
+    writeln!(out, "stack: [{}]", stack.join(", ")).unwrap();
+    if let Some(wait) = chip8.key_wait() {
+        writeln!(out, "halted: {}", wait).unwrap();
+    }
+    let (w, h) = chip8.display_size();
+    writeln!(out, "framebuffer {}x{} sha1 {}", w, h, framebuffer_hash(chip8)).unwrap();
+    out.push_str(&framebuffer_text(chip8));
+    out
+}
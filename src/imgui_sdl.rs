@@ -0,0 +1,118 @@
+use imgui::{Context, Key};
+use sdl2::event::Event;
+use sdl2::keyboard::{Mod, Scancode};
+use sdl2::mouse::MouseState;
+use sdl2::video::Window;
+use std::time::Instant;
+
+/**
+ *  Feeds SDL window, mouse and keyboard state into imgui.
+ *  handle_event() for every polled event, prepare_frame() right before imgui::Context::frame()
+ */
+pub struct ImguiSdl {
+    last_frame: Instant,
+    // Wheel and button presses that happened and ended between two frames still need to reach imgui
+    mouse_pressed: [bool; 5],
+}
+
+impl ImguiSdl {
+
+    pub fn new(imgui: &mut Context) -> Self {
+        let io = imgui.io_mut();
+        io.key_map[Key::Tab as usize] = Scancode::Tab as u32;
+        io.key_map[Key::LeftArrow as usize] = Scancode::Left as u32;
+        io.key_map[Key::RightArrow as usize] = Scancode::Right as u32;
+        io.key_map[Key::UpArrow as usize] = Scancode::Up as u32;
+        io.key_map[Key::DownArrow as usize] = Scancode::Down as u32;
+        io.key_map[Key::PageUp as usize] = Scancode::PageUp as u32;
+        io.key_map[Key::PageDown as usize] = Scancode::PageDown as u32;
+        io.key_map[Key::Home as usize] = Scancode::Home as u32;
+        io.key_map[Key::End as usize] = Scancode::End as u32;
+        io.key_map[Key::Insert as usize] = Scancode::Insert as u32;
+        io.key_map[Key::Delete as usize] = Scancode::Delete as u32;
+        io.key_map[Key::Backspace as usize] = Scancode::Backspace as u32;
+        io.key_map[Key::Space as usize] = Scancode::Space as u32;
+        io.key_map[Key::Enter as usize] = Scancode::Return as u32;
+        io.key_map[Key::Escape as usize] = Scancode::Escape as u32;
+        io.key_map[Key::KeyPadEnter as usize] = Scancode::KpEnter as u32;
+        io.key_map[Key::A as usize] = Scancode::A as u32;
+        io.key_map[Key::C as usize] = Scancode::C as u32;
+        io.key_map[Key::V as usize] = Scancode::V as u32;
+        io.key_map[Key::X as usize] = Scancode::X as u32;
+        io.key_map[Key::Y as usize] = Scancode::Y as u32;
+        io.key_map[Key::Z as usize] = Scancode::Z as u32;
+
+        imgui.set_ini_filename(None);
+        imgui.set_platform_name(Some(imgui::ImString::from(String::from("rusty-chip8-emu sdl2"))));
+
+        ImguiSdl {
+            last_frame: Instant::now(),
+            mouse_pressed: [false; 5],
+        }
+    }
+
+    pub fn handle_event(&mut self, imgui: &mut Context, event: &Event) {
+        use sdl2::mouse::MouseButton;
+        let io = imgui.io_mut();
+        match event {
+            Event::MouseWheel { y, .. } => {
+                io.mouse_wheel += *y as f32;
+            },
+            Event::MouseButtonDown { mouse_btn, .. } => {
+                let index = match mouse_btn {
+                    MouseButton::Left => 0,
+                    MouseButton::Right => 1,
+                    MouseButton::Middle => 2,
+                    MouseButton::X1 => 3,
+                    MouseButton::X2 => 4,
+                    _ => return
+                };
+                self.mouse_pressed[index] = true;
+            },
+            Event::TextInput { text, .. } => {
+                for c in text.chars() {
+                    io.add_input_character(c);
+                }
+            },
+            Event::KeyDown { scancode: Some(sc), keymod, .. } => {
+                io.keys_down[*sc as usize] = true;
+                set_modifiers(io, *keymod);
+            },
+            Event::KeyUp { scancode: Some(sc), keymod, .. } => {
+                io.keys_down[*sc as usize] = false;
+                set_modifiers(io, *keymod);
+            },
+            _ => {}
+        }
+    }
+
+    pub fn prepare_frame(&mut self, imgui: &mut Context, window: &Window, mouse: &MouseState) {
+        let io = imgui.io_mut();
+
+        let (win_w, win_h) = window.size();
+        let (draw_w, draw_h) = window.drawable_size();
+        io.display_size = [win_w as f32, win_h as f32];
+        if win_w > 0 && win_h > 0 {
+            io.display_framebuffer_scale = [draw_w as f32 / win_w as f32, draw_h as f32 / win_h as f32];
+        }
+
+        self.last_frame = io.update_delta_time(self.last_frame);
+
+        io.mouse_down = [
+            self.mouse_pressed[0] || mouse.left(),
+            self.mouse_pressed[1] || mouse.right(),
+            self.mouse_pressed[2] || mouse.middle(),
+            self.mouse_pressed[3] || mouse.x1(),
+            self.mouse_pressed[4] || mouse.x2(),
+        ];
+        self.mouse_pressed = [false; 5];
+        io.mouse_pos = [mouse.x() as f32, mouse.y() as f32];
+    }
+}
+
+fn set_modifiers(io: &mut imgui::Io, keymod: Mod) {
+    io.key_ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+    io.key_alt = keymod.intersects(Mod::LALTMOD | Mod::RALTMOD);
+    io.key_shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+    io.key_super = keymod.intersects(Mod::LGUIMOD | Mod::RGUIMOD);
+}
@@ -0,0 +1,310 @@
+use rusty_chip8_emu::database::RomInfo;
+use sdl2::keyboard::Scancode;
+
+// Physical keys one hex keypad key can be bound to
+pub const MAX_BINDINGS: usize = 4;
+
+// The hex keypad row by row as laid out on the COSMAC VIP, the order keys are asked for when rebinding
+pub const KEYPAD_ORDER: [u8; 16] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+type Bindings = [Option<Scancode>; MAX_BINDINGS];
+
+/**
+ *  Which physical keys press which hex keypad keys. Keys are bound by scancode, the position of the key
+ *  rather than the letter on it, so the default block below is in the same place on AZERTY or Dvorak.
+ *  A keymap can leave keys unset, layers of them are stacked with or() like Settings.
+ */
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Option<Bindings>; 16],
+}
+
+impl Keymap {
+
+    /*
+        The default, the left hand block of a QWERTY keyboard
+
+        Keyboard       Chip8 Keypad
+        +-+-+-+-+      +-+-+-+-+
+        |1|2|3|4|      |1|2|3|C|
+        +-+-+-+-+      +-+-+-+-+
+        |Q|W|E|R|      |4|5|6|D|
+        +-+-+-+-+  =>  +-+-+-+-+
+        |A|S|D|F|      |7|8|9|E|
+        +-+-+-+-+      +-+-+-+-+
+        |Z|X|C|V|      |A|0|B|F|
+        +-+-+-+-+      +-+-+-+-+
+    */
+    pub fn qwerty() -> Keymap {
+        const BLOCK: [Scancode; 16] = [
+            Scancode::Num1, Scancode::Num2, Scancode::Num3, Scancode::Num4,
+            Scancode::Q, Scancode::W, Scancode::E, Scancode::R,
+            Scancode::A, Scancode::S, Scancode::D, Scancode::F,
+            Scancode::Z, Scancode::X, Scancode::C, Scancode::V,
+        ];
+        let mut keymap = Keymap::default();
+        for (key, scancode) in KEYPAD_ORDER.iter().zip(BLOCK.iter()) {
+            keymap.keys[*key as usize] = Some([Some(*scancode), None, None, None]);
+        }
+        keymap
+    }
+
+    // This keymap, with keys it leaves unset taken from fallback
+    pub fn or(&self, fallback: &Keymap) -> Keymap {
+        let mut keymap = *self;
+        for (key, fallback) in keymap.keys.iter_mut().zip(fallback.keys.iter()) {
+            *key = key.or(*fallback);
+        }
+        keymap
+    }
+
+    pub fn keypad_key(&self, scancode: Scancode) -> Option<u8> {
+        (0..16).find(|key| self.bindings(*key).any(|bound| bound == scancode))
+    }
+
+    pub fn bindings(&self, key: u8) -> impl Iterator<Item = Scancode> + '_ {
+        self.keys[(key & 0xF) as usize].iter().flatten().flatten().copied()
+    }
+
+    // The keys this keymap sets, in hex order
+    pub fn set_keys(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..16).filter(move |key| self.keys[*key as usize].is_some())
+    }
+
+    // Binds key to scancodes, an empty list leaves it with none
+    pub fn set(&mut self, key: u8, scancodes: &[Scancode]) -> Result<(), String> {
+        if scancodes.len() > MAX_BINDINGS {
+            return Err(format!("key {:X} can have at most {} keys bound to it, got {}", key, MAX_BINDINGS, scancodes.len()));
+        }
+        if let Some(reserved) = scancodes.iter().find(|scancode| is_reserved(**scancode)) {
+            return Err(format!("key {:X} can't be bound to {}, the emulator keeps it for itself", key, reserved.name()));
+        }
+        let mut bindings = [None; MAX_BINDINGS];
+        for (slot, scancode) in bindings.iter_mut().zip(scancodes) {
+            *slot = Some(*scancode);
+        }
+        self.keys[(key & 0xF) as usize] = Some(bindings);
+        Ok(())
+    }
+
+    // scancode as the one key bound to key, taking it away from any other key it pressed
+    pub fn bind(&mut self, key: u8, scancode: Scancode) {
+        for bindings in self.keys.iter_mut().flatten() {
+            for bound in bindings.iter_mut() {
+                if *bound == Some(scancode) {
+                    *bound = None;
+                }
+            }
+        }
+        self.keys[(key & 0xF) as usize] = Some([Some(scancode), None, None, None]);
+    }
+}
+
+// By SDL's names for keys (Scancode::name), e.g. "W", "Up", "Left Shift", "Keypad 5"
+pub fn scancode_from_name(name: &str) -> Result<Scancode, String> {
+    Scancode::from_name(name)
+        .ok_or_else(|| format!("Unknown key '{}', keys go by SDL's names like W, 1, Up, Space, Left Shift or Keypad 5", name))
+}
+
+/**
+ *  Keys that can't be bound to the keypad: Escape quits, F11 saves the settings and F12 rebinds the keys,
+ *  so a keymap can never take away the way out of it. Any other hotkey a keymap binds is the program's.
+ */
+pub fn is_reserved(scancode: Scancode) -> bool {
+    matches!(scancode, Scancode::Escape | Scancode::F11 | Scancode::F12)
+}
+
+/**
+ *  Games the ROM database knows can also be played with the arrow keys, space and return,
+ *  for the keypad keys it lists as up, down, left, right, a and b.
+ */
+pub fn game_key(scancode: Scancode, known: &RomInfo) -> Option<u8> {
+    let name = match scancode {
+        Scancode::Up     => "up",
+        Scancode::Down   => "down",
+        Scancode::Left   => "left",
+        Scancode::Right  => "right",
+        Scancode::Space  => "a",
+        Scancode::Return => "b",
+        _ => return None
+    };
+    known.key(name)
+}
+
+/**
+ *  The rebinding screen: asks for a key for each keypad key in turn, in KEYPAD_ORDER.
+ *  Backspace keeps a key's bindings as they are and Escape gives up on the whole thing.
+ */
+pub struct Rebinding {
+    keymap: Keymap,
+    next: usize,
+    // Why the last key pressed wasn't taken
+    pub message: Option<String>,
+}
+
+impl Rebinding {
+
+    pub fn new(keymap: Keymap) -> Self {
+        Rebinding { keymap, next: 0, message: None }
+    }
+
+    // The keypad key being asked for
+    pub fn key(&self) -> u8 {
+        KEYPAD_ORDER[self.next]
+    }
+
+    // The keymap so far, keys not asked for yet still bound as they were
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    // e.g. "Press a key for 5 (W now)"
+    pub fn prompt(&self) -> String {
+        let bound: Vec<&str> = self.keymap.bindings(self.key()).map(Scancode::name).collect();
+        match bound.len() {
+            0 => format!("Press a key for {:X}", self.key()),
+            _ => format!("Press a key for {:X} ({} now)", self.key(), bound.join(", ")),
+        }
+    }
+
+    /**
+     *  Binds the key asked for, returning the finished keymap after the last one. Keys already given to
+     *  a keypad key earlier on are refused rather than taken away from it, ones bound to keypad keys
+     *  still to come are theirs to rebind.
+     */
+    pub fn press(&mut self, scancode: Scancode) -> Option<Keymap> {
+        if is_reserved(scancode) {
+            self.message = Some(format!("{} is taken by the emulator, try another key", scancode.name()));
+            return None;
+        }
+        let asked = &KEYPAD_ORDER[..self.next];
+        if let Some(key) = self.keymap.keypad_key(scancode).filter(|key| asked.contains(key)) {
+            self.message = Some(format!("{} is already bound to {:X}, try another key", scancode.name(), key));
+            return None;
+        }
+        self.keymap.bind(self.key(), scancode);
+        self.skip()
+    }
+
+    // Moves on leaving the key asked for bound as it was
+    pub fn skip(&mut self) -> Option<Keymap> {
+        self.message = None;
+        self.next += 1;
+        if self.next == KEYPAD_ORDER.len() {
+            Some(self.keymap)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(keys: &[(u8, &[Scancode])]) -> Keymap {
+        let mut keymap = Keymap::default();
+        for (key, scancodes) in keys {
+            keymap.set(*key, scancodes).unwrap();
+        }
+        keymap
+    }
+
+    #[test]
+    fn qwerty_is_the_left_hand_block() {
+        let keymap = Keymap::qwerty();
+        assert_eq!(keymap.keypad_key(Scancode::Num1), Some(0x1));
+        assert_eq!(keymap.keypad_key(Scancode::Num4), Some(0xC));
+        assert_eq!(keymap.keypad_key(Scancode::Q), Some(0x4));
+        assert_eq!(keymap.keypad_key(Scancode::X), Some(0x0));
+        assert_eq!(keymap.keypad_key(Scancode::V), Some(0xF));
+        assert_eq!(keymap.keypad_key(Scancode::P), None);
+        assert_eq!(keymap.set_keys().count(), 16);
+    }
+
+    #[test]
+    fn several_keys_press_one_keypad_key() {
+        let keymap = keymap(&[(5, &[Scancode::W, Scancode::Up])]);
+        assert_eq!(keymap.keypad_key(Scancode::W), Some(5));
+        assert_eq!(keymap.keypad_key(Scancode::Up), Some(5));
+        assert_eq!(keymap.bindings(5).collect::<Vec<_>>(), [Scancode::W, Scancode::Up]);
+        assert_eq!(keymap.set_keys().collect::<Vec<_>>(), [5]);
+    }
+
+    #[test]
+    fn set_checks_the_keys() {
+        let mut keymap = Keymap::default();
+        let five = [Scancode::A, Scancode::B, Scancode::C, Scancode::D, Scancode::E];
+        assert_eq!(keymap.set(5, &five), Err("key 5 can have at most 4 keys bound to it, got 5".to_string()));
+        assert_eq!(keymap.set(5, &[Scancode::W, Scancode::Escape]),
+            Err("key 5 can't be bound to Escape, the emulator keeps it for itself".to_string()));
+        assert!(keymap.set(5, &[Scancode::F12]).is_err());
+        assert_eq!(keymap, Keymap::default());
+    }
+
+    #[test]
+    fn or_fills_in_unset_keys() {
+        let keymap = keymap(&[(5, &[Scancode::Space]), (6, &[])]).or(&Keymap::qwerty());
+        assert_eq!(keymap.keypad_key(Scancode::Space), Some(5));
+        assert_eq!(keymap.keypad_key(Scancode::W), None);
+        // Bound to nothing on purpose isn't unset
+        assert_eq!(keymap.bindings(6).count(), 0);
+        assert_eq!(keymap.keypad_key(Scancode::E), None);
+        assert_eq!(keymap.keypad_key(Scancode::Q), Some(4));
+    }
+
+    #[test]
+    fn bind_takes_the_key_from_other_keypad_keys() {
+        let mut keymap = keymap(&[(5, &[Scancode::W, Scancode::Up]), (6, &[Scancode::E])]);
+        keymap.bind(6, Scancode::Up);
+        assert_eq!(keymap.bindings(5).collect::<Vec<_>>(), [Scancode::W]);
+        assert_eq!(keymap.bindings(6).collect::<Vec<_>>(), [Scancode::Up]);
+    }
+
+    #[test]
+    fn rebinding_asks_for_every_key() {
+        let mut screen = Rebinding::new(Keymap::qwerty());
+        assert_eq!(screen.prompt(), "Press a key for 1 (1 now)");
+        let letters = [
+            Scancode::A, Scancode::B, Scancode::C, Scancode::D, Scancode::E, Scancode::F, Scancode::G, Scancode::H,
+            Scancode::I, Scancode::J, Scancode::K, Scancode::L, Scancode::M, Scancode::N, Scancode::O, Scancode::P,
+        ];
+        // P and the other emulator hotkeys can be bound, only Escape, F11 and F12 can't
+        for scancode in &letters[..15] {
+            assert_eq!(screen.press(*scancode), None);
+        }
+        let keymap = screen.press(letters[15]).unwrap();
+        for (key, scancode) in KEYPAD_ORDER.iter().zip(&letters) {
+            assert_eq!(keymap.keypad_key(*scancode), Some(*key));
+        }
+        assert_eq!(keymap.keypad_key(Scancode::Num1), None);
+    }
+
+    #[test]
+    fn rebinding_refuses_reserved_keys() {
+        let mut screen = Rebinding::new(Keymap::qwerty());
+        assert_eq!(screen.press(Scancode::F11), None);
+        assert_eq!(screen.message.as_deref(), Some("F11 is taken by the emulator, try another key"));
+        assert_eq!(screen.key(), 0x1);
+        // The next key clears the message
+        screen.press(Scancode::A);
+        assert_eq!((screen.key(), screen.message.as_deref()), (0x2, None));
+    }
+
+    #[test]
+    fn rebinding_refuses_keys_already_given_out() {
+        let mut screen = Rebinding::new(Keymap::qwerty());
+        screen.press(Scancode::Up);
+        assert_eq!(screen.press(Scancode::Up), None);
+        assert_eq!(screen.message.as_deref(), Some("Up is already bound to 1, try another key"));
+        // Backspace keeps 2's key, so that's given out too
+        screen.skip();
+        assert_eq!(screen.press(Scancode::Num2), None);
+        assert_eq!(screen.message.as_deref(), Some("2 is already bound to 2, try another key"));
+        // Keys belonging to keypad keys still to come can be taken, they get asked for later
+        assert_eq!(screen.press(Scancode::W), None);
+        assert_eq!(screen.message, None);
+        assert_eq!(screen.keymap().keypad_key(Scancode::W), Some(0x3));
+        assert_eq!(screen.keymap().bindings(0x5).count(), 0);
+    }
+}
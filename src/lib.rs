@@ -0,0 +1,42 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+//! The emulator core: CHIP-8 (and SCHIP/XO-CHIP) interpreter, debugger, save states, rewind,
+//! the assembler/disassembler tools and the COSMAC VIP emulation. Nothing in here knows about SDL,
+//! frontends feed keys in with Chip8::key_down/key_up, step it with cycle/step_frame and draw
+//! gfx/render_to_pixels. The rusty-chip8-emu binary (the "sdl" feature) is one such frontend.
+
+pub mod chip8;
+pub mod quirks;
+pub mod framebuffer;
+pub mod rng;
+pub mod cdp1802;
+
+// Everything else needs std, build with --no-default-features for just the no_std core above
+#[cfg(feature = "std")]
+pub mod util;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod savestate;
+#[cfg(feature = "std")]
+pub mod rewind;
+#[cfg(feature = "std")]
+pub mod disasm;
+#[cfg(feature = "std")]
+pub mod asm;
+#[cfg(feature = "std")]
+pub mod octo;
+#[cfg(feature = "std")]
+pub mod headless;
+#[cfg(feature = "std")]
+pub mod sanitizer;
+#[cfg(feature = "std")]
+pub mod timing;
+#[cfg(feature = "std")]
+pub mod vip;
+#[cfg(feature = "std")]
+pub mod database;
+#[cfg(feature = "std")]
+pub mod detect;
+
+pub use chip8::{Chip8, Chip8Error};
+pub use quirks::Quirks;
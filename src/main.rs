@@ -1,122 +1,627 @@
-mod util;
-mod chip8;
+mod audio;
+mod renderer;
+mod imgui_sdl;
+mod overlay;
+mod input;
+mod cli;
+mod config;
 
-extern crate libc;
 extern crate imgui;
 extern crate sdl2;
-extern crate rand;
+extern crate gl;
 
-use chip8::Chip8;
+use rusty_chip8_emu::{disasm, asm, octo, headless, savestate, detect};
+use rusty_chip8_emu::chip8::{Chip8, PROGRAM_START};
+use rusty_chip8_emu::rng::XorShift;
+use config::{Config, Settings};
+use cli::{Command, MachineArgs, RunArgs, HeadlessArgs, DisasmArgs, AsmArgs, InfoArgs};
+use audio::Beeper;
+use input::{Keymap, Rebinding};
+use renderer::Renderer;
+use imgui_sdl::ImguiSdl;
+use overlay::DebugOverlay;
+use rusty_chip8_emu::debugger::Debugger;
+use rusty_chip8_emu::savestate::SaveSlots;
+use rusty_chip8_emu::rewind::Rewind;
+use rusty_chip8_emu::timing::{Timing, VipClock};
+use rusty_chip8_emu::vip::Vip;
+use rusty_chip8_emu::database::{Database, RomInfo};
 
-use util::FrameTimer;
-use sdl2::pixels::{Color, PixelFormatEnum};
+use rusty_chip8_emu::util::FrameTimer;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use std::time::{Instant};
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::audio::AudioDevice;
+use std::path::{Path, PathBuf};
 
-// TODO: Make this into program parameter
-const TARGET_LOGIC_DELTA: f32 = 1.0/200.0;
-const TARGET_DELAY_SOUND_DELTA: f32 = 1.0/60.0;
+pub fn main() {
+    let argv: Vec<String> = std::env::args().skip(1).collect();
+    let result = cli::parse(&argv).and_then(|command| match command {
+        Command::Run(args) => run_command(args),
+        Command::Headless(args) => headless_command(args),
+        Command::Disasm(args) => disasm_command(args),
+        Command::Asm(args) => asm_command(args),
+        Command::Info(args) => info_command(args),
+        Command::Help(usage) => {
+            println!("{}", usage);
+            Ok(())
+        },
+    });
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
 
-pub fn main() -> Result<(), String> {
+// rusty-chip8-emu [run] <program> [options], the windowed emulator
+fn run_command(mut args: RunArgs) -> Result<(), String> {
+    let (rom, source) = read_program(&args.machine.program)?;
+    let rom_hash = savestate::rom_hash(&rom);
+    let mut config = load_config(&args.machine)?;
+    let (known, recommended) = recommend(&args.machine, &rom);
+    let settings = resolve_settings(&args.machine, config.as_ref(), &rom_hash, &recommended);
 
-    // unsafe {
-    //     igGetWindowHeight();
-    // }
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
-    // {
-    //     let gl_attr = video_subsystem.gl_attr();
-    //     gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
-    //     gl_attr.set_context_version(3, 3);
-    // }
+    {
+        let gl_attr = video_subsystem.gl_attr();
+        gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
+        gl_attr.set_context_version(3, 3);
+    }
+
+    let audio_subsystem = sdl_context.audio()?;
+    let mut beeper = Beeper::open(&audio_subsystem, settings.beeper())?;
 
-    let window = video_subsystem.window("Rusty Chip8", 1280, 720)
+    let (width, height) = settings.window_size();
+    let window = video_subsystem.window("Rusty Chip8", width, height)
         .resizable()
         .position_centered()
+        .opengl()
         .build()
         .unwrap();
- 
-    let mut canvas = window.into_canvas()
-        .accelerated()
-        .build()
-        .unwrap();
- 
-    canvas.set_draw_color(Color::BLACK);
+
+    let _gl_context = window.gl_create_context()?;
+    gl::load_with(|s| video_subsystem.gl_get_proc_address(s) as _);
+    // vsync is a nice to have, the timers below keep emulation speed right either way
+    let _ = video_subsystem.gl_set_swap_interval(1);
+
+    let mut imgui = imgui::Context::create();
+    let mut imgui_sdl = ImguiSdl::new(&mut imgui);
+    let mut renderer = Renderer::new(&mut imgui)?;
+    let mut overlay = DebugOverlay::new();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let mut chip8 = Chip8::new();
+    let mut keymap = settings.keymap();
+    if let Some(interpreter) = &args.vip {
+        let vip = load_vip(&args, interpreter, &rom)?;
+        return run_vip(vip, &keymap, &window, &mut event_pump, &mut renderer, &mut beeper);
+    }
+
+    let mut debugger = Debugger::new();
 
-    {
-        let prog = get_program_name()?;
-        if let Err(e) = chip8.load_program(&prog) {
-            return Err(format!("Error loading program at path '{}' :: std::io::Error {}", prog, e))
+    if let Some(program) = source {
+        for (_, addr) in &program.breakpoints {
+            debugger.breakpoints.insert(*addr);
         }
+        overlay.set_source_map(program.source_map);
+    }
+    let mut chip8 = load_machine(&args.machine, &settings, &rom)?;
+    chip8.set_palette(settings.palette());
+    let save_slots = SaveSlots::new(rom_hash);
+    // The disassembly's labels come from tracing the ROM as loaded at 0x200
+    if settings.load_address() == PROGRAM_START {
+        overlay.set_program(&rom);
     }
 
-    let texture_creator = canvas.texture_creator();
-
-    let mut chip8_display = texture_creator.create_texture_streaming(PixelFormatEnum::RGB888, Chip8::DISPLAY_W, Chip8::DISPLAY_H).unwrap();
+    {
+        let (w, h) = chip8.display_size();
+        renderer.update_display(&chip8.render_to_pixels(), w, h);
+    }
 
-    let mut logic_timer = FrameTimer::new(TARGET_LOGIC_DELTA);
-    let mut sound_delay_timer = FrameTimer::new(TARGET_DELAY_SOUND_DELTA);
+    let mut logic_timer = FrameTimer::new(1.0 / settings.speed() as f32);
+    let mut sound_delay_timer = FrameTimer::new(1.0 / settings.timer_hz());
+    // Catch up on at most a tenth of a second, any more behind is a stall (e.g. dragging the window) to skip
+    let max_instructions = settings.speed() / 10 + 1;
+    let max_ticks = (settings.timer_hz() / 10.0) as u32 + 1;
+    let mut rewind = Rewind::new(settings.rewind_seconds(), settings.rewind_interval());
+    let mut rewinding = false;
+    let mut vip_clock = VipClock::new();
+    let mut rebinding: Option<Rebinding> = None;
 
-    // let mut clock = Instant::now();
-    // let mut frame_accumulator: f32 = 0.0;
-    'running: loop {
+    let result = 'running: loop {
         
         for event in event_pump.poll_iter() {
+            imgui_sdl.handle_event(&mut imgui, &event);
+            // Typing into a debugger field shouldn't also press chip8 keys or trigger hotkeys
+            let ui_has_keyboard = overlay.visible && imgui.io().want_capture_keyboard;
+            // The rebinding screen takes every key until it's done
+            if let Some(screen) = rebinding.as_mut() {
+                let done = match event {
+                    Event::Quit {..} => break 'running Ok(()),
+                    Event::KeyDown { scancode: Some(Scancode::Escape), .. } => {
+                        println!("Left the keys as they were");
+                        rebinding = None;
+                        continue;
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::Backspace), repeat: false, .. } => screen.skip(),
+                    Event::KeyDown { scancode: Some(scancode), repeat: false, .. } => screen.press(scancode),
+                    _ => None,
+                };
+                if let Some(rebound) = done {
+                    keymap = rebound;
+                    // Saved with the rest of the session's settings by F11
+                    args.machine.settings.keys = Some(rebound);
+                    rebinding = None;
+                    println!("Rebound the keypad, F11 saves the new keys to the config");
+                }
+                continue;
+            }
             match event {
-                Event::Quit {..} |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                Event::Quit {..} => break 'running Ok(()),
+                Event::KeyDown { .. } | Event::KeyUp { .. } if ui_has_keyboard => continue,
+                // Escape, F11 and F12 go by scancode like the keypad, input::is_reserved keeps them out of it
+                Event::KeyDown { scancode: Some(Scancode::Escape), .. } => {
                     break 'running Ok(());
                 },
+                // F12 asks for a new key for each keypad key in turn
+                Event::KeyDown { scancode: Some(Scancode::F12), repeat: false, .. } => {
+                    // Keys held now would never see their release
+                    for key in 0..16 {
+                        chip8.key_up(key);
+                    }
+                    rebinding = Some(Rebinding::new(keymap));
+                },
+                // F11 writes the command line's settings to the ROM's section of the config, shift + F11 to the defaults
+                Event::KeyDown { scancode: Some(Scancode::F11), keymod, repeat: false, .. } => {
+                    let to_defaults = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    match save_settings(config.as_mut(), &args.machine, &rom_hash, to_defaults) {
+                        Ok(path) => println!("Saved settings to '{}'", path.display()),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                },
+                // Keys bound to the keypad are the program's, even where they land on a hotkey (P is in the block on Dvorak)
+                Event::KeyDown { scancode: Some(key), .. } | Event::KeyUp { scancode: Some(key), .. }
+                    if keypad_key(&keymap, key, known.as_ref()).is_some() => {},
+                Event::KeyDown { keycode: Some(Keycode::M), repeat: false, .. } => {
+                    beeper.lock().toggle_mute();
+                },
+                Event::KeyDown { keycode: Some(Keycode::Backquote), repeat: false, .. } => {
+                    overlay.visible = !overlay.visible;
+                },
+                Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } => {
+                    debugger.toggle_pause();
+                },
+                Event::KeyDown { keycode: Some(Keycode::N), .. } => {
+                    debugger.step_into();
+                },
+                Event::KeyDown { keycode: Some(Keycode::O), .. } => {
+                    debugger.step_over(&chip8);
+                },
+                Event::KeyDown { keycode: Some(Keycode::U), repeat: false, .. } => {
+                    debugger.step_out(&chip8);
+                },
+                Event::KeyDown { keycode: Some(Keycode::B), repeat: false, .. } => {
+                    debugger.toggle_breakpoint(chip8.pc());
+                },
+                Event::KeyDown { keycode: Some(Keycode::Backspace), repeat: false, .. } if rewind.enabled() => {
+                    rewinding = true;
+                },
+                Event::KeyUp { keycode: Some(Keycode::Backspace), .. } => {
+                    rewinding = false;
+                },
+                // F1-F10 load slots 1-10, holding shift saves to them instead
+                Event::KeyDown { keycode: Some(key), keymod, repeat: false, .. } if save_slot_for_key(key).is_some() => {
+                    let slot = save_slot_for_key(key).unwrap();
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        match save_slots.save(slot, &chip8) {
+                            Ok(path) => println!("Saved slot {} to '{}'", slot, path.display()),
+                            Err(e) => eprintln!("{}", e),
+                        }
+                    } else {
+                        match save_slots.load(slot, &mut chip8) {
+                            Ok(_) => {
+                                println!("Loaded slot {}", slot);
+                                // History from before the load would rewind into a different timeline
+                                rewind.clear();
+                                let (w, h) = chip8.display_size();
+                                renderer.update_display(&chip8.render_to_pixels(), w, h);
+                            },
+                            Err(e) => eprintln!("Unable to load slot {} :: {}", slot, e),
+                        }
+                    }
+                },
+                _ => {}
+            }
+            match event {
+                Event::KeyDown { scancode: Some(key), .. } => if let Some(k) = keypad_key(&keymap, key, known.as_ref()) { chip8.key_down(k) },
+                Event::KeyUp { scancode: Some(key), .. } => if let Some(k) = keypad_key(&keymap, key, known.as_ref()) { chip8.key_up(k) },
                 _ => {}
             }
-            chip8.process_input(&event);
         }
-        
-        // frame_accumulator += clock.elapsed().as_secs_f32();
-        // clock = Instant::now(); 
 
-        if logic_timer.frame() {
+        if rewinding {
+            // Step back one snapshot per timer frame, the game runs backwards at the speed it was recorded
             logic_timer.reset();
-            if let Ok(true) = chip8.cycle() {
-                let format = chip8_display.query().format;
-                // update the SDL texture we draw every frame with chip8 gfx buffer            
-                let _ = chip8_display.update(None, chip8.render_to_pixels().as_slice(), format.byte_size_of_pixels(Chip8::DISPLAY_W as usize));
+            if sound_delay_timer.frame() {
+                sound_delay_timer.reset();
+                match rewind.step_back(&mut chip8) {
+                    Ok(_) => {
+                        let (w, h) = chip8.display_size();
+                        renderer.update_display(&chip8.render_to_pixels(), w, h);
+                    },
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        rewind.clear();
+                        rewinding = false;
+                    }
+                }
             }
+            beeper.lock().set_active(false);
+        } else if debugger.paused() || rebinding.is_some() {
+            logic_timer.reset();
+            sound_delay_timer.reset();
+            beeper.lock().set_active(false);
         }
 
-        if sound_delay_timer.frame() {
-            sound_delay_timer.reset();
+        // With VIP timing instructions run a frame's worth at a time below, just before the timers
+        if settings.timing() == Timing::Fixed && rebinding.is_none() {
+            let mut redraw = false;
+            for _ in 0..logic_timer.frames(max_instructions) {
+                match debugger.cycle(&mut chip8) {
+                    Ok(drew) => redraw |= drew,
+                    // The debugger pauses on the fault and the overlay shows the crash screen
+                    Err(e) => eprintln!("{}", e),
+                }
+                if debugger.paused() || chip8.exited() {
+                    break;
+                }
+            }
+            if redraw {
+                let (w, h) = chip8.display_size();
+                // update the texture we draw every frame with chip8 gfx buffer
+                renderer.update_display(&chip8.render_to_pixels(), w, h);
+            }
+            if chip8.exited() {
+                break 'running Ok(());
+            }
+        }
+
+        let ticks = if rewinding || rebinding.is_some() { 0 } else { sound_delay_timer.frames(max_ticks) };
+        for _ in 0..ticks {
+            if settings.timing() == Timing::Vip {
+                vip_clock.start_frame();
+                let mut redraw = false;
+                while vip_clock.admit(&chip8) {
+                    match debugger.cycle(&mut chip8) {
+                        Ok(drew) => redraw |= drew,
+                        Err(e) => eprintln!("{}", e),
+                    }
+                    if debugger.paused() || chip8.exited() {
+                        break;
+                    }
+                }
+                if redraw {
+                    let (w, h) = chip8.display_size();
+                    renderer.update_display(&chip8.render_to_pixels(), w, h);
+                }
+                if chip8.exited() {
+                    break 'running Ok(());
+                }
+            }
             chip8.cycle_timers();
+            rewind.record(&chip8);
+
+            let mut b = beeper.lock();
+            b.set_active(chip8.buzzer_active());
+            b.set_pattern(chip8.audio_pattern(), chip8.audio_playback_rate());
         }
 
-        canvas.clear();
-        // TODO/NOTE :: Change/play with last parameter of copy (dest) for different display sizes
-        canvas.copy(&chip8_display, None, None)?;
-        canvas.present();
+        let window_size = window.drawable_size();
+        renderer.clear(window_size);
+        renderer.draw_display(window_size);
+
+        imgui_sdl.prepare_frame(&mut imgui, &window, &event_pump.mouse_state());
+        let ui = imgui.frame();
+        overlay.draw(&ui, &mut chip8, &mut debugger);
+        if let Some(screen) = &rebinding {
+            overlay::draw_rebinding(&ui, screen);
+        }
+        renderer.render(ui.render());
+
+        window.gl_swap_window();
+    };
+
+    if let Some(sanitizer) = chip8.sanitizer() {
+        eprint!("{}", sanitizer.report());
+    }
+    result
+}
+
+// Octo source is compiled on load, anything else is taken to be a raw ROM
+fn read_program(path: &str) -> Result<(Vec<u8>, Option<octo::Program>), String> {
+    if path.ends_with(".8o") {
+        let mut program = octo::compile_file(path).map_err(|e| e.to_string())?;
+        let rom = std::mem::take(&mut program.rom);
+        Ok((rom, Some(program)))
+    } else {
+        match std::fs::read(path) {
+            Ok(rom) => Ok((rom, None)),
+            Err(e) => Err(format!("Error loading program at path '{}' :: std::io::Error {}", path, e))
+        }
     }
 }
 
-fn get_program_name() -> Result<String, String> {
-    let args: Vec<String> = std::env::args().skip(1).collect();
+// The config file the command line asked for, None with --no-config
+fn load_config(args: &MachineArgs) -> Result<Option<Config>, String> {
+    if args.no_config {
+        return Ok(None);
+    }
+    Config::load(args.config.as_deref()).map(Some)
+}
 
-    if args.len() > 1 {
-        return Err(format!("Multiple arguments not yet implemented. Please only pass the relative path of the chip8 program to be loaded."));
+/**
+ *  What the program should run with going by the ROM database's entry for it (skipped with --no-database),
+ *  or failing that what detect makes of its code. Logged, so it's clear where the quirks and speed came
+ *  from. The entry is returned as well for its key mapping.
+ */
+fn recommend(args: &MachineArgs, rom: &[u8]) -> (Option<RomInfo>, Settings) {
+    let known = if args.no_database { None } else { open_database().lookup(rom) };
+    if let Some(info) = known {
+        eprintln!("Found {} in the ROM database", info);
+        if info.platform.id == "hybridVIP" {
+            eprintln!("It calls its own 1802 machine code, run it with --vip for that to work");
+        }
+        let settings = Settings::known(&info);
+        return (Some(info), settings);
     }
 
-    let name = if args.len() == 1 {
-        String::from(&args[0])
-    } else {  
-        match std::env::current_dir() {
-            // TODO/BUG :: cur_dir is current calling directory, so this doesnt work if we put chippin in PATH.
-            Ok(cur_dir) => cur_dir.into_os_string().into_string().unwrap() + "/test_opcode.ch8",
-            Err(_) => return Err(format!("Error loading default program :: Unable to get current path of executable. Please provide a full path to program to be loaded"))
+    // detect reads the program as loaded at 0x200
+    if args.settings.load_address.is_some_and(|addr| addr != PROGRAM_START) {
+        return (None, Settings::default());
+    }
+    let detection = detect::detect(rom);
+    if let Some(platform) = detection.platform {
+        eprintln!("Not in the ROM database, going by its code it's {}:", detection);
+        for evidence in &detection.evidence {
+            eprintln!("  {}", evidence);
+        }
+        if platform == detect::Platform::HybridVip {
+            eprintln!("Run it with --vip for its machine code to work");
         }
+    }
+    (None, Settings { quirks: detection.quirks(), ..Settings::default() })
+}
+
+// The user's copy of the ROM database, or the built in one if that can't be read
+fn open_database() -> Database {
+    Database::open().unwrap_or_else(|e| {
+        eprintln!("{}, using the built in ROM database instead", e);
+        Database::embedded()
+    })
+}
+
+/**
+ *  Command line first, then the ROM's section of the config file, then the config's defaults, then what
+ *  recommend came up with for anything the user left unset.
+ */
+fn resolve_settings(args: &MachineArgs, config: Option<&Config>, rom: &savestate::RomHash, recommended: &Settings) -> Settings {
+    match config {
+        Some(config) => args.settings.or(&config.rom(rom)).or(config.defaults()).or(recommended),
+        None => args.settings.or(recommended),
+    }
+}
+
+/**
+ *  Writes the command line's settings over the ROM's section of the config file (or the defaults),
+ *  so the next run of it starts the same way without them. Only those, and keys rebound with F12:
+ *  saving everything resolve_settings came up with would pin the database's recommendations and
+ *  the built in defaults into the file, where later changes to either would never reach.
+ */
+fn save_settings(config: Option<&mut Config>, args: &MachineArgs, rom: &savestate::RomHash, to_defaults: bool) -> Result<PathBuf, String> {
+    let config = config.ok_or("Settings aren't saved with --no-config")?;
+    if to_defaults {
+        let settings = args.settings.or(config.defaults());
+        config.set_defaults(settings);
+    } else {
+        let name = Path::new(&args.program).file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+        let settings = args.settings.or(&config.rom(rom));
+        config.set_rom(rom, &name, settings);
+    }
+    config.save()?;
+    Ok(config.path().to_path_buf())
+}
+
+/**
+ *  The Chip8 run and headless start from: seeded, with the program loaded where they were asked to
+ *  and, given --state, restored from a save state of it.
+ */
+fn load_machine(args: &MachineArgs, settings: &Settings, rom: &[u8]) -> Result<Chip8, String> {
+    let mut chip8 = match args.seed {
+        Some(seed) => Chip8::with_rng(settings.quirks(), XorShift::new(seed)),
+        None => Chip8::new(settings.quirks()),
     };
-    Ok(name)
-}
\ No newline at end of file
+    if settings.sanitize() {
+        chip8.enable_sanitizer();
+    }
+    chip8.load_rom_at(settings.load_address(), rom)
+        .map_err(|e| format!("Error loading program at path '{}' :: {}", args.program, e))?;
+    if let Some(state) = &args.state {
+        savestate::load_file(Path::new(state), &savestate::rom_hash(rom), &mut chip8)?;
+    }
+    Ok(chip8)
+}
+
+/**
+ *  rusty-chip8-emu headless <program> [options]
+ *  Runs without a window until a limit or a halt (self jump, 00FD, error), then prints registers and the screen.
+ *  Exits with an error if the program itself failed.
+ */
+fn headless_command(args: HeadlessArgs) -> Result<(), String> {
+    let (rom, _) = read_program(&args.machine.program)?;
+    let config = load_config(&args.machine)?;
+    let (_, recommended) = recommend(&args.machine, &rom);
+    let settings = resolve_settings(&args.machine, config.as_ref(), &savestate::rom_hash(&rom), &recommended);
+    let mut chip8 = load_machine(&args.machine, &settings, &rom)?;
+
+    let options = headless::HeadlessOptions {
+        instructions_per_second: settings.speed() as u64,
+        timing: settings.timing(),
+        ..args.options
+    };
+    let report = headless::run(&mut chip8, &options);
+    print!("{}", headless::dump(&chip8, &report));
+    if let Some(sanitizer) = chip8.sanitizer() {
+        print!("{}", sanitizer.report());
+    }
+    match report.halt {
+        headless::Halt::Error(e) => Err(e.to_string()),
+        _ => Ok(())
+    }
+}
+
+// rusty-chip8-emu disasm <rom> [--syntax cowgod|octo] [--linear]
+fn disasm_command(args: DisasmArgs) -> Result<(), String> {
+    let rom = std::fs::read(&args.program)
+        .map_err(|e| format!("Error loading program at path '{}' :: std::io::Error {}", args.program, e))?;
+    let analysis = if args.linear {
+        disasm::Analysis::linear(&rom)
+    } else {
+        disasm::Analysis::trace(&rom)
+    };
+    print!("{}", disasm::listing(&rom, &analysis, args.syntax));
+    Ok(())
+}
+
+// rusty-chip8-emu asm <source> [-o rom.ch8] [--target chip8|schip|xochip]
+fn asm_command(args: AsmArgs) -> Result<(), String> {
+    let AsmArgs { source, output, target } = args;
+    let output = output.unwrap_or_else(|| Path::new(&source).with_extension("ch8").display().to_string());
+    let rom = asm::assemble_file(&source, target).map_err(|e| e.to_string())?;
+    std::fs::write(&output, &rom).map_err(|e| format!("Unable to write '{}' :: {}", output, e))?;
+    println!("Wrote {} bytes to '{}'", rom.len(), output);
+    Ok(())
+}
+
+// rusty-chip8-emu info <program>, what we can tell about a ROM without running it
+fn info_command(args: InfoArgs) -> Result<(), String> {
+    let (rom, source) = read_program(&args.program)?;
+    let hash = savestate::rom_hash(&rom);
+
+    println!("program: {}", args.program);
+    if let Some(program) = &source {
+        println!("source: Octo, {} lines", program.source_map.line_count());
+    }
+    println!("size: {} bytes", rom.len());
+    // CHIP-8 and SCHIP programs have to fit between 0x200 and the end of the VIP's 4 KB
+    if rom.len() > 0x1000 - PROGRAM_START as usize {
+        println!("  too large for CHIP-8 or SCHIP, only XO-CHIP's 64 KB fits it");
+    }
+    println!("sha1: {}", savestate::hash_to_hex(&hash));
+    match open_database().lookup_hash(&hash) {
+        Some(info) => {
+            println!("title: {}", info.title);
+            if !info.authors.is_empty() {
+                println!("authors: {}", info.authors.join(", "));
+            }
+            if let Some(release) = &info.release {
+                println!("release: {}", release);
+            }
+            println!("platform: {} ({})", info.platform.name, info.platform.id);
+            println!("tickrate: {} instructions a frame", info.tickrate);
+        },
+        None => println!("title: not in the ROM database"),
+    }
+    let detection = detect::detect(&rom);
+    println!("detected: {}", detection);
+    for evidence in &detection.evidence {
+        println!("  {}", evidence);
+    }
+
+    let analysis = disasm::Analysis::trace(&rom);
+    let instructions = (PROGRAM_START as usize..PROGRAM_START as usize + rom.len())
+        .filter(|addr| *addr <= 0xFFFF && analysis.is_code(*addr as u16))
+        .count();
+    println!("code: {} instructions reachable from {:#05X}", instructions, PROGRAM_START);
+
+    let slots = SaveSlots::new(hash);
+    let saved: Vec<String> = (1..=savestate::SLOT_COUNT)
+        .filter(|slot| slots.path(*slot).exists())
+        .map(|slot| slot.to_string())
+        .collect();
+    if saved.is_empty() {
+        println!("save slots: none");
+    } else {
+        println!("save slots: {} in '{}'", saved.join(", "), slots.path(1).parent().unwrap().display());
+    }
+    Ok(())
+}
+
+// The COSMAC VIP for --vip, running the interpreter image with the program loaded after it
+fn load_vip(args: &RunArgs, interpreter: &str, rom: &[u8]) -> Result<Vip, String> {
+    let read = |path: &str| std::fs::read(path).map_err(|e| format!("Error reading '{}' :: {}", path, e));
+    let monitor = args.vip_monitor.as_deref().map(read).transpose()?;
+    let mut vip = Vip::new(&read(interpreter)?, monitor.as_deref())?;
+    vip.load_rom(rom).map_err(|e| format!("Error loading program at path '{}' :: {}", args.machine.program, e))?;
+    Ok(vip)
+}
+
+/**
+ *  Windowed loop for --vip, the program runs on the emulated COSMAC VIP instead of Chip8.
+ *  The debugger, save states and rewind all work on Chip8 so aren't available here.
+ */
+fn run_vip(mut vip: Vip, keymap: &Keymap, window: &sdl2::video::Window, event_pump: &mut sdl2::EventPump,
+    renderer: &mut Renderer, beeper: &mut AudioDevice<Beeper>) -> Result<(), String> {
+
+    // The 1861 sets the pace, --speed and --timer-hz don't apply
+    let mut frame_timer = FrameTimer::new(1.0 / config::DEFAULT_TIMER_HZ);
+    loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit {..} | Event::KeyDown { scancode: Some(Scancode::Escape), .. } => return Ok(()),
+                Event::KeyDown { keycode: Some(Keycode::M), repeat: false, .. } => {
+                    beeper.lock().toggle_mute();
+                },
+                Event::KeyDown { scancode: Some(key), .. } => if let Some(k) = keymap.keypad_key(key) { vip.key_down(k) },
+                Event::KeyUp { scancode: Some(key), .. } => if let Some(k) = keymap.keypad_key(key) { vip.key_up(k) },
+                _ => {}
+            }
+        }
+
+        if frame_timer.frame() {
+            frame_timer.reset();
+            vip.run_frame();
+            let (w, h) = vip.display_size();
+            renderer.update_display(&vip.render_to_pixels(), w, h);
+            beeper.lock().set_active(vip.buzzer_active());
+        }
+
+        let window_size = window.drawable_size();
+        renderer.clear(window_size);
+        renderer.draw_display(window_size);
+        window.gl_swap_window();
+    }
+}
+
+// The hex keypad key for a key on the keyboard, the ROM database's game keys included
+fn keypad_key(keymap: &Keymap, key: Scancode, known: Option<&RomInfo>) -> Option<u8> {
+    keymap.keypad_key(key).or_else(|| known.and_then(|info| input::game_key(key, info)))
+}
+
+fn save_slot_for_key(key: Keycode) -> Option<u8> {
+    let slot = match key {
+        Keycode::F1 => 1,
+        Keycode::F2 => 2,
+        Keycode::F3 => 3,
+        Keycode::F4 => 4,
+        Keycode::F5 => 5,
+        Keycode::F6 => 6,
+        Keycode::F7 => 7,
+        Keycode::F8 => 8,
+        Keycode::F9 => 9,
+        Keycode::F10 => 10,
+        _ => return None
+    };
+    debug_assert!(slot <= savestate::SLOT_COUNT);
+    Some(slot)
+}
@@ -0,0 +1,1166 @@
+use crate::asm::AsmError;
+use crate::chip8::PROGRAM_START;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub source_map: SourceMap,
+    // Addresses of :breakpoint directives, with their names
+    pub breakpoints: Vec<(String, u16)>,
+}
+
+/**
+ *  Maps instruction addresses back to the source line they were compiled from.
+ *  Instructions coming from a macro point at the line in the macro body.
+ */
+pub struct SourceMap {
+    name: String,
+    lines: Vec<String>,
+    addrs: BTreeMap<u16, usize>,
+}
+
+impl SourceMap {
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // 1 based line the instruction at addr came from
+    pub fn line_for(&self, addr: u16) -> Option<usize> {
+        self.addrs.get(&addr).copied()
+    }
+
+    pub fn line_text(&self, line: usize) -> Option<&str> {
+        self.lines.get(line.wrapping_sub(1)).map(String::as_str)
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+}
+
+/**
+ *  Compiler for Octo (.8o) source, https://github.com/JohnEarnest/Octo
+ *
+ *  Covers the language as documented in Octo's manual: labels, :alias, :const, :calc, :byte,
+ *  :org, :next, :unpack, :macro, :assert, :breakpoint, loop/while/again, if/then and
+ *  if/begin/else/end, the < > <= >= pseudo comparisons (which clobber vf) and every SCHIP
+ *  and XO-CHIP instruction. :stringmode and :monitor are not supported.
+ *
+ *  Octo starts running at the label main. When main is the first thing in the source it already
+ *  sits at 0x200, otherwise a jump to main is placed there first.
+ */
+pub fn compile(source: &str, name: &str) -> Result<Program, AsmError> {
+    let mut c = Compiler::new(source);
+    c.run().map_err(|(pos, message)| AsmError { file: name.to_string(), line: pos.line, col: pos.col, message })?;
+    Ok(Program {
+        rom: c.rom,
+        source_map: SourceMap {
+            name: name.to_string(),
+            lines: source.lines().map(str::to_string).collect(),
+            addrs: c.source_lines,
+        },
+        breakpoints: c.breakpoints,
+    })
+}
+
+pub fn compile_file(path: &str) -> Result<Program, AsmError> {
+    let source = std::fs::read_to_string(path).map_err(|e| AsmError {
+        file: path.to_string(), line: 0, col: 0, message: format!("Unable to read '{}' :: {}", path, e)
+    })?;
+    compile(&source, path)
+}
+
+const MAX_MACRO_DEPTH: usize = 64;
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Pos {
+    line: usize,
+    col: usize,
+}
+
+type CompileResult<T> = Result<T, (Pos, String)>;
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    pos: Pos,
+    // Tokens spliced in by a macro, to catch macros that expand forever
+    depth: usize,
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (n, line) in source.lines().enumerate() {
+        let mut chars = line.char_indices().peekable();
+        while let Some((at, c)) = chars.next() {
+            if c == '#' {
+                break;
+            }
+            if c.is_whitespace() {
+                continue;
+            }
+            let mut text = c.to_string();
+            while let Some((_, c)) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                text.push(*c);
+                chars.next();
+            }
+            tokens.push(Token { text, pos: Pos { line: n + 1, col: at + 1 }, depth: 0 });
+        }
+    }
+    tokens
+}
+
+fn number(s: &str) -> Option<i64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let n = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -n } else { n })
+}
+
+fn register(s: &str) -> Option<u8> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(c), None) | (Some('V'), Some(c), None) => c.to_digit(16).map(|d| d as u8),
+        _ => None
+    }
+}
+
+const KEYWORDS: [&str; 52] = [
+    ":", ":alias", ":const", ":calc", ":macro", ":byte", ":org", ":next", ":unpack", ":breakpoint",
+    ":assert", ":call", ":monitor", ":stringmode", ";", "return", "clear", "bcd", "save", "load",
+    "saveflags", "loadflags", "sprite", "jump", "jump0", "native", "exit", "hires", "lores", "scroll-up",
+    "scroll-down", "scroll-left", "scroll-right", "plane", "audio", "if", "then", "begin", "else", "end",
+    "loop", "again", "while", "key", "-key", "hex", "bighex", "random", "delay", "buzzer", "pitch", "long",
+];
+
+// How a value that isn't known yet gets written once its label is defined
+#[derive(Debug, Copy, Clone)]
+enum FixupKind {
+    // Low 12 bits of the instruction at addr
+    Addr12,
+    // Whole 16 bit word at addr, for i := long
+    Word16,
+    // :unpack, nibble in the high bits of the v0 load, address split across both loads
+    Unpack(u8),
+    UnpackLong,
+}
+
+struct Fixup {
+    addr: u16,
+    kind: FixupKind,
+    name: String,
+    pos: Pos,
+}
+
+enum Block {
+    Loop { start: u16, breaks: Vec<u16> },
+    // Jump over the taken branch, patched by else/end
+    If { jump: u16, seen_else: bool },
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+    calls: usize,
+}
+
+// Skip instructions for a condition, one skipping when it's false (if/then) and one skipping when it's true (begin, while)
+struct Condition {
+    setup: Vec<u16>,
+    skip_if_false: u16,
+    skip_if_true: u16,
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>,
+    here: u32,
+    labels: HashMap<String, u16>,
+    consts: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<(Block, Pos)>,
+    // :next label, defined at the second byte of the next instruction
+    next_label: Option<(String, Pos)>,
+    source_lines: BTreeMap<u16, usize>,
+    breakpoints: Vec<(String, u16)>,
+    last_pos: Pos,
+}
+
+impl Compiler {
+
+    fn new(source: &str) -> Self {
+        Compiler {
+            tokens: tokenize(source).into(),
+            rom: Vec::new(),
+            here: PROGRAM_START as u32,
+            labels: HashMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            next_label: None,
+            source_lines: BTreeMap::new(),
+            breakpoints: Vec::new(),
+            last_pos: Pos::default(),
+        }
+    }
+
+    fn run(&mut self) -> CompileResult<()> {
+        let starts_with_main = match (self.tokens.front(), self.tokens.get(1)) {
+            (Some(a), Some(b)) => a.text == ":" && b.text == "main",
+            _ => false,
+        };
+        if !starts_with_main {
+            let pos = self.tokens.front().map_or(Pos { line: 1, col: 1 }, |t| t.pos);
+            self.emit_ref(0x1000, "main", FixupKind::Addr12, pos)?;
+        }
+
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+
+        if let Some((block, pos)) = self.blocks.pop() {
+            let what = match block {
+                Block::Loop { .. } => "loop without a matching again",
+                Block::If { .. } => "begin without a matching end",
+            };
+            return Err((pos, what.to_string()));
+        }
+        if let Some((name, pos)) = self.next_label.take() {
+            return Err((pos, format!(":next {} isn't followed by an instruction", name)));
+        }
+        if !self.labels.contains_key("main") {
+            return Err((Pos { line: 1, col: 1 }, "This program is missing a 'main' label".to_string()));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let value = *self.labels.get(&fixup.name)
+                .ok_or_else(|| (fixup.pos, format!("Undefined name '{}'", fixup.name)))?;
+            let at = (fixup.addr - PROGRAM_START) as usize;
+            match fixup.kind {
+                FixupKind::Addr12 => {
+                    if value > 0xFFF {
+                        return Err((fixup.pos, format!("'{}' at 0x{:X} is out of reach of a 12 bit address, use i := long", fixup.name, value)));
+                    }
+                    self.rom[at] = (self.rom[at] & 0xF0) | (value >> 8) as u8;
+                    self.rom[at + 1] = value as u8;
+                },
+                FixupKind::Word16 => {
+                    self.rom[at] = (value >> 8) as u8;
+                    self.rom[at + 1] = value as u8;
+                },
+                FixupKind::Unpack(nibble) => {
+                    self.rom[at + 1] = nibble << 4 | ((value >> 8) & 0xF) as u8;
+                    self.rom[at + 3] = value as u8;
+                },
+                FixupKind::UnpackLong => {
+                    self.rom[at + 1] = (value >> 8) as u8;
+                    self.rom[at + 3] = value as u8;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // ---- Token stream ----
+
+    fn next(&mut self) -> CompileResult<Token> {
+        match self.tokens.pop_front() {
+            Some(t) => {
+                self.last_pos = t.pos;
+                Ok(t)
+            },
+            None => Err((self.last_pos, "Unexpected end of file".to_string())),
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|t| t.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> CompileResult<()> {
+        let t = self.next()?;
+        if t.text != text {
+            return Err((t.pos, format!("Expected '{}', got '{}'", text, t.text)));
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> CompileResult<Token> {
+        let t = self.next()?;
+        if !is_name(&t.text) {
+            return Err((t.pos, format!("'{}' can't be used as a name", t.text)));
+        }
+        Ok(t)
+    }
+
+    fn reg(&mut self) -> CompileResult<u8> {
+        let t = self.next()?;
+        self.register_of(&t.text).ok_or_else(|| (t.pos, format!("Expected a register, got '{}'", t.text)))
+    }
+
+    fn register_of(&self, s: &str) -> Option<u8> {
+        register(s).or_else(|| self.aliases.get(s).copied())
+    }
+
+    // A value that has to be known right now: number, constant, :calc result or an already defined label
+    fn value(&mut self, t: &Token) -> CompileResult<i64> {
+        if let Some(n) = number(&t.text) {
+            return Ok(n);
+        }
+        if let Some(v) = self.consts.get(&t.text) {
+            return Ok(*v as i64);
+        }
+        if let Some(addr) = self.labels.get(&t.text) {
+            return Ok(*addr as i64);
+        }
+        Err((t.pos, format!("Undefined name '{}'", t.text)))
+    }
+
+    fn value_range(&mut self, t: &Token, max: i64) -> CompileResult<u16> {
+        let v = self.value(t)?;
+        if v > max || v < -(max + 1) / 2 {
+            return Err((t.pos, format!("Value {} is out of range (0 to {})", v, max)));
+        }
+        Ok((v & max) as u16)
+    }
+
+    // ---- Output ----
+
+    fn write_byte(&mut self, b: u8, pos: Pos) -> CompileResult<()> {
+        if self.here > 0xFFFF {
+            return Err((pos, "Program doesn't fit in 64K of memory".to_string()));
+        }
+        let at = (self.here - PROGRAM_START as u32) as usize;
+        if self.rom.len() <= at {
+            self.rom.resize(at + 1, 0);
+        }
+        self.rom[at] = b;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, op: u16, pos: Pos) -> CompileResult<()> {
+        if self.here < PROGRAM_START as u32 {
+            return Err((pos, format!("Can't put instructions below 0x{:X}", PROGRAM_START)));
+        }
+        let addr = self.here as u16;
+        self.source_lines.entry(addr).or_insert(pos.line);
+        if let Some((name, label_pos)) = self.next_label.take() {
+            self.define_label(&name, addr.wrapping_add(1), label_pos)?;
+        }
+        self.write_byte((op >> 8) as u8, pos)?;
+        self.write_byte(op as u8, pos)
+    }
+
+    // Instruction with an address that can be filled in later, nnn's bits of op are replaced
+    fn emit_ref(&mut self, op: u16, name: &str, kind: FixupKind, pos: Pos) -> CompileResult<()> {
+        match self.labels.get(name).copied().or_else(|| self.consts.get(name).map(|v| *v as u16)) {
+            Some(addr) if addr <= 0xFFF => self.emit(op | addr, pos),
+            Some(addr) => Err((pos, format!("'{}' at 0x{:X} is out of reach of a 12 bit address, use i := long", name, addr))),
+            None => {
+                let addr = self.here as u16;
+                self.emit(op, pos)?;
+                self.fixups.push(Fixup { addr, kind, name: name.to_string(), pos });
+                Ok(())
+            }
+        }
+    }
+
+    // Address operand: a literal, a constant or any label, defined yet or not
+    fn emit_addr(&mut self, op: u16, t: &Token) -> CompileResult<()> {
+        match number(&t.text) {
+            Some(n) => {
+                if !(0..=0xFFF).contains(&n) {
+                    return Err((t.pos, format!("Address {} is out of range (0 to 0xFFF)", n)));
+                }
+                self.emit(op | n as u16, t.pos)
+            },
+            None if is_name(&t.text) => self.emit_ref(op, &t.text, FixupKind::Addr12, t.pos),
+            None => Err((t.pos, format!("Expected an address, got '{}'", t.text))),
+        }
+    }
+
+    fn define_label(&mut self, name: &str, addr: u16, pos: Pos) -> CompileResult<()> {
+        if self.labels.contains_key(name) || self.consts.contains_key(name) {
+            return Err((pos, format!("'{}' is already defined", name)));
+        }
+        self.labels.insert(name.to_string(), addr);
+        Ok(())
+    }
+
+    fn define_const(&mut self, name: &Token, value: f64) -> CompileResult<()> {
+        if self.labels.contains_key(&name.text) {
+            return Err((name.pos, format!("'{}' is already a label", name.text)));
+        }
+        self.consts.insert(name.text.clone(), value);
+        Ok(())
+    }
+
+    // ---- Statements ----
+
+    fn statement(&mut self) -> CompileResult<()> {
+        let t = self.next()?;
+        let pos = t.pos;
+
+        if let Some(x) = self.register_of(&t.text) {
+            return self.register_statement(x, pos);
+        }
+
+        match t.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                let here = self.here as u16;
+                self.define_label(&name.text, here, name.pos)?;
+            },
+            ":alias" => {
+                let name = self.name()?;
+                let r = self.reg()?;
+                self.aliases.insert(name.text, r);
+            },
+            ":const" => {
+                let name = self.name()?;
+                let v = self.next()?;
+                let value = self.value(&v)?;
+                self.define_const(&name, value as f64)?;
+            },
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.define_const(&name, value)?;
+            },
+            ":byte" => {
+                let value = if self.peek_is("{") {
+                    self.calc()? as i64
+                } else {
+                    let v = self.next()?;
+                    self.value(&v)?
+                };
+                self.write_byte(value as u8, pos)?;
+            },
+            ":org" => {
+                let v = if self.peek_is("{") { self.calc()? as i64 } else {
+                    let v = self.next()?;
+                    self.value(&v)?
+                };
+                if !(PROGRAM_START as i64..=0xFFFF).contains(&v) {
+                    return Err((pos, format!(":org address 0x{:X} is outside program memory", v)));
+                }
+                self.here = v as u32;
+            },
+            ":next" => {
+                let name = self.name()?;
+                self.next_label = Some((name.text, name.pos));
+            },
+            ":unpack" => {
+                let (nibble, long) = if self.peek_is("long") {
+                    self.next()?;
+                    (0, true)
+                } else {
+                    let n = self.next()?;
+                    (self.value_range(&n, 0xF)? as u8, false)
+                };
+                let target = self.next()?;
+                let resolved = number(&target.text).or_else(|| self.labels.get(&target.text).map(|a| *a as i64))
+                    .or_else(|| self.consts.get(&target.text).map(|v| *v as i64));
+                let addr = self.here as u16;
+                let v = resolved.unwrap_or(0) as u16;
+                if long {
+                    self.emit(0x6000 | (v >> 8), pos)?;
+                } else {
+                    self.emit(0x6000 | (nibble as u16) << 4 | ((v >> 8) & 0xF), pos)?;
+                }
+                self.emit(0x6100 | (v & 0xFF), pos)?;
+                if resolved.is_none() {
+                    if !is_name(&target.text) {
+                        return Err((target.pos, format!("Expected an address, got '{}'", target.text)));
+                    }
+                    let kind = if long { FixupKind::UnpackLong } else { FixupKind::Unpack(nibble) };
+                    self.fixups.push(Fixup { addr, kind, name: target.text, pos: target.pos });
+                }
+            },
+            ":breakpoint" => {
+                let name = self.next()?;
+                self.breakpoints.push((name.text, self.here as u16));
+            },
+            ":assert" => {
+                let message = if self.peek_is("{") { None } else { Some(self.next()?.text) };
+                if self.calc()? == 0.0 {
+                    return Err((pos, format!("Assertion failed{}", message.map(|m| format!(": {}", m)).unwrap_or_default())));
+                }
+            },
+            ":call" => {
+                let target = self.next()?;
+                self.emit_addr(0x2000, &target)?;
+            },
+            ":macro" => self.define_macro()?,
+            ":monitor" | ":stringmode" => {
+                return Err((pos, format!("{} is not supported", t.text)));
+            },
+            ";" | "return" => self.emit(0x00EE, pos)?,
+            "clear" => self.emit(0x00E0, pos)?,
+            "exit" => self.emit(0x00FD, pos)?,
+            "lores" => self.emit(0x00FE, pos)?,
+            "hires" => self.emit(0x00FF, pos)?,
+            "scroll-right" => self.emit(0x00FB, pos)?,
+            "scroll-left" => self.emit(0x00FC, pos)?,
+            "scroll-down" => {
+                let n = self.next()?;
+                let n = self.value_range(&n, 0xF)?;
+                self.emit(0x00C0 | n, pos)?;
+            },
+            "scroll-up" => {
+                let n = self.next()?;
+                let n = self.value_range(&n, 0xF)?;
+                self.emit(0x00D0 | n, pos)?;
+            },
+            "audio" => self.emit(0xF002, pos)?,
+            "plane" => {
+                let n = self.next()?;
+                let n = self.value_range(&n, 0xF)?;
+                self.emit(0xF001 | n << 8, pos)?;
+            },
+            "bcd" => { let x = self.reg()?; self.emit(0xF033 | (x as u16) << 8, pos)?; },
+            "saveflags" => { let x = self.reg()?; self.emit(0xF075 | (x as u16) << 8, pos)?; },
+            "loadflags" => { let x = self.reg()?; self.emit(0xF085 | (x as u16) << 8, pos)?; },
+            "save" | "load" => {
+                let x = self.reg()?;
+                if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.reg()?;
+                    let op = if t.text == "save" { 0x5002 } else { 0x5003 };
+                    self.emit(op | (x as u16) << 8 | (y as u16) << 4, pos)?;
+                } else {
+                    let op = if t.text == "save" { 0xF055 } else { 0xF065 };
+                    self.emit(op | (x as u16) << 8, pos)?;
+                }
+            },
+            "sprite" => {
+                let x = self.reg()?;
+                let y = self.reg()?;
+                let n = self.next()?;
+                let n = self.value_range(&n, 0xF)?;
+                self.emit(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n, pos)?;
+            },
+            "jump" => { let target = self.next()?; self.emit_addr(0x1000, &target)?; },
+            "jump0" => { let target = self.next()?; self.emit_addr(0xB000, &target)?; },
+            "native" => { let target = self.next()?; self.emit_addr(0x0000, &target)?; },
+            "i" => self.i_statement(pos)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.reg()? as u16;
+                let op = match t.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit(op | x << 8, pos)?;
+            },
+            "if" => {
+                let cond = self.condition()?;
+                let body = self.next()?;
+                for op in &cond.setup {
+                    self.emit(*op, pos)?;
+                }
+                match body.text.as_str() {
+                    "then" => self.emit(cond.skip_if_false, pos)?,
+                    "begin" => {
+                        self.emit(cond.skip_if_true, pos)?;
+                        let jump = self.here as u16;
+                        self.emit(0x1000, pos)?;
+                        self.blocks.push((Block::If { jump, seen_else: false }, pos));
+                    },
+                    other => return Err((body.pos, format!("Expected 'then' or 'begin', got '{}'", other))),
+                }
+            },
+            "else" => {
+                match self.blocks.pop() {
+                    Some((Block::If { jump, seen_else: false }, open)) => {
+                        let skip_else = self.here as u16;
+                        self.emit(0x1000, pos)?;
+                        self.patch_jump(jump, self.here as u16);
+                        self.blocks.push((Block::If { jump: skip_else, seen_else: true }, open));
+                    },
+                    _ => return Err((pos, "else without a matching if ... begin".to_string())),
+                }
+            },
+            "end" => {
+                match self.blocks.pop() {
+                    Some((Block::If { jump, .. }, _)) => self.patch_jump(jump, self.here as u16),
+                    _ => return Err((pos, "end without a matching if ... begin".to_string())),
+                }
+            },
+            "loop" => {
+                let start = self.here as u16;
+                self.blocks.push((Block::Loop { start, breaks: Vec::new() }, pos));
+            },
+            "while" => {
+                let cond = self.condition()?;
+                for op in &cond.setup {
+                    self.emit(*op, pos)?;
+                }
+                self.emit(cond.skip_if_true, pos)?;
+                let jump = self.here as u16;
+                self.emit(0x1000, pos)?;
+                match self.blocks.iter_mut().rev().find_map(|(b, _)| match b {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                }) {
+                    Some(breaks) => breaks.push(jump),
+                    None => return Err((pos, "while outside of a loop".to_string())),
+                }
+            },
+            "again" => {
+                match self.blocks.pop() {
+                    Some((Block::Loop { start, breaks }, _)) => {
+                        self.emit(0x1000 | start, pos)?;
+                        for jump in breaks {
+                            self.patch_jump(jump, self.here as u16);
+                        }
+                    },
+                    _ => return Err((pos, "again without a matching loop".to_string())),
+                }
+            },
+            "{" | "}" => return Err((pos, format!("Unexpected '{}'", t.text))),
+            _ if self.macros.contains_key(&t.text) => self.expand_macro(&t)?,
+            _ if number(&t.text).is_some() => {
+                let v = self.value_range(&t, 0xFF)?;
+                self.write_byte(v as u8, pos)?;
+            },
+            // A bare name calls a subroutine
+            _ if is_name(&t.text) => self.emit_ref(0x2000, &t.text, FixupKind::Addr12, pos)?,
+            _ => return Err((pos, format!("Unexpected '{}'", t.text))),
+        }
+        Ok(())
+    }
+
+    fn patch_jump(&mut self, jump: u16, target: u16) {
+        let at = (jump - PROGRAM_START) as usize;
+        self.rom[at] = 0x10 | ((target >> 8) & 0xF) as u8;
+        self.rom[at + 1] = target as u8;
+    }
+
+    fn register_statement(&mut self, x: u8, pos: Pos) -> CompileResult<()> {
+        let op = self.next()?;
+        let x16 = (x as u16) << 8;
+        let rhs = self.next()?;
+        let y = self.register_of(&rhs.text).map(|y| (y as u16) << 4);
+
+        let code = match (op.text.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | x16 | y,
+            ("|=", Some(y)) => 0x8001 | x16 | y,
+            ("&=", Some(y)) => 0x8002 | x16 | y,
+            ("^=", Some(y)) => 0x8003 | x16 | y,
+            ("+=", Some(y)) => 0x8004 | x16 | y,
+            ("-=", Some(y)) => 0x8005 | x16 | y,
+            (">>=", Some(y)) => 0x8006 | x16 | y,
+            ("=-", Some(y)) => 0x8007 | x16 | y,
+            ("<<=", Some(y)) => 0x800E | x16 | y,
+            (":=", None) => match rhs.text.as_str() {
+                "delay" => 0xF007 | x16,
+                "key" => 0xF00A | x16,
+                "random" => {
+                    let mask = self.next()?;
+                    0xC000 | x16 | self.value_range(&mask, 0xFF)?
+                },
+                _ => 0x6000 | x16 | self.value_range(&rhs, 0xFF)?,
+            },
+            ("+=", None) => 0x7000 | x16 | self.value_range(&rhs, 0xFF)?,
+            ("-=", None) => {
+                let v = self.value_range(&rhs, 0xFF)?;
+                0x7000 | x16 | (v as u8).wrapping_neg() as u16
+            },
+            _ => return Err((op.pos, format!("Unknown register operation '{}' with '{}'", op.text, rhs.text))),
+        };
+        self.emit(code, pos)
+    }
+
+    fn i_statement(&mut self, pos: Pos) -> CompileResult<()> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            "+=" => {
+                let x = self.reg()?;
+                self.emit(0xF01E | (x as u16) << 8, pos)
+            },
+            ":=" => {
+                let rhs = self.next()?;
+                match rhs.text.as_str() {
+                    "hex" => { let x = self.reg()?; self.emit(0xF029 | (x as u16) << 8, pos) },
+                    "bighex" => { let x = self.reg()?; self.emit(0xF030 | (x as u16) << 8, pos) },
+                    "long" => {
+                        let target = self.next()?;
+                        self.emit(0xF000, pos)?;
+                        let addr = self.here as u16;
+                        match number(&target.text).or_else(|| self.labels.get(&target.text).map(|a| *a as i64))
+                                .or_else(|| self.consts.get(&target.text).map(|v| *v as i64)) {
+                            Some(v) => {
+                                self.write_byte((v >> 8) as u8, pos)?;
+                                self.write_byte(v as u8, pos)
+                            },
+                            None if is_name(&target.text) => {
+                                self.write_byte(0, pos)?;
+                                self.write_byte(0, pos)?;
+                                self.fixups.push(Fixup { addr, kind: FixupKind::Word16, name: target.text, pos: target.pos });
+                                Ok(())
+                            },
+                            None => Err((target.pos, format!("Expected an address, got '{}'", target.text))),
+                        }
+                    },
+                    _ => self.emit_addr(0xA000, &rhs),
+                }
+            },
+            _ => Err((op.pos, format!("Unknown operation on i '{}'", op.text))),
+        }
+    }
+
+    fn condition(&mut self) -> CompileResult<Condition> {
+        let x = self.reg()? as u16;
+        let op = self.next()?;
+        match op.text.as_str() {
+            "key" => return Ok(Condition { setup: vec![], skip_if_false: 0xE0A1 | x << 8, skip_if_true: 0xE09E | x << 8 }),
+            "-key" => return Ok(Condition { setup: vec![], skip_if_false: 0xE09E | x << 8, skip_if_true: 0xE0A1 | x << 8 }),
+            _ => {}
+        }
+        let rhs = self.next()?;
+        let y = self.register_of(&rhs.text).map(|y| y as u16);
+        let imm = match y {
+            Some(_) => 0,
+            None => self.value_range(&rhs, 0xFF)?,
+        };
+
+        let (eq, ne) = match y {
+            Some(y) => (0x9000 | x << 8 | y << 4, 0x5000 | x << 8 | y << 4),
+            None => (0x4000 | x << 8 | imm, 0x3000 | x << 8 | imm),
+        };
+        // eq skips when they differ, ne skips when they're equal
+        let cond = match op.text.as_str() {
+            "==" => Condition { setup: vec![], skip_if_false: eq, skip_if_true: ne },
+            "!=" => Condition { setup: vec![], skip_if_false: ne, skip_if_true: eq },
+            "<" | ">" | "<=" | ">=" => {
+                // vf := rhs, then vf := vx - vf (=-) or vf - vx (-=), leaving only the no-borrow flag in vf
+                let load = match y {
+                    Some(y) => 0x8F00 | y << 4,
+                    None => 0x6F00 | imm,
+                };
+                let sub = match op.text.as_str() {
+                    "<" | ">=" => 0x8F07 | x << 4,
+                    _ => 0x8F05 | x << 4,
+                };
+                // < and > hold when that leaves vf at 0, <= and >= when it leaves 1
+                let (vf_zero, vf_set) = (0x4F00, 0x3F00);
+                match op.text.as_str() {
+                    "<" | ">" => Condition { setup: vec![load, sub], skip_if_false: vf_zero, skip_if_true: vf_set },
+                    _ => Condition { setup: vec![load, sub], skip_if_false: vf_set, skip_if_true: vf_zero },
+                }
+            },
+            _ => return Err((op.pos, format!("Unknown comparison '{}'", op.text))),
+        };
+        Ok(cond)
+    }
+
+    // ---- Macros ----
+
+    fn define_macro(&mut self) -> CompileResult<()> {
+        let name = self.name()?;
+        let mut args = Vec::new();
+        loop {
+            let t = self.next()?;
+            if t.text == "{" {
+                break;
+            }
+            args.push(t.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let t = self.next()?;
+            match t.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                _ => {}
+            }
+            body.push(t);
+        }
+        self.macros.insert(name.text, Macro { args, body, calls: 0 });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, call: &Token) -> CompileResult<()> {
+        if call.depth >= MAX_MACRO_DEPTH {
+            return Err((call.pos, format!("Macro '{}' expands too deeply, is it calling itself?", call.text)));
+        }
+        let arg_count = self.macros[&call.text].args.len();
+        let mut values = Vec::with_capacity(arg_count);
+        for _ in 0..arg_count {
+            values.push(self.next()?);
+        }
+
+        let m = self.macros.get_mut(&call.text).unwrap();
+        let calls = m.calls;
+        m.calls += 1;
+        let expanded: Vec<Token> = m.body.iter().map(|t| {
+            let mut t = match m.args.iter().position(|a| *a == t.text) {
+                Some(i) => Token { pos: t.pos, ..values[i].clone() },
+                None => t.clone(),
+            };
+            if t.text == "CALLS" {
+                t.text = calls.to_string();
+            }
+            t.depth = call.depth + 1;
+            t
+        }).collect();
+
+        for t in expanded.into_iter().rev() {
+            self.tokens.push_front(t);
+        }
+        Ok(())
+    }
+
+    // ---- :calc expressions ----
+
+    /**
+     *  { expression } as in Octo: no precedence, operators are applied right to left
+     *  so { 2 * 3 + 4 } is 2 * (3 + 4). Parentheses group as usual.
+     */
+    fn calc(&mut self) -> CompileResult<f64> {
+        self.expect("{")?;
+        let mut tokens = Vec::new();
+        let mut depth = 1;
+        loop {
+            let t = self.next()?;
+            match t.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                _ => {}
+            }
+            tokens.push(t);
+        }
+        let mut at = 0;
+        let v = self.calc_expr(&tokens, &mut at)?;
+        if at < tokens.len() {
+            return Err((tokens[at].pos, format!("Unexpected '{}' in expression", tokens[at].text)));
+        }
+        Ok(v)
+    }
+
+    fn calc_expr(&self, tokens: &[Token], at: &mut usize) -> CompileResult<f64> {
+        let lhs = self.calc_term(tokens, at)?;
+        let op = match tokens.get(*at) {
+            Some(t) if t.text != ")" => t.clone(),
+            _ => return Ok(lhs),
+        };
+        *at += 1;
+        let rhs = self.calc_expr(tokens, at)?;
+        let v = match op.text.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => (lhs as i64 & rhs as i64) as f64,
+            "|" => (lhs as i64 | rhs as i64) as f64,
+            "^" => (lhs as i64 ^ rhs as i64) as f64,
+            "<<" => ((lhs as i64) << (rhs as i64)) as f64,
+            ">>" => ((lhs as i64) >> (rhs as i64)) as f64,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => (lhs < rhs) as i64 as f64,
+            ">" => (lhs > rhs) as i64 as f64,
+            "<=" => (lhs <= rhs) as i64 as f64,
+            ">=" => (lhs >= rhs) as i64 as f64,
+            "==" => (lhs == rhs) as i64 as f64,
+            "!=" => (lhs != rhs) as i64 as f64,
+            _ => return Err((op.pos, format!("Unknown operator '{}' in expression", op.text))),
+        };
+        Ok(v)
+    }
+
+    fn calc_term(&self, tokens: &[Token], at: &mut usize) -> CompileResult<f64> {
+        let t = match tokens.get(*at) {
+            Some(t) => t,
+            None => return Err((tokens.last().map_or(self.last_pos, |t| t.pos), "Expression ends early".to_string())),
+        };
+        *at += 1;
+        let unary: Option<fn(f64) -> f64> = match t.text.as_str() {
+            "-" => Some(|v| -v),
+            "~" => Some(|v| !(v as i64) as f64),
+            "!" => Some(|v| (v == 0.0) as i64 as f64),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(f) = unary {
+            return Ok(f(self.calc_term(tokens, at)?));
+        }
+        match t.text.as_str() {
+            "(" => {
+                let v = self.calc_expr(tokens, at)?;
+                match tokens.get(*at) {
+                    Some(close) if close.text == ")" => {
+                        *at += 1;
+                        Ok(v)
+                    },
+                    _ => Err((t.pos, "Unclosed '(' in expression".to_string())),
+                }
+            },
+            // Byte already compiled at an address
+            "@" => {
+                let addr = self.calc_term(tokens, at)? as i64;
+                let byte = addr.checked_sub(PROGRAM_START as i64)
+                    .and_then(|i| self.rom.get(i as usize)).copied().unwrap_or(0);
+                Ok(byte as f64)
+            },
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            text => {
+                if let Some(n) = number(text) {
+                    Ok(n as f64)
+                } else if let Some(v) = self.consts.get(text) {
+                    Ok(*v)
+                } else if let Some(a) = self.labels.get(text) {
+                    Ok(*a as f64)
+                } else if let Some(r) = self.register_of(text) {
+                    Ok(r as f64)
+                } else {
+                    Err((t.pos, format!("Undefined name '{}' in expression, :calc can only use names defined above it", text)))
+                }
+            }
+        }
+    }
+}
+
+fn is_name(s: &str) -> bool {
+    !s.is_empty() && number(s).is_none() && register(s).is_none() && !KEYWORDS.contains(&s)
+        && !matches!(s, "{" | "}" | ":=" | "+=" | "-=" | "|=" | "&=" | "^=" | "=-" | ">>=" | "<<=" | "==" | "!=" | "<" | ">" | "<=" | ">=")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+
+    fn rom(source: &str) -> Vec<u8> {
+        compile(source, "test.8o").unwrap_or_else(|e| panic!("{}", e)).rom
+    }
+
+    fn words(ops: &[u16]) -> Vec<u8> {
+        ops.iter().flat_map(|op| op.to_be_bytes()).collect()
+    }
+
+    // (line, column, message)
+    fn error(source: &str) -> (usize, usize, String) {
+        let e = compile(source, "test.8o").err().expect("should not compile");
+        (e.line, e.col, e.message)
+    }
+
+    #[test]
+    fn jumps_to_main_unless_it_comes_first() {
+        assert_eq!(rom(": main clear"), words(&[0x00E0]));
+        assert_eq!(rom(": sub return : main sub"), words(&[0x1204, 0x00EE, 0x2202]));
+    }
+
+    #[test]
+    fn constants_calc_and_macros() {
+        let source = "
+            : main
+            :const five 5
+            :calc ten { five * 2 }
+            :calc grouped { 2 * 3 + 4 }   # right to left, 2 * ( 3 + 4 )
+            :calc nested { ( 2 * 3 ) + 4 }
+            v0 := five
+            v1 := ten
+            v2 := grouped
+            v3 := nested
+            :macro twice reg { reg += 1 reg += 1 }
+            twice v4
+            twice v5
+            :macro count { :byte CALLS }
+            count count
+        ";
+        assert_eq!(rom(source), [
+            0x60, 0x05, 0x61, 0x0A, 0x62, 0x0E, 0x63, 0x0A,
+            0x74, 0x01, 0x74, 0x01, 0x75, 0x01, 0x75, 0x01,
+            0x00, 0x01,
+        ]);
+    }
+
+    #[test]
+    fn if_then_and_if_begin_else_end() {
+        let source = "
+            : main
+            if v0 == 1 then v1 := 2
+            if v0 != v1 begin
+                v2 := 3
+            else
+                v2 := 4
+            end
+        ";
+        assert_eq!(rom(source), words(&[
+            0x4001,         // skip the load unless v0 == 1
+            0x6102,
+            0x9010,         // skip the jump to else when v0 != v1
+            0x120C,
+            0x6203,
+            0x120E,         // jump over else
+            0x6204,
+        ]));
+    }
+
+    #[test]
+    fn loop_while_again() {
+        let source = "
+            : main
+            loop
+                v0 += 1
+                while v0 != 10
+                v1 += 1
+            again
+        ";
+        assert_eq!(rom(source), words(&[0x7001, 0x400A, 0x120A, 0x7101, 0x1200]));
+    }
+
+    #[test]
+    fn comparison_encodings() {
+        let cases: &[(&str, &[u16])] = &[
+            ("v1 == 5", &[0x4105]),
+            ("v1 != 5", &[0x3105]),
+            ("v1 == v2", &[0x9120]),
+            ("v1 != v2", &[0x5120]),
+            ("v1 key", &[0xE1A1]),
+            ("v1 -key", &[0xE19E]),
+            // vf := rhs, then subtract one way or the other and test the no-borrow flag
+            ("v1 < 5", &[0x6F05, 0x8F17, 0x4F00]),
+            ("v1 > 5", &[0x6F05, 0x8F15, 0x4F00]),
+            ("v1 <= 5", &[0x6F05, 0x8F15, 0x3F00]),
+            ("v1 >= 5", &[0x6F05, 0x8F17, 0x3F00]),
+            ("v1 < v2", &[0x8F20, 0x8F17, 0x4F00]),
+            ("v1 >= v2", &[0x8F20, 0x8F17, 0x3F00]),
+        ];
+        for (condition, ops) in cases {
+            let compiled = rom(&format!(": main if {} then clear", condition));
+            let mut expected = words(ops);
+            expected.extend_from_slice(&[0x00, 0xE0]);
+            assert_eq!(compiled, expected, "{}", condition);
+        }
+    }
+
+    #[test]
+    fn comparisons_hold_when_they_should() {
+        for &(a, b) in &[(3, 5), (5, 5), (7, 5), (0, 255)] {
+            let source = format!("
+                : main
+                v0 := {} v1 := {}
+                if v0 == v1 then v2 := 1
+                if v0 != v1 then v3 := 1
+                if v0 < v1 then v4 := 1
+                if v0 > v1 then v5 := 1
+                if v0 <= v1 then v6 := 1
+                if v0 >= v1 then v7 := 1
+                : halt jump halt
+            ", a, b);
+            let mut chip8 = Chip8::new(Quirks::default());
+            chip8.load_rom(&rom(&source)).unwrap();
+            for _ in 0..40 {
+                chip8.cycle().unwrap();
+            }
+            let expected = [a == b, a != b, a < b, a > b, a <= b, a >= b].map(|hold| hold as u8);
+            assert_eq!(chip8.v()[2..8], expected, "{} vs {}", a, b);
+        }
+    }
+
+    #[test]
+    fn forward_references() {
+        let source = "
+            : main
+            i := long data
+            :unpack 0xA data
+            jump0 data
+            : data 0xAB
+        ";
+        assert_eq!(rom(source), [0xF0, 0x00, 0x02, 0x0A, 0x60, 0xA2, 0x61, 0x0A, 0xB2, 0x0A, 0xAB]);
+    }
+
+    #[test]
+    fn scroll_up_runs() {
+        let mut chip8 = Chip8::new(Quirks::xochip());
+        chip8.load_rom(&rom(": main scroll-up 3 scroll-down 3")).unwrap();
+        assert_eq!(chip8.memory()[0x200..0x204], [0x00, 0xD3, 0x00, 0xC3]);
+        chip8.cycle().unwrap();
+        chip8.cycle().unwrap();
+    }
+
+    #[test]
+    fn errors_point_at_the_token() {
+        assert_eq!(error(": main\n  v0 := 256"), (2, 9, "Value 256 is out of range (0 to 255)".to_string()));
+        assert_eq!(error(": main\n\n  jump nowhere"), (3, 8, "Undefined name 'nowhere'".to_string()));
+        assert_eq!(error(": main\n  loop v0 += 1"), (2, 3, "loop without a matching again".to_string()));
+        assert_eq!(error(": main\n  if v0 == 1 begin"), (2, 3, "begin without a matching end".to_string()));
+        assert_eq!(error(": main\n  v0 ? 1"), (2, 6, "Unknown register operation '?' with '1'".to_string()));
+        assert_eq!(error(": main\n  if v0 =< 1 then"), (2, 9, "Unknown comparison '=<'".to_string()));
+        assert_eq!(error(": main : main"), (1, 10, "'main' is already defined".to_string()));
+        assert_eq!(error("clear"), (1, 1, "This program is missing a 'main' label".to_string()));
+        assert_eq!(error(": main :stringmode"), (1, 8, ":stringmode is not supported".to_string()));
+        assert_eq!(error(": main v0 :="), (1, 11, "Unexpected end of file".to_string()));
+    }
+
+    #[test]
+    fn source_map() {
+        let source = "\
+: main
+  v0 := 1
+  :macro bump { v0 += 1
+    v1 += 1 }
+  bump
+
+  loop again";
+        let program = compile(source, "test.8o").unwrap();
+        let map = &program.source_map;
+        assert_eq!(map.name(), "test.8o");
+        assert_eq!(map.line_count(), 7);
+        assert_eq!(map.line_for(0x200), Some(2));
+        // Instructions from the macro point into its body
+        assert_eq!(map.line_for(0x202), Some(3));
+        assert_eq!(map.line_for(0x204), Some(4));
+        assert_eq!(map.line_for(0x206), Some(7));
+        assert_eq!(map.line_for(0x208), None);
+        assert_eq!(map.line_text(7), Some("  loop again"));
+    }
+}
@@ -0,0 +1,459 @@
+use rusty_chip8_emu::chip8::{Chip8, Chip8Error};
+use rusty_chip8_emu::debugger::Debugger;
+use rusty_chip8_emu::chip8::Opcode;
+use rusty_chip8_emu::disasm::{self, Analysis, Syntax};
+use rusty_chip8_emu::octo::SourceMap;
+use imgui::{im_str, Condition, ImStr, ImString, Selectable, Ui, Window};
+use sdl2::keyboard::Scancode;
+use crate::input::Rebinding;
+
+const HIGHLIGHT: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+const DIM: [f32; 4] = [0.55, 0.55, 0.55, 1.0];
+
+// Keypad in the same layout as the original COSMAC VIP hex keypad
+const KEYPAD_LAYOUT: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// Instructions shown either side of pc in the disassembly panel
+const DISASM_CONTEXT: u16 = 12;
+// Lines shown either side of the current one in the source panel
+const SOURCE_CONTEXT: usize = 6;
+
+/**
+ *  imgui debugger drawn over the chip8 display. Everything is read only while the
+ *  emulator runs, pausing turns the registers, stack, timers, memory and keypad into inputs.
+ */
+pub struct DebugOverlay {
+    pub visible: bool,
+    // First address of the 256 byte page shown in the memory editor
+    memory_page: u16,
+    follow_i: bool,
+    // Text box in the breakpoints window and the result of the last add
+    break_input: ImString,
+    break_message: String,
+    // Labels and code/data split for the loaded ROM, traced once at load
+    analysis: Option<Analysis>,
+    // Set when the program was compiled from Octo source, and where pc last mapped to a line
+    source_map: Option<SourceMap>,
+    source_line: Option<usize>,
+}
+
+impl DebugOverlay {
+
+    pub fn new() -> Self {
+        DebugOverlay {
+            visible: false,
+            memory_page: 0x200,
+            follow_i: false,
+            break_input: ImString::with_capacity(32),
+            break_message: String::new(),
+            analysis: None,
+            source_map: None,
+            source_line: None,
+        }
+    }
+
+    pub fn set_program(&mut self, rom: &[u8]) {
+        self.analysis = Some(Analysis::trace(rom));
+    }
+
+    pub fn set_source_map(&mut self, map: SourceMap) {
+        self.source_map = Some(map);
+    }
+
+    pub fn draw(&mut self, ui: &Ui, chip8: &mut Chip8, debugger: &mut Debugger) {
+        // Shown even with the debugger hidden, otherwise a crashed program just looks frozen
+        if let Some(fault) = debugger.fault() {
+            self.draw_crash(ui, fault);
+        }
+        if !self.visible {
+            return;
+        }
+        let paused = debugger.paused();
+        self.draw_registers(ui, chip8, debugger);
+        self.draw_stack(ui, chip8, paused);
+        self.draw_disassembly(ui, chip8, debugger);
+        self.draw_source(ui, chip8);
+        self.draw_breakpoints(ui, debugger);
+        self.draw_memory(ui, chip8, paused);
+        self.draw_keypad(ui, chip8, paused);
+    }
+
+    fn draw_registers(&mut self, ui: &Ui, chip8: &mut Chip8, debugger: &mut Debugger) {
+        Window::new(im_str!("Registers"))
+            .position([10.0, 10.0], Condition::FirstUseEver)
+            .size([230.0, 360.0], Condition::FirstUseEver)
+            .build(ui, || {
+                let editable = debugger.paused();
+                if ui.small_button(if editable { im_str!("Run (P)") } else { im_str!("Pause (P)") }) {
+                    debugger.toggle_pause();
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Step (N)")) {
+                    debugger.step_into();
+                }
+                if ui.small_button(im_str!("Over (O)")) {
+                    debugger.step_over(chip8);
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Out (U)")) {
+                    debugger.step_out(chip8);
+                }
+                if let Some(reason) = debugger.last_break() {
+                    ui.text_colored(HIGHLIGHT, reason.to_string());
+                }
+                if let Some(wait) = chip8.key_wait() {
+                    ui.text_colored(HIGHLIGHT, wait.to_string());
+                }
+                ui.separator();
+
+                ui.columns(2, im_str!("registers"), false);
+                for r in 0..16 {
+                    let value = chip8.v()[r] as u32;
+                    if let Some(v) = hex_field(ui, &im_str!("V{:X}", r), value, 2, editable) {
+                        chip8.v_mut()[r] = v as u8;
+                    }
+                    ui.next_column();
+                }
+                ui.columns(1, im_str!("registers"), false);
+                ui.separator();
+
+                if let Some(v) = hex_field(ui, im_str!("I"), chip8.i() as u32, 4, editable) {
+                    chip8.set_i(v as u16);
+                }
+                if let Some(v) = hex_field(ui, im_str!("PC"), chip8.pc() as u32, 4, editable) {
+                    chip8.set_pc(v as u16);
+                }
+                if let Some(v) = hex_field(ui, im_str!("SP"), chip8.sp() as u32, 2, editable) {
+                    chip8.set_sp(v as u16);
+                }
+                if let Some(v) = hex_field(ui, im_str!("DT"), chip8.delay_timer() as u32, 2, editable) {
+                    chip8.set_delay_timer(v as u8);
+                }
+                if let Some(v) = hex_field(ui, im_str!("ST"), chip8.sound_timer() as u32, 2, editable) {
+                    chip8.set_sound_timer(v as u8);
+                }
+            });
+    }
+
+    fn draw_crash(&self, ui: &Ui, fault: &Chip8Error) {
+        let state = fault.state();
+        Window::new(im_str!("Crashed"))
+            .position([250.0, 200.0], Condition::FirstUseEver)
+            .size([420.0, 260.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text_colored(HIGHLIGHT, fault.to_string());
+                let label = |a: u16| self.analysis.as_ref().and_then(|an| an.label_name(a));
+                if let Some(text) = disasm::mnemonic(Opcode(state.opcode), 0, Syntax::Cowgod, &label) {
+                    ui.text(format!("{:04X}  {}", state.pc, text));
+                }
+                if let Some(map) = &self.source_map {
+                    if let Some(line) = map.line_for(state.pc) {
+                        let text = map.line_text(line).unwrap_or("").trim();
+                        ui.text_disabled(format!("{}:{}  {}", map.name(), line, text));
+                    }
+                }
+                ui.separator();
+
+                // Registers as they were when it faulted, the debugger windows show them live
+                for row in 0..4 {
+                    let regs: Vec<String> = (row * 4..row * 4 + 4).map(|r| format!("V{:X}={:02X}", r, state.v[r])).collect();
+                    ui.text(regs.join("  "));
+                }
+                ui.text(format!("I={:04X}  SP={:X}  DT={:02X}  ST={:02X}", state.i, state.sp, state.delay_timer, state.sound_timer));
+                let stack: Vec<String> = state.stack[1..=state.sp as usize].iter().map(|a| format!("{:04X}", a)).collect();
+                ui.text(format!("Stack: [{}]", stack.join(", ")));
+                ui.separator();
+                ui.text_disabled("F1-F10 loads a save state, Backspace rewinds, ` opens the debugger");
+            });
+    }
+
+    fn draw_stack(&mut self, ui: &Ui, chip8: &mut Chip8, paused: bool) {
+        Window::new(im_str!("Call Stack"))
+            .position([10.0, 380.0], Condition::FirstUseEver)
+            .size([230.0, 200.0], Condition::FirstUseEver)
+            .build(ui, || {
+                // CALL pre-increments sp, so stack[1..=sp] holds the live return addresses
+                let sp = chip8.sp() as usize;
+                if sp == 0 {
+                    ui.text_disabled("(empty)");
+                }
+                for depth in (1..chip8.stack().len()).rev() {
+                    let live = depth <= sp;
+                    if !live && !paused {
+                        continue;
+                    }
+                    let addr = chip8.stack()[depth] as u32;
+                    let label = im_str!("#{:X}{}", depth, if live { "" } else { " (unused)" });
+                    if let Some(v) = hex_field(ui, &label, addr, 4, paused) {
+                        chip8.stack_mut()[depth] = v as u16;
+                    }
+                }
+            });
+    }
+
+    fn draw_disassembly(&mut self, ui: &Ui, chip8: &Chip8, debugger: &mut Debugger) {
+        Window::new(im_str!("Disassembly"))
+            .position([250.0, 10.0], Condition::FirstUseEver)
+            .size([300.0, 420.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text_disabled("Click a line to toggle a breakpoint");
+                let pc = chip8.pc();
+                let start = pc.saturating_sub(DISASM_CONTEXT * 2);
+                for n in 0..DISASM_CONTEXT * 2 + 1 {
+                    let addr = start.wrapping_add(n * 2);
+                    let op = chip8.opcode_at(addr);
+                    let label = |a: u16| self.analysis.as_ref().and_then(|an| an.label_name(a));
+                    if let Some(name) = label(addr) {
+                        ui.text_colored(DIM, &im_str!("{}:", name));
+                    }
+                    let text = disasm::mnemonic(Opcode(op), chip8.opcode_at(addr.wrapping_add(2)), Syntax::Cowgod, &label)
+                        .unwrap_or_else(|| format!("DW {:04X}", op));
+                    let marker = if debugger.breakpoints.contains(&addr) { "*" } else { " " };
+                    let cursor = if addr == pc { ">" } else { " " };
+                    let line = im_str!("{}{} {:04X}  {:04X}  {}", marker, cursor, addr, op, text);
+                    if Selectable::new(&line).selected(addr == pc).build(ui) {
+                        debugger.toggle_breakpoint(addr);
+                    }
+                }
+            });
+    }
+
+    fn draw_source(&mut self, ui: &Ui, chip8: &Chip8) {
+        let map = match &self.source_map {
+            Some(map) => map,
+            None => return
+        };
+        // pc sits between lines while a skip or macro body runs, keep showing the last line we knew
+        if let Some(line) = map.line_for(chip8.pc()) {
+            self.source_line = Some(line);
+        }
+        let current = self.source_line;
+        Window::new(im_str!("Source"))
+            .position([560.0, 440.0], Condition::FirstUseEver)
+            .size([620.0, 260.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text_disabled(&im_str!("{}", map.name()));
+                let line = match current {
+                    Some(line) => line,
+                    None => {
+                        ui.text_disabled("pc hasn't reached any compiled line yet");
+                        return;
+                    }
+                };
+                let first = line.saturating_sub(SOURCE_CONTEXT).max(1);
+                let last = (line + SOURCE_CONTEXT).min(map.line_count());
+                for n in first..=last {
+                    let text = im_str!("{}{:5}  {}", if n == line { ">" } else { " " }, n, map.line_text(n).unwrap_or(""));
+                    if n == line {
+                        ui.text_colored(HIGHLIGHT, &text);
+                    } else {
+                        ui.text(&text);
+                    }
+                }
+            });
+    }
+
+    fn draw_breakpoints(&mut self, ui: &Ui, debugger: &mut Debugger) {
+        let input = &mut self.break_input;
+        let message = &mut self.break_message;
+        Window::new(im_str!("Breakpoints"))
+            .position([250.0, 440.0], Condition::FirstUseEver)
+            .size([300.0, 260.0], Condition::FirstUseEver)
+            .build(ui, || {
+                let entered = ui.input_text(im_str!("Add"), input)
+                    .enter_returns_true(true)
+                    .build();
+                if entered {
+                    *message = match debugger.add_from_str(input.to_str()) {
+                        Ok(added) => format!("Added {}", added),
+                        Err(e) => e,
+                    };
+                    input.clear();
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("200          break at PC 0x200\n\
+                                     V3 == 10     register condition\n\
+                                     op Dxyn      opcode pattern\n\
+                                     w 300-30F    memory watch (r, w, rw)");
+                }
+                if !message.is_empty() {
+                    ui.text_disabled(&*message);
+                }
+                ui.separator();
+
+                let mut remove_bp = None;
+                for &addr in debugger.breakpoints.iter() {
+                    if ui.small_button(&im_str!("x##bp{}", addr)) {
+                        remove_bp = Some(addr);
+                    }
+                    ui.same_line(0.0);
+                    ui.text(format!("PC == {:04X}", addr));
+                }
+                if let Some(addr) = remove_bp {
+                    debugger.breakpoints.remove(&addr);
+                }
+
+                if let Some(n) = list_removable(ui, "cond", debugger.conditions.iter().map(|c| c.to_string())) {
+                    debugger.conditions.remove(n);
+                }
+                if let Some(n) = list_removable(ui, "op", debugger.opcode_breaks.iter().map(|p| format!("op {}", p))) {
+                    debugger.opcode_breaks.remove(n);
+                }
+                if let Some(n) = list_removable(ui, "watch", debugger.watchpoints.iter().map(|w| w.to_string())) {
+                    debugger.watchpoints.remove(n);
+                }
+            });
+    }
+
+    fn draw_memory(&mut self, ui: &Ui, chip8: &mut Chip8, paused: bool) {
+        let follow_i = &mut self.follow_i;
+        let memory_page = &mut self.memory_page;
+        Window::new(im_str!("Memory"))
+            .position([560.0, 10.0], Condition::FirstUseEver)
+            .size([620.0, 420.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.checkbox(im_str!("Follow I"), follow_i);
+                ui.same_line(0.0);
+                if *follow_i {
+                    *memory_page = chip8.i() & 0xFFF0;
+                }
+                let _w = ui.push_item_width(60.0);
+                if let Some(v) = hex_field(ui, im_str!("Page"), *memory_page as u32, 4, true) {
+                    *memory_page = v as u16 & 0xFFF0;
+                    *follow_i = false;
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("<")) {
+                    *memory_page = memory_page.wrapping_sub(0x100);
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!(">")) {
+                    *memory_page = memory_page.wrapping_add(0x100);
+                }
+                ui.separator();
+
+                let page = *memory_page as usize;
+                for row in 0..16 {
+                    let row_addr = (page + row * 16) % chip8.memory().len();
+                    ui.text_colored(DIM, format!("{:04X}", row_addr));
+                    for col in 0..16 {
+                        let addr = (row_addr + col) % chip8.memory().len();
+                        let value = chip8.memory()[addr];
+                        ui.same_line(0.0);
+                        if paused {
+                            let _id = ui.push_id(addr as i32);
+                            let _cw = ui.push_item_width(22.0);
+                            if let Some(v) = hex_input(ui, im_str!(""), value as u32, 2) {
+                                chip8.memory_mut()[addr] = v as u8;
+                            }
+                        } else if addr == chip8.i() as usize {
+                            ui.text_colored(HIGHLIGHT, format!("{:02X}", value));
+                        } else {
+                            ui.text(format!("{:02X}", value));
+                        }
+                    }
+                }
+            });
+    }
+
+    fn draw_keypad(&mut self, ui: &Ui, chip8: &mut Chip8, paused: bool) {
+        Window::new(im_str!("Keypad"))
+            .position([10.0, 590.0], Condition::FirstUseEver)
+            .always_auto_resize(true)
+            .build(ui, || {
+                for row in KEYPAD_LAYOUT.iter() {
+                    for (n, &key) in row.iter().enumerate() {
+                        if n > 0 {
+                            ui.same_line(0.0);
+                        }
+                        if paused {
+                            let mut down = chip8.keyboard()[key];
+                            if ui.checkbox(&im_str!("{:X}", key), &mut down) {
+                                chip8.keyboard_mut()[key] = down;
+                            }
+                        } else if chip8.keyboard()[key] {
+                            ui.text_colored(HIGHLIGHT, format!("[{:X}]", key));
+                        } else {
+                            ui.text_colored(DIM, format!(" {:X} ", key));
+                        }
+                    }
+                }
+            });
+    }
+}
+
+/**
+ *  The F12 rebinding screen, drawn whether or not the debugger is showing: what it's asking for and
+ *  the keypad with the keys bound to each, the one being asked for highlighted.
+ */
+pub fn draw_rebinding(ui: &Ui, rebinding: &Rebinding) {
+    Window::new(im_str!("Keys"))
+        .position([300.0, 200.0], Condition::FirstUseEver)
+        .always_auto_resize(true)
+        .build(ui, || {
+            ui.text_colored(HIGHLIGHT, rebinding.prompt());
+            if let Some(message) = &rebinding.message {
+                ui.text(message);
+            }
+            ui.text_colored(DIM, "Backspace keeps the key as it is, Escape cancels");
+            ui.separator();
+            for row in KEYPAD_LAYOUT.iter() {
+                for (n, &key) in row.iter().enumerate() {
+                    if n > 0 {
+                        ui.same_line(0.0);
+                    }
+                    let bound: Vec<&str> = rebinding.keymap().bindings(key as u8).map(Scancode::name).collect();
+                    let text = format!("{:X}: {:<12}", key, bound.join(", "));
+                    if key as u8 == rebinding.key() {
+                        ui.text_colored(HIGHLIGHT, text);
+                    } else {
+                        ui.text(text);
+                    }
+                }
+            }
+        });
+}
+
+// One line per item with a remove button, returns the index of the item to remove
+fn list_removable<I: Iterator<Item = String>>(ui: &Ui, id: &str, items: I) -> Option<usize> {
+    let mut remove = None;
+    for (n, text) in items.enumerate() {
+        if ui.small_button(&im_str!("x##{}{}", id, n)) {
+            remove = Some(n);
+        }
+        ui.same_line(0.0);
+        ui.text(text);
+    }
+    remove
+}
+
+// "label: value" in hex, as an input returning the new value when editable and changed
+fn hex_field(ui: &Ui, label: &ImStr, value: u32, digits: usize, editable: bool) -> Option<u32> {
+    if editable {
+        let _w = ui.push_item_width(12.0 * digits as f32 + 16.0);
+        hex_input(ui, label, value, digits)
+    } else {
+        ui.text(format!("{}: {:0width$X}", label.to_str(), value, width = digits));
+        None
+    }
+}
+
+fn hex_input(ui: &Ui, label: &ImStr, value: u32, digits: usize) -> Option<u32> {
+    let mut buf = ImString::with_capacity(digits + 1);
+    buf.push_str(&format!("{:0width$X}", value, width = digits));
+    let changed = ui.input_text(label, &mut buf)
+        .chars_hexadecimal(true)
+        .chars_uppercase(true)
+        .chars_noblank(true)
+        .build();
+    if changed {
+        u32::from_str_radix(buf.to_str(), 16).ok()
+    } else {
+        None
+    }
+}
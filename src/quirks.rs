@@ -0,0 +1,140 @@
+#[cfg(feature = "std")]
+use std::str::FromStr;
+
+/**
+ *  How Fx55/Fx65 (LD [I], Vx / LD Vx, [I]) leave the I register once they are done.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryIncrement {
+    // I is left untouched (SCHIP 1.1)
+    Unchanged,
+    // I = I + x (CHIP-48)
+    X,
+    // I = I + x + 1 (COSMAC VIP, XO-CHIP)
+    XPlusOne,
+}
+
+/**
+ *  The ambiguous parts of the CHIP-8 instruction set. Every interpreter since the
+ *  original COSMAC VIP picked its own answer for these, and ROMs were written against
+ *  whichever interpreter the author had at hand.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Quirks {
+    // 8xy6/8xyE shift Vy into Vx instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+    // What Fx55/Fx65 do to I
+    pub memory_increment: MemoryIncrement,
+    // Bnnn is read as BxNN and jumps to xNN + Vx instead of nnn + V0
+    pub jump_uses_vx: bool,
+    // 8xy1/8xy2/8xy3 reset VF to 0
+    pub logic_resets_vf: bool,
+    // Sprites are clipped at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+    // Dxyn waits for the next 60hz vertical blank before drawing
+    pub display_wait: bool,
+    // Fx0A finishes when the key it saw go down is released, instead of as soon as it's pressed
+    pub key_wait_release: bool,
+}
+
+impl Quirks {
+
+    pub const PRESET_NAMES: [&'static str; 4] = ["vip", "chip48", "schip", "xochip"];
+
+    // Original COSMAC VIP interpreter
+    pub fn vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+            key_wait_release: true,
+        }
+    }
+
+    // CHIP-48 on the HP-48 calculators
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increment: MemoryIncrement::X,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+            key_wait_release: true,
+        }
+    }
+
+    // SUPER-CHIP 1.1
+    pub fn schip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increment: MemoryIncrement::Unchanged,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+            key_wait_release: true,
+        }
+    }
+
+    // Octo / XO-CHIP
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+            key_wait_release: false,
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        let is = |aliases: &[&str]| aliases.iter().any(|a| a.eq_ignore_ascii_case(name));
+        if is(&["vip", "cosmac", "chip8"]) {
+            Some(Quirks::vip())
+        } else if is(&["chip48", "chip-48"]) {
+            Some(Quirks::chip48())
+        } else if is(&["schip", "superchip", "schip1.1"]) {
+            Some(Quirks::schip())
+        } else if is(&["xochip", "xo-chip", "octo"]) {
+            Some(Quirks::xochip())
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Quirks {
+    /**
+     *  The behaviour this emulator has always had: shifts work on Vx alone, I is left alone by
+     *  Fx55/Fx65, Bnnn adds V0, VF survives logic ops and sprites wrap around the screen.
+     *  Fx0A waits for press then release like every original interpreter did.
+     */
+    fn default() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increment: MemoryIncrement::Unchanged,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+            key_wait_release: true,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Quirks::preset(s).ok_or_else(|| {
+            format!("Unknown quirks preset '{}'. Expected one of: {}", s, Quirks::PRESET_NAMES.join(", "))
+        })
+    }
+}
@@ -0,0 +1,295 @@
+use gl::types::*;
+use imgui::{DrawCmd, DrawCmdParams, DrawIdx, DrawVert, TextureId};
+use imgui::internal::RawWrapper;
+use std::ffi::CString;
+use std::mem;
+
+const VERTEX_SHADER: &str = r#"
+#version 330 core
+uniform mat4 ProjMtx;
+layout (location = 0) in vec2 Position;
+layout (location = 1) in vec2 UV;
+layout (location = 2) in vec4 Color;
+out vec2 Frag_UV;
+out vec4 Frag_Color;
+void main() {
+    Frag_UV = UV;
+    Frag_Color = Color;
+    gl_Position = ProjMtx * vec4(Position.xy, 0, 1);
+}
+"#;
+
+const FRAGMENT_SHADER: &str = r#"
+#version 330 core
+uniform sampler2D Texture;
+in vec2 Frag_UV;
+in vec4 Frag_Color;
+out vec4 Out_Color;
+void main() {
+    Out_Color = Frag_Color * texture(Texture, Frag_UV.st);
+}
+"#;
+
+/**
+ *  OpenGL 3.3 renderer for both the chip8 display and the imgui debugger overlay.
+ *  The display is just another textured quad pushed through the same shader as imgui's draw lists.
+ */
+pub struct Renderer {
+    program: GLuint,
+    loc_tex: GLint,
+    loc_proj: GLint,
+    vao: GLuint,
+    vbo: GLuint,
+    ebo: GLuint,
+    font_texture: GLuint,
+    display_texture: GLuint,
+    display_size: (u32, u32),
+}
+
+impl Renderer {
+
+    pub fn new(imgui: &mut imgui::Context) -> Result<Self, String> {
+        unsafe {
+            let program = link_program(VERTEX_SHADER, FRAGMENT_SHADER)?;
+            let loc_tex = gl::GetUniformLocation(program, b"Texture\0".as_ptr() as _);
+            let loc_proj = gl::GetUniformLocation(program, b"ProjMtx\0".as_ptr() as _);
+
+            let mut vao = 0;
+            let mut vbo = 0;
+            let mut ebo = 0;
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut vbo);
+            gl::GenBuffers(1, &mut ebo);
+
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+            let stride = mem::size_of::<DrawVert>() as GLsizei;
+            gl::EnableVertexAttribArray(0);
+            gl::EnableVertexAttribArray(1);
+            gl::EnableVertexAttribArray(2);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, 0 as _);
+            gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, 8 as _);
+            gl::VertexAttribPointer(2, 4, gl::UNSIGNED_BYTE, gl::TRUE, stride, 16 as _);
+            gl::BindVertexArray(0);
+
+            let font_texture = {
+                let mut fonts = imgui.fonts();
+                let atlas = fonts.build_rgba32_texture();
+                let tex = create_texture(gl::LINEAR);
+                gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as _, atlas.width as _, atlas.height as _, 0,
+                    gl::RGBA, gl::UNSIGNED_BYTE, atlas.data.as_ptr() as _);
+                tex
+            };
+            imgui.fonts().tex_id = TextureId::from(font_texture as usize);
+            imgui.io_mut().backend_flags.insert(imgui::BackendFlags::RENDERER_HAS_VTX_OFFSET);
+
+            let display_texture = create_texture(gl::NEAREST);
+
+            Ok(Renderer {
+                program, loc_tex, loc_proj, vao, vbo, ebo,
+                font_texture, display_texture,
+                display_size: (0, 0),
+            })
+        }
+    }
+
+    // pixels is in the XRGB layout produced by Chip8::render_to_pixels
+    pub fn update_display(&mut self, pixels: &[u8], width: u32, height: u32) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.display_texture);
+            if self.display_size != (width, height) {
+                gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as _, width as _, height as _, 0,
+                    gl::BGRA, gl::UNSIGNED_BYTE, pixels.as_ptr() as _);
+                self.display_size = (width, height);
+            } else {
+                gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, width as _, height as _,
+                    gl::BGRA, gl::UNSIGNED_BYTE, pixels.as_ptr() as _);
+            }
+        }
+    }
+
+    pub fn clear(&self, window_size: (u32, u32)) {
+        unsafe {
+            gl::Viewport(0, 0, window_size.0 as _, window_size.1 as _);
+            gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+    }
+
+    // Stretches the chip8 display over the whole window
+    pub fn draw_display(&self, window_size: (u32, u32)) {
+        let (w, h) = (window_size.0 as f32, window_size.1 as f32);
+        let vert = |x: f32, y: f32| DrawVert { pos: [x * w, y * h], uv: [x, y], col: [255; 4] };
+        let vertices = [vert(0.0, 0.0), vert(1.0, 0.0), vert(1.0, 1.0), vert(0.0, 1.0)];
+        let indices: [DrawIdx; 6] = [0, 1, 2, 0, 2, 3];
+
+        unsafe {
+            self.setup_render_state([0.0, 0.0], [w, h], false);
+            upload(&vertices, &indices);
+            gl::BindTexture(gl::TEXTURE_2D, self.display_texture);
+            gl::DrawElements(gl::TRIANGLES, 6, draw_idx_type(), std::ptr::null());
+            gl::BindVertexArray(0);
+        }
+    }
+
+    pub fn render(&self, draw_data: &imgui::DrawData) {
+        let [w, h] = draw_data.display_size;
+        let [scale_w, scale_h] = draw_data.framebuffer_scale;
+        let fb_w = w * scale_w;
+        let fb_h = h * scale_h;
+        if fb_w <= 0.0 || fb_h <= 0.0 {
+            return;
+        }
+
+        unsafe {
+            self.setup_render_state(draw_data.display_pos, draw_data.display_size, true);
+            gl::Enable(gl::SCISSOR_TEST);
+
+            for draw_list in draw_data.draw_lists() {
+                upload(draw_list.vtx_buffer(), draw_list.idx_buffer());
+
+                for cmd in draw_list.commands() {
+                    match cmd {
+                        DrawCmd::Elements { count, cmd_params: DrawCmdParams { clip_rect, texture_id, vtx_offset, idx_offset } } => {
+                            let [pos_x, pos_y] = draw_data.display_pos;
+                            let x = (clip_rect[0] - pos_x) * scale_w;
+                            let y = (clip_rect[1] - pos_y) * scale_h;
+                            let z = (clip_rect[2] - pos_x) * scale_w;
+                            let wc = (clip_rect[3] - pos_y) * scale_h;
+                            if x >= fb_w || y >= fb_h || z < 0.0 || wc < 0.0 {
+                                continue;
+                            }
+                            gl::Scissor(x as GLint, (fb_h - wc) as GLint, (z - x) as GLsizei, (wc - y) as GLsizei);
+                            gl::BindTexture(gl::TEXTURE_2D, texture_id.id() as GLuint);
+                            gl::DrawElementsBaseVertex(
+                                gl::TRIANGLES,
+                                count as GLsizei,
+                                draw_idx_type(),
+                                (idx_offset * mem::size_of::<DrawIdx>()) as _,
+                                vtx_offset as GLint,
+                            );
+                        },
+                        DrawCmd::ResetRenderState => {
+                            self.setup_render_state(draw_data.display_pos, draw_data.display_size, true);
+                        },
+                        DrawCmd::RawCallback { callback, raw_cmd } => callback(draw_list.raw(), raw_cmd),
+                    }
+                }
+            }
+
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::BindVertexArray(0);
+        }
+    }
+
+    unsafe fn setup_render_state(&self, pos: [f32; 2], size: [f32; 2], blend: bool) {
+        if blend {
+            gl::Enable(gl::BLEND);
+            gl::BlendEquation(gl::FUNC_ADD);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        } else {
+            gl::Disable(gl::BLEND);
+        }
+        gl::Disable(gl::CULL_FACE);
+        gl::Disable(gl::DEPTH_TEST);
+
+        let (l, r, t, b) = (pos[0], pos[0] + size[0], pos[1], pos[1] + size[1]);
+        let ortho: [[f32; 4]; 4] = [
+            [2.0 / (r - l), 0.0, 0.0, 0.0],
+            [0.0, 2.0 / (t - b), 0.0, 0.0],
+            [0.0, 0.0, -1.0, 0.0],
+            [(r + l) / (l - r), (t + b) / (b - t), 0.0, 1.0],
+        ];
+        gl::UseProgram(self.program);
+        gl::Uniform1i(self.loc_tex, 0);
+        gl::UniformMatrix4fv(self.loc_proj, 1, gl::FALSE, ortho[0].as_ptr());
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindVertexArray(self.vao);
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.font_texture);
+            gl::DeleteTextures(1, &self.display_texture);
+            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteBuffers(1, &self.ebo);
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.program);
+        }
+    }
+}
+
+fn draw_idx_type() -> GLenum {
+    if mem::size_of::<DrawIdx>() == 2 { gl::UNSIGNED_SHORT } else { gl::UNSIGNED_INT }
+}
+
+unsafe fn upload(vertices: &[DrawVert], indices: &[DrawIdx]) {
+    gl::BufferData(gl::ARRAY_BUFFER, mem::size_of_val(vertices) as _,
+        vertices.as_ptr() as _, gl::STREAM_DRAW);
+    gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, mem::size_of_val(indices) as _,
+        indices.as_ptr() as _, gl::STREAM_DRAW);
+}
+
+unsafe fn create_texture(filter: GLenum) -> GLuint {
+    let mut tex = 0;
+    gl::GenTextures(1, &mut tex);
+    gl::BindTexture(gl::TEXTURE_2D, tex);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as _);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as _);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as _);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
+    tex
+}
+
+unsafe fn compile_shader(kind: GLenum, source: &str) -> Result<GLuint, String> {
+    let shader = gl::CreateShader(kind);
+    let source = CString::new(source).unwrap();
+    gl::ShaderSource(shader, 1, &source.as_ptr(), std::ptr::null());
+    gl::CompileShader(shader);
+
+    let mut status = 0;
+    gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
+    if status == 0 {
+        let log = info_log(shader, gl::GetShaderiv, gl::GetShaderInfoLog);
+        gl::DeleteShader(shader);
+        return Err(format!("Error compiling shader :: {}", log));
+    }
+    Ok(shader)
+}
+
+unsafe fn link_program(vertex: &str, fragment: &str) -> Result<GLuint, String> {
+    let vs = compile_shader(gl::VERTEX_SHADER, vertex)?;
+    let fs = compile_shader(gl::FRAGMENT_SHADER, fragment)?;
+    let program = gl::CreateProgram();
+    gl::AttachShader(program, vs);
+    gl::AttachShader(program, fs);
+    gl::LinkProgram(program);
+    gl::DeleteShader(vs);
+    gl::DeleteShader(fs);
+
+    let mut status = 0;
+    gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
+    if status == 0 {
+        let log = info_log(program, gl::GetProgramiv, gl::GetProgramInfoLog);
+        gl::DeleteProgram(program);
+        return Err(format!("Error linking shader program :: {}", log));
+    }
+    Ok(program)
+}
+
+unsafe fn info_log(
+    object: GLuint,
+    get_iv: unsafe fn(GLuint, GLenum, *mut GLint),
+    get_log: unsafe fn(GLuint, GLsizei, *mut GLsizei, *mut GLchar),
+) -> String {
+    let mut len = 0;
+    get_iv(object, gl::INFO_LOG_LENGTH, &mut len);
+    let mut buf = vec![0u8; len.max(1) as usize];
+    get_log(object, len, std::ptr::null_mut(), buf.as_mut_ptr() as _);
+    String::from_utf8_lossy(&buf).trim_end_matches('\0').to_string()
+}
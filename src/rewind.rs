@@ -0,0 +1,316 @@
+use crate::chip8::Chip8;
+use crate::savestate::{StateReader, StateWriter};
+use std::collections::VecDeque;
+
+pub const DEFAULT_SECONDS: u32 = 60;
+pub const DEFAULT_INTERVAL: u32 = 1;
+// Snapshots are taken from the 60hz timer tick
+const FRAMES_PER_SECOND: u32 = 60;
+// Cap on the deltas kept, whatever the depth asked for. A program rewriting a lot of memory every
+// frame can make deltas nearly as big as snapshots, ~74 KB since XO-CHIP's 64K of memory
+const MAX_HISTORY_BYTES: usize = 64 << 20;
+
+enum Entry {
+    // XOR against the next newer snapshot, zero runs packed by pack_delta()
+    Delta(Vec<u8>),
+    // Snapshot size changed in between (hires toggled), so no delta to take
+    Full(Vec<u8>),
+}
+
+impl Entry {
+    fn len(&self) -> usize {
+        match self {
+            Entry::Delta(bytes) | Entry::Full(bytes) => bytes.len(),
+        }
+    }
+}
+
+/**
+ *  Ring buffer of machine snapshots for stepping back through gameplay.
+ *  Only the newest snapshot is kept whole, every older one is stored as the packed XOR
+ *  against its successor. Most of memory doesn't change from frame to frame so a delta
+ *  is usually tens of bytes instead of the ~74 KB a full snapshot takes (64K of memory,
+ *  a byte per screen cell and the registers), a minute of history well under a megabyte.
+ */
+pub struct Rewind {
+    head: Option<Vec<u8>>,
+    history: VecDeque<Entry>,
+    // Bytes held in history
+    history_bytes: usize,
+    capacity: usize,
+    interval: u32,
+    frame: u32,
+}
+
+impl Rewind {
+
+    /**
+     *  seconds: how far back we can go, 0 turns rewinding off.
+     *  interval: take a snapshot every interval frames (at 60hz), higher trades smoothness for memory
+     */
+    pub fn new(seconds: u32, interval: u32) -> Self {
+        let interval = interval.max(1);
+        Rewind {
+            head: None,
+            history: VecDeque::new(),
+            history_bytes: 0,
+            capacity: (seconds.saturating_mul(FRAMES_PER_SECOND) / interval) as usize,
+            interval,
+            frame: 0,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    // Bytes the snapshots take up altogether
+    pub fn memory_used(&self) -> usize {
+        self.head.as_ref().map_or(0, Vec::len) + self.history_bytes
+    }
+
+    // How many steps back there are
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.head = None;
+        self.history.clear();
+        self.history_bytes = 0;
+        self.frame = 0;
+    }
+
+    /**
+     *  Call once per timer frame while the game runs
+     */
+    pub fn record(&mut self, chip8: &Chip8) {
+        if !self.enabled() {
+            return;
+        }
+        self.frame += 1;
+        if self.frame < self.interval {
+            return;
+        }
+        self.frame = 0;
+
+        let mut w = StateWriter::new();
+        chip8.save_state(&mut w);
+        let snapshot = w.into_inner();
+
+        if let Some(prev) = self.head.take() {
+            let entry = if prev.len() == snapshot.len() {
+                Entry::Delta(pack_delta(&prev, &snapshot))
+            } else {
+                Entry::Full(prev)
+            };
+            self.history_bytes += entry.len();
+            self.history.push_back(entry);
+            // The head counts towards capacity too
+            while self.history.len() >= self.capacity || self.history_bytes > MAX_HISTORY_BYTES {
+                if let Some(oldest) = self.history.pop_front() {
+                    self.history_bytes -= oldest.len();
+                }
+            }
+        }
+        self.head = Some(snapshot);
+    }
+
+    /**
+     *  Restores the previous snapshot into chip8, gfx included.
+     *  Returns false once history runs out, leaving chip8 at the oldest state we have.
+     *  Keys currently held are left alone so they don't get stuck down or up after rewinding.
+     */
+    pub fn step_back(&mut self, chip8: &mut Chip8) -> Result<bool, String> {
+        let head = match self.head.as_mut() {
+            Some(head) => head,
+            None => return Ok(false)
+        };
+        let entry = self.history.pop_back();
+        self.history_bytes -= entry.as_ref().map_or(0, Entry::len);
+        let stepped = match entry {
+            Some(Entry::Delta(delta)) => {
+                unpack_delta(&delta, head)?;
+                true
+            },
+            Some(Entry::Full(prev)) => {
+                *head = prev;
+                true
+            },
+            None => false
+        };
+
+        let keyboard = *chip8.keyboard();
+        chip8.load_state(&mut StateReader::new(head))?;
+        *chip8.keyboard_mut() = keyboard;
+        self.frame = 0;
+        Ok(stepped)
+    }
+}
+
+/**
+ *  XOR of two equal length buffers, encoded as repeating
+ *    varint  count of unchanged (zero) bytes
+ *    varint  count of changed bytes
+ *    ...     that many XORed bytes
+ */
+fn pack_delta(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < a.len() {
+        let zeros = common_prefix(&a[pos..], &b[pos..]);
+        pos += zeros;
+        let changed = a[pos..].iter().zip(&b[pos..]).take_while(|(x, y)| x != y).count();
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, changed);
+        out.extend(a[pos..pos + changed].iter().zip(&b[pos..pos + changed]).map(|(x, y)| x ^ y));
+        pos += changed;
+    }
+    out
+}
+
+// Most of a snapshot is unchanged, so it's skipped a block at a time (a memcmp) rather than byte by byte
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    const BLOCK: usize = 64;
+    let len = a.len().min(b.len());
+    let mut n = 0;
+    while n + BLOCK <= len && a[n..n + BLOCK] == b[n..n + BLOCK] {
+        n += BLOCK;
+    }
+    n + a[n..].iter().zip(&b[n..]).take_while(|(x, y)| x == y).count()
+}
+
+// XORs a pack_delta() result back onto target, turning one side of the pair into the other
+fn unpack_delta(delta: &[u8], target: &mut [u8]) -> Result<(), String> {
+    let corrupt = || "Rewind history is corrupt".to_string();
+    let mut src = delta.iter();
+    let mut pos = 0;
+    while src.len() > 0 {
+        pos += read_varint(&mut src).ok_or_else(corrupt)?;
+        let changed = read_varint(&mut src).ok_or_else(corrupt)?;
+        if pos + changed > target.len() || changed > src.len() {
+            return Err(corrupt());
+        }
+        for (dst, x) in target[pos..pos + changed].iter_mut().zip(src.by_ref()) {
+            *dst ^= x;
+        }
+        pos += changed;
+    }
+    Ok(())
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7F) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint(src: &mut std::slice::Iter<u8>) -> Option<usize> {
+    let mut n = 0;
+    let mut shift = 0;
+    loop {
+        let b = *src.next()?;
+        n |= ((b & 0x7F) as usize) << shift;
+        if b & 0x80 == 0 {
+            return Some(n);
+        }
+        shift += 7;
+        if shift >= usize::BITS as usize {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    fn round_trip(a: &[u8], b: &[u8]) -> Vec<u8> {
+        let delta = pack_delta(a, b);
+        let mut target = b.to_vec();
+        unpack_delta(&delta, &mut target).unwrap();
+        assert_eq!(target, a);
+        unpack_delta(&delta, &mut target).unwrap();
+        assert_eq!(target, b);
+        delta
+    }
+
+    #[test]
+    fn delta_round_trips() {
+        let a: Vec<u8> = (0..1000u32).map(|n| (n * 7 % 251) as u8).collect();
+        let mut b = a.clone();
+        // Runs long enough to need more than one varint byte, at the start, middle and end
+        b[0] ^= 1;
+        for byte in &mut b[300..600] {
+            *byte = byte.wrapping_add(1);
+        }
+        b[999] ^= 0x80;
+        round_trip(&a, &b);
+        assert!(round_trip(&a, &a).len() <= 3);
+        round_trip(&[], &[]);
+    }
+
+    #[test]
+    fn corrupt_delta_is_an_error() {
+        let mut target = vec![0; 16];
+        // Says 20 changed bytes follow, only 2 do
+        assert!(unpack_delta(&[0, 20, 1, 2], &mut target).is_err());
+        // Changes run past the end of the target
+        assert!(unpack_delta(&[15, 2, 1, 2], &mut target).is_err());
+        // A varint that never ends
+        assert!(unpack_delta(&[0x80; 12], &mut target).is_err());
+    }
+
+    // LD V0, 0; LD F, V0; DRW V0, V1, 5; ADD V1, 1; LD DT, V1; JP 204 moving the 0 down the screen
+    const ROM: &[u8] = &[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x15, 0x71, 0x01, 0xF1, 0x15, 0x12, 0x04];
+
+    #[test]
+    fn steps_back_through_frames() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(ROM).unwrap();
+        let mut rewind = Rewind::new(1, 1);
+        let mut frames = Vec::new();
+        for _ in 0..90 {
+            chip8.step_frame(4).unwrap();
+            rewind.record(&chip8);
+            frames.push((chip8.v()[1], chip8.render_to_pixels()));
+        }
+        // A second at one snapshot a frame, the head included
+        assert_eq!(rewind.len(), 59);
+
+        for (v1, pixels) in frames.iter().rev().skip(1).take(59) {
+            assert!(rewind.step_back(&mut chip8).unwrap());
+            assert_eq!(chip8.v()[1], *v1);
+            assert!(chip8.render_to_pixels() == *pixels);
+        }
+        assert!(!rewind.step_back(&mut chip8).unwrap());
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn a_minute_of_history_fits_in_a_megabyte() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(ROM).unwrap();
+        let mut rewind = Rewind::new(DEFAULT_SECONDS, DEFAULT_INTERVAL);
+        for _ in 0..DEFAULT_SECONDS * FRAMES_PER_SECOND {
+            chip8.step_frame(4).unwrap();
+            rewind.record(&chip8);
+        }
+        let snapshot = 0x10000 + 64 * 32;
+        assert!(rewind.memory_used() > snapshot);
+        assert!(rewind.memory_used() < 1 << 20, "{} bytes", rewind.memory_used());
+    }
+
+    #[test]
+    fn huge_rewind_doesnt_overflow() {
+        assert_eq!(Rewind::new(u32::MAX, 1).capacity, u32::MAX as usize);
+        assert!(Rewind::new(u32::MAX, u32::MAX).enabled());
+    }
+}
@@ -0,0 +1,53 @@
+/**
+ *  Where Cxkk gets its random numbers from. Implement it over a hardware RNG on embedded targets,
+ *  or a fixed sequence to make runs reproducible. Closures returning a u8 work as well.
+ */
+pub trait Rng {
+    fn next_u8(&mut self) -> u8;
+}
+
+impl<F: FnMut() -> u8> Rng for F {
+    fn next_u8(&mut self) -> u8 {
+        self()
+    }
+}
+
+/**
+ *  Chip8's default, a xorshift32 generator. Plenty random for games and needs nothing from the
+ *  platform. Seeded from the OS with std, from a fixed value without.
+ */
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u32,
+}
+
+impl XorShift {
+    pub fn new(seed: u32) -> Self {
+        // An all zero state would only ever produce zeros
+        XorShift { state: if seed == 0 { 0x2545_F491 } else { seed } }
+    }
+}
+
+impl Default for XorShift {
+    #[cfg(feature = "std")]
+    fn default() -> Self {
+        XorShift::new(rand::random())
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn default() -> Self {
+        XorShift::new(0)
+    }
+}
+
+impl Rng for XorShift {
+    fn next_u8(&mut self) -> u8 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        // The high bits are the most random
+        (x >> 24) as u8
+    }
+}
@@ -0,0 +1,186 @@
+use crate::chip8::{MemoryAccess, Opcode, MEMORY_SIZE};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+// Per byte of memory, what has happened to it so far
+const LOADED: u8 = 0b0001; // Font or ROM, present before the program started
+const WRITTEN: u8 = 0b0010;
+const EXECUTED: u8 = 0b0100;
+const READ: u8 = 0b1000; // Drawn as a sprite or loaded into registers/the audio buffer
+
+// Classic interpreters only had 4 KB, anything above is XO-CHIP only
+const CLASSIC_MEMORY_END: u16 = 0x0FFF;
+// The COSMAC VIP interpreter only had room for 12 return addresses
+const VIP_STACK_DEPTH: u16 = 12;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    UninitialisedRead,
+    UninitialisedExecute,
+    ExecutedData,
+    CodeAsData,
+    SelfModifyingCode,
+    HighMemory,
+    SchipInLowres,
+    DeepStack,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Category::UninitialisedRead => "read memory that was never loaded or written",
+            Category::UninitialisedExecute => "executed memory that was never loaded or written",
+            Category::ExecutedData => "executed bytes that were drawn or read as data",
+            Category::CodeAsData => "drew or read bytes that were executed as code",
+            Category::SelfModifyingCode => "wrote over bytes that were executed as code",
+            Category::HighMemory => "accessed memory past 0xFFF (XO-CHIP only)",
+            Category::SchipInLowres => "SCHIP only instruction in low-res mode",
+            Category::DeepStack => "call stack deeper than the VIP's 12 levels",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Warning {
+    pub opcode: u16,
+    // First address the problem was seen at, pc itself for execution and opcode warnings
+    pub addr: u16,
+    pub count: u32,
+}
+
+/**
+ *  Opt in strict mode for Chip8 (Chip8::enable_sanitizer). Watches every instruction that runs and
+ *  the memory it touches, and collects the patterns that only work by accident on this emulator.
+ *  Warnings are grouped by category and the pc of the instruction responsible.
+ */
+#[derive(Clone)]
+pub struct Sanitizer {
+    memory: Vec<u8>,
+    warnings: BTreeMap<(Category, u16), Warning>,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Sanitizer::new()
+    }
+}
+
+impl Sanitizer {
+    pub fn new() -> Self {
+        Sanitizer {
+            memory: vec![0; MEMORY_SIZE],
+            warnings: BTreeMap::new(),
+        }
+    }
+
+    // Memory holding the font or ROM when the program starts
+    pub fn loaded(&mut self, start: usize, len: usize) {
+        for flags in self.memory[start..start + len].iter_mut() {
+            *flags |= LOADED;
+        }
+    }
+
+    /**
+     *  Called by Chip8::cycle after each instruction it runs.
+     *  hires is the display mode the instruction ran in, sp the stack pointer after it.
+     */
+    pub fn record(&mut self, pc: u16, opcode: u16, hires: bool, sp: u16, access: MemoryAccess) {
+        // Problems found with this instruction, each category only counts once per run of it
+        let mut found: Vec<(Category, u16)> = Vec::new();
+        let op = Opcode(opcode);
+        for addr in pc as usize..(pc as usize + op.size() as usize).min(MEMORY_SIZE) {
+            let flags = self.memory[addr];
+            if flags & (LOADED | WRITTEN) == 0 {
+                found.push((Category::UninitialisedExecute, addr as u16));
+            } else if flags & READ != 0 && flags & EXECUTED == 0 {
+                found.push((Category::ExecutedData, addr as u16));
+            }
+            self.memory[addr] |= EXECUTED;
+        }
+        if pc > CLASSIC_MEMORY_END {
+            found.push((Category::HighMemory, pc));
+        }
+
+        let schip_only = match opcode & 0xF0FF {
+            0x00C0..=0x00CF | 0x00FB | 0x00FC | 0xF030 => true,
+            _ => opcode & 0xF00F == 0xD000,
+        };
+        if schip_only && !hires {
+            found.push((Category::SchipInLowres, pc));
+        }
+        if sp > VIP_STACK_DEPTH {
+            found.push((Category::DeepStack, pc));
+        }
+
+        if let Some(read) = access.read {
+            for addr in read.start as usize..read.start as usize + read.len as usize {
+                let flags = self.memory[addr];
+                if flags & (LOADED | WRITTEN) == 0 {
+                    found.push((Category::UninitialisedRead, addr as u16));
+                } else if flags & EXECUTED != 0 {
+                    found.push((Category::CodeAsData, addr as u16));
+                }
+                self.memory[addr] |= READ;
+            }
+            if read.end() > CLASSIC_MEMORY_END {
+                found.push((Category::HighMemory, read.end()));
+            }
+        }
+        if let Some(write) = access.write {
+            for addr in write.start as usize..write.start as usize + write.len as usize {
+                if self.memory[addr] & EXECUTED != 0 {
+                    found.push((Category::SelfModifyingCode, addr as u16));
+                }
+                self.memory[addr] |= WRITTEN;
+            }
+            if write.end() > CLASSIC_MEMORY_END {
+                found.push((Category::HighMemory, write.end()));
+            }
+        }
+
+        found.sort_by_key(|(category, _)| *category);
+        found.dedup_by_key(|(category, _)| *category);
+        for (category, addr) in found {
+            self.warn(category, pc, opcode, addr);
+        }
+    }
+
+    fn warn(&mut self, category: Category, pc: u16, opcode: u16, addr: u16) {
+        self.warnings.entry((category, pc))
+            .or_insert(Warning { opcode, addr, count: 0 })
+            .count += 1;
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = (Category, u16, &Warning)> {
+        self.warnings.iter().map(|((category, pc), w)| (*category, *pc, w))
+    }
+
+    /**
+     *  Every warning grouped by category:
+     *      read memory that was never loaded or written
+     *        0234  F265  at 0F00, 12 times
+     */
+    pub fn report(&self) -> String {
+        if self.warnings.is_empty() {
+            return "Sanitizer: no problems found\n".to_string();
+        }
+        let mut out = format!("Sanitizer: {} warnings\n", self.warnings.len());
+        let mut last = None;
+        for (category, pc, w) in self.warnings() {
+            if last != Some(category) {
+                writeln!(out, "  {}", category).unwrap();
+                last = Some(category);
+            }
+            write!(out, "    {:04X}  {:04X}", pc, w.opcode).unwrap();
+            if w.addr != pc {
+                write!(out, "  at {:04X}", w.addr).unwrap();
+            }
+            if w.count > 1 {
+                write!(out, ", {} times", w.count).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
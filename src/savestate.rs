@@ -0,0 +1,288 @@
+use crate::chip8::Chip8;
+use crate::quirks::{MemoryIncrement, Quirks};
+use sha1::{Digest, Sha1};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"RC8S";
+// Bump whenever Chip8::save_state/load_state change what they write
+pub const VERSION: u16 = 2;
+
+pub const SLOT_COUNT: u8 = 10;
+
+pub type RomHash = [u8; 20];
+
+pub fn rom_hash(rom: &[u8]) -> RomHash {
+    Sha1::digest(rom).into()
+}
+
+pub fn hash_to_hex(hash: &RomHash) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/**
+ *  Little endian byte writer used to serialize the machine state
+ */
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::default()
+    }
+
+    pub fn u8(&mut self, v: u8) { self.buf.push(v); }
+    pub fn bool(&mut self, v: bool) { self.buf.push(v as u8); }
+    pub fn u16(&mut self, v: u16) { self.buf.extend_from_slice(&v.to_le_bytes()); }
+    pub fn bytes(&mut self, v: &[u8]) { self.buf.extend_from_slice(v); }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/**
+ *  Reads back what StateWriter wrote, running off the end is an error rather than a panic
+ */
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.pos + len > self.data.len() {
+            return Err(format!("Save state is truncated, expected {} more bytes at offset {}", len, self.pos));
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> { Ok(self.bytes(1)?[0]) }
+    pub fn bool(&mut self) -> Result<bool, String> { Ok(self.u8()? != 0) }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
+
+fn write_quirks(w: &mut StateWriter, q: &Quirks) {
+    w.bool(q.shift_uses_vy);
+    w.u8(match q.memory_increment {
+        MemoryIncrement::Unchanged => 0,
+        MemoryIncrement::X => 1,
+        MemoryIncrement::XPlusOne => 2,
+    });
+    w.bool(q.jump_uses_vx);
+    w.bool(q.logic_resets_vf);
+    w.bool(q.clip_sprites);
+    w.bool(q.display_wait);
+    w.bool(q.key_wait_release);
+}
+
+fn read_quirks(r: &mut StateReader) -> Result<Quirks, String> {
+    Ok(Quirks {
+        shift_uses_vy: r.bool()?,
+        memory_increment: match r.u8()? {
+            0 => MemoryIncrement::Unchanged,
+            1 => MemoryIncrement::X,
+            2 => MemoryIncrement::XPlusOne,
+            n => return Err(format!("Save state has an invalid memory increment quirk ({})", n))
+        },
+        jump_uses_vx: r.bool()?,
+        logic_resets_vf: r.bool()?,
+        clip_sprites: r.bool()?,
+        display_wait: r.bool()?,
+        key_wait_release: r.bool()?,
+    })
+}
+
+/**
+ *  Save state file layout:
+ *    "RC8S"  magic
+ *    u16     format VERSION
+ *    [u8;20] SHA-1 of the ROM the state was taken from
+ *    quirks  active when the state was taken
+ *    ...     Chip8::save_state
+ */
+pub fn encode(chip8: &Chip8, rom: &RomHash) -> Vec<u8> {
+    let mut w = StateWriter::new();
+    w.bytes(MAGIC);
+    w.u16(VERSION);
+    w.bytes(rom);
+    write_quirks(&mut w, &chip8.quirks());
+    chip8.save_state(&mut w);
+    w.into_inner()
+}
+
+/**
+ *  Validates a state against the running ROM and restores it into chip8.
+ *  Nothing is touched unless the whole state decodes cleanly.
+ */
+pub fn decode_into(data: &[u8], rom: &RomHash, chip8: &mut Chip8) -> Result<(), String> {
+    let mut r = StateReader::new(data);
+    if r.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err("Not a save state file".to_string());
+    }
+    let version = r.u16()?;
+    if version != VERSION {
+        return Err(format!("Save state is format version {}, this build only reads version {}", version, VERSION));
+    }
+    let hash = r.bytes(20)?;
+    if hash != &rom[..] {
+        return Err(format!("Save state belongs to a different ROM (sha1 {}, running {})",
+            hash.iter().map(|b| format!("{:02x}", b)).collect::<String>(), hash_to_hex(rom)));
+    }
+    let quirks = read_quirks(&mut r)?;
+
+    let mut restored = chip8.clone();
+    restored.set_quirks(quirks);
+    restored.load_state(&mut r)?;
+    if !r.is_empty() {
+        return Err("Save state has trailing data, it may be corrupt".to_string());
+    }
+    *chip8 = restored;
+    Ok(())
+}
+
+// Restores a state file, a slot or one copied out of the saves directory, into chip8
+pub fn load_file(path: &Path, rom: &RomHash, chip8: &mut Chip8) -> Result<(), String> {
+    let data = std::fs::read(path)
+        .map_err(|e| format!("Unable to read save state '{}' :: {}", path.display(), e))?;
+    decode_into(&data, rom, chip8)
+}
+
+/**
+ *  Numbered quick-save slots, kept per ROM in <data dir>/rusty-chip8-emu/saves/<rom sha1>/
+ */
+pub struct SaveSlots {
+    dir: PathBuf,
+    rom: RomHash,
+}
+
+impl SaveSlots {
+
+    pub fn new(rom: RomHash) -> Self {
+        SaveSlots {
+            dir: data_dir().join("saves").join(hash_to_hex(&rom)),
+            rom,
+        }
+    }
+
+    pub fn path(&self, slot: u8) -> PathBuf {
+        self.dir.join(format!("slot{}.state", slot))
+    }
+
+    pub fn save(&self, slot: u8, chip8: &Chip8) -> Result<PathBuf, String> {
+        let path = self.path(slot);
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Unable to create save directory '{}' :: {}", self.dir.display(), e))?;
+        std::fs::write(&path, encode(chip8, &self.rom))
+            .map_err(|e| format!("Unable to write save state '{}' :: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    pub fn load(&self, slot: u8, chip8: &mut Chip8) -> Result<PathBuf, String> {
+        let path = self.path(slot);
+        load_file(&path, &self.rom, chip8)?;
+        Ok(path)
+    }
+}
+
+// $XDG_DATA_HOME/rusty-chip8-emu, falling back to the platform's usual spot
+pub fn data_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_DATA_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local").join("share")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("rusty-chip8-emu")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0, 7; LD I, 300; LD F, V0; DRW V0, V0, 5; LD DT, V0; CALL 20C; JP 20A
+    const ROM: &[u8] = &[0x60, 0x07, 0xA3, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0xF0, 0x15, 0x22, 0x0C, 0x12, 0x0A];
+
+    // A machine part way through ROM, with something on screen and on the stack
+    fn running() -> Chip8 {
+        let mut chip8 = Chip8::new(Quirks::schip());
+        chip8.load_rom(ROM).unwrap();
+        for _ in 0..6 {
+            chip8.cycle().unwrap();
+        }
+        chip8.memory_mut()[0x300] = 0xAB;
+        chip8
+    }
+
+    #[test]
+    fn round_trip() {
+        let chip8 = running();
+        let hash = rom_hash(ROM);
+        let state = encode(&chip8, &hash);
+
+        let mut restored = Chip8::new(Quirks::default());
+        decode_into(&state, &hash, &mut restored).unwrap();
+        assert_eq!(restored.quirks(), Quirks::schip());
+        assert_eq!((restored.pc(), restored.i(), restored.sp()), (chip8.pc(), chip8.i(), chip8.sp()));
+        assert_eq!(restored.v(), chip8.v());
+        assert_eq!(restored.stack(), chip8.stack());
+        assert_eq!(restored.delay_timer(), 7);
+        assert_eq!(restored.memory(), chip8.memory());
+        assert_eq!(restored.render_to_pixels(), chip8.render_to_pixels());
+        // Taking it again gives the same bytes
+        assert_eq!(encode(&restored, &hash), state);
+    }
+
+    // A state that doesn't decode leaves the machine as it was
+    fn rejected(state: &[u8], hash: &RomHash) -> String {
+        let mut chip8 = Chip8::new(Quirks::default());
+        chip8.load_rom(ROM).unwrap();
+        let err = decode_into(state, hash, &mut chip8).unwrap_err();
+        assert_eq!((chip8.pc(), chip8.quirks()), (0x200, Quirks::default()));
+        err
+    }
+
+    #[test]
+    fn rejects_another_roms_state() {
+        let state = encode(&running(), &rom_hash(ROM));
+        let err = rejected(&state, &rom_hash(b"another rom"));
+        assert!(err.contains("different ROM"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut state = encode(&running(), &rom_hash(ROM));
+        state[0] = b'X';
+        assert_eq!(rejected(&state, &rom_hash(ROM)), "Not a save state file");
+    }
+
+    #[test]
+    fn rejects_a_newer_version() {
+        let mut state = encode(&running(), &rom_hash(ROM));
+        state[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let err = rejected(&state, &rom_hash(ROM));
+        assert!(err.contains(&format!("version {}", VERSION + 1)), "{}", err);
+    }
+
+    #[test]
+    fn rejects_truncated_and_trailing_data() {
+        let state = encode(&running(), &rom_hash(ROM));
+        assert!(rejected(&state[..state.len() - 1], &rom_hash(ROM)).contains("truncated"));
+        let mut longer = state.clone();
+        longer.push(0);
+        assert!(rejected(&longer, &rom_hash(ROM)).contains("trailing data"));
+    }
+}
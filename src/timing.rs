@@ -0,0 +1,125 @@
+use crate::chip8::{Chip8, Opcode};
+use std::str::FromStr;
+
+/**
+ *  Machine cycles the VIP interpreter gets every 60hz frame. The 1802 runs 3668 machine cycles
+ *  per frame (1.76 MHz, 8 clocks each), 1024 of those go to video DMA for the 64x32 display
+ *  and roughly 46 to the interrupt routine that counts the timers down.
+ */
+pub const VIP_CYCLES_PER_FRAME: i64 = 3668 - 1024 - 46;
+
+/**
+ *  How fast instructions run.
+ *  Fixed: a flat number of instructions per second whatever they are, what this emulator always did.
+ *  Vip: each instruction costs what it did on the COSMAC VIP, against a per frame cycle budget, and
+ *       Dxyn waits for the start of a frame before drawing.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Timing {
+    #[default]
+    Fixed,
+    Vip,
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fixed" => Ok(Timing::Fixed),
+            "vip" | "cosmac" => Ok(Timing::Vip),
+            _ => Err(format!("Unknown timing '{}'. Expected one of: fixed, vip", s))
+        }
+    }
+}
+
+/**
+ *  Hands out each frame's VIP cycle budget one instruction at a time:
+ *      clock.start_frame();
+ *      while clock.admit(&chip8) { chip8.cycle(); }
+ *  An instruction that runs past the end of the frame is paid for out of the next one.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct VipClock {
+    budget: i64,
+    // Nothing has run yet this frame, so we're right after the interrupt
+    frame_start: bool,
+}
+
+impl VipClock {
+    pub fn new() -> Self {
+        VipClock::default()
+    }
+
+    pub fn start_frame(&mut self) {
+        self.budget = self.budget.min(0) + VIP_CYCLES_PER_FRAME;
+        self.frame_start = true;
+    }
+
+    // True if the instruction at pc gets to run in what's left of this frame, which is charged for it
+    pub fn admit(&mut self, chip8: &Chip8) -> bool {
+        // Fx0A idles in the interpreter's keyboard loop until the next frame
+        if self.budget <= 0 || chip8.key_wait().is_some() {
+            return false;
+        }
+        let opcode = chip8.opcode_at(chip8.pc());
+        // The VIP interpreter waits for the vertical interrupt before drawing, the rest of the frame goes idle
+        if opcode & 0xF000 == 0xD000 && !self.frame_start {
+            self.budget = 0;
+            return false;
+        }
+        self.budget -= vip_cycles(chip8, opcode) as i64;
+        self.frame_start = false;
+        true
+    }
+}
+
+/**
+ *  Machine cycles the VIP interpreter takes to run opcode in chip8's current state.
+ *  Averages from Laurence Scotford's disassembly of the interpreter, with the parts that vary
+ *  the most (skips, sprite rows and alignment, BCD digits, registers moved) worked out per call.
+ *  Instructions the VIP didn't have are charged like a jump.
+ */
+pub fn vip_cycles(chip8: &Chip8, opcode: u16) -> u32 {
+    let op = Opcode(opcode);
+    let vx = chip8.v()[op.x()];
+    let vy = chip8.v()[op.y()];
+    // Taking a skip costs one more fetch
+    let skip = |taken: bool| if taken { 4 } else { 0 };
+
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => 678,
+            _ => 23,
+        },
+        0x1000 | 0x2000 | 0xB000 => 23,
+        0x3000 => 10 + skip(vx == op.kk()),
+        0x4000 => 10 + skip(vx != op.kk()),
+        0x5000 => 14 + skip(vx == vy),
+        0x6000 => 6,
+        0x7000 => 10,
+        0x8000 => 44,
+        0x9000 => 14 + skip(vx != vy),
+        0xA000 => 12,
+        0xC000 => 36,
+        // Each row is shifted into place a bit at a time, then XORed into one byte of the display or two
+        0xD000 => {
+            let shift = (vx % 8) as u32;
+            let row = if shift == 0 { 22 } else { 34 + 2 * shift };
+            26 + op.n() as u32 * row
+        },
+        0xE000 => {
+            let down = chip8.keyboard()[(vx & 0xF) as usize];
+            14 + skip(if op.kk() == 0x9E { down } else { !down })
+        },
+        _ => match opcode & 0xF0FF {
+            0xF007 | 0xF00A | 0xF015 | 0xF018 => 10,
+            0xF01E => 19,
+            0xF029 => 20,
+            // BCD is worked out by repeated subtraction, once per unit of each digit
+            0xF033 => 60 + 14 * (vx / 100 + vx / 10 % 10 + vx % 10) as u32,
+            0xF055 | 0xF065 => 10 + 14 * (op.x() as u32 + 1),
+            _ => 23,
+        },
+    }
+}
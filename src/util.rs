@@ -4,7 +4,7 @@ use fmt::*;
 pub trait Array2DShow: Default + Clone + Display {}
 impl<T: Default + Clone + Display> Array2DShow for T {}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Flat2DArray<T: Array2DShow = u8> {
     width: usize,
     pub data: Vec<T>
@@ -52,13 +52,24 @@ impl FrameTimer {
     */
     pub fn frame(&mut self) -> bool {
         let acc = self.accum_elapsed();
-        if acc > self.target_delta {
-            true
-        } else {
-            false
+        acc > self.target_delta
+    }
+
+    /**
+     *  For timers that can fall more than one update behind between calls: returns how many
+     *  updates are due and keeps the leftover time. More than max due (after a stall) runs max
+     *  and drops the rest rather than racing to catch up.
+    */
+    pub fn frames(&mut self, max: u32) -> u32 {
+        let due = (self.accum_elapsed() / self.target_delta) as u32;
+        if due > max {
+            self.accum = 0.0;
+            return max;
         }
+        self.accum -= due as f32 * self.target_delta;
+        due
     }
-    
+
 }
 
 impl<T> Flat2DArray<T> where T: Array2DShow {
@@ -81,6 +92,14 @@ impl<T> Flat2DArray<T> where T: Array2DShow {
     pub fn set(&mut self, x: usize, y: usize, value: T) {
         self.data[y * self.width + x] = value;
     }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.data.len() / self.width
+    }
 }
 
 impl<T> Display for Flat2DArray<T> where T: Array2DShow {
@@ -89,7 +108,7 @@ impl<T> Display for Flat2DArray<T> where T: Array2DShow {
         for (i, c) in self.data.iter().enumerate() {
             write!(display, " {} |", c)?;
             if (i + 1) % self.width == 0 {
-                write!(display, "\n")?;
+                writeln!(display)?;
             }
         }
         write!(formatter, "{}", display)
@@ -0,0 +1,299 @@
+use crate::cdp1802::{Bus, Cdp1802};
+
+// 4 KB of RAM, repeated through the lower half of the address space
+pub const RAM_SIZE: usize = 0x1000;
+const RAM_END: u16 = 0x8000;
+pub const PROGRAM_START: u16 = 0x200;
+// The interpreter keeps its stack, variables and display buffer in the top 352 bytes of RAM
+const PROGRAM_END: u16 = 0xEA0;
+pub const INTERPRETER_SIZE: usize = PROGRAM_START as usize;
+pub const MONITOR_SIZE: usize = 0x200;
+
+// CDP1861 video timing, in machine cycles
+const CYCLES_PER_LINE: u32 = 14;
+const LINES_PER_FRAME: u32 = 262;
+pub const CYCLES_PER_FRAME: u32 = CYCLES_PER_LINE * LINES_PER_FRAME;
+const DISPLAY_START_LINE: u32 = 80;
+pub const DISPLAY_WIDTH: u32 = 64;
+pub const DISPLAY_LINES: u32 = 128;
+// Each line's 8 DMA cycles are requested this far into it
+const DMA_OFFSET: u32 = 2;
+/**
+ *  The interrupt comes ahead of the first DMA, leaving the interrupt routine time to point R0 at the
+ *  display buffer. The 1861 asks for it 29 cycles before it asks for DMA; with both only serviced
+ *  between instructions here it's 31, which keeps the interpreter's line repeating loop in step
+ *  however far into an instruction the interrupt arrives.
+ */
+const INTERRUPT_LEAD: u32 = 31;
+const INTERRUPT_CYCLE: u32 = DISPLAY_START_LINE * CYCLES_PER_LINE + DMA_OFFSET - INTERRUPT_LEAD;
+// EF1 is asserted for the 4 lines before the display starts and the last 4 lines of it
+const EF1_LINES: [std::ops::Range<u32>; 2] = [
+    DISPLAY_START_LINE - 4..DISPLAY_START_LINE,
+    DISPLAY_START_LINE + DISPLAY_LINES - 4..DISPLAY_START_LINE + DISPLAY_LINES,
+];
+
+/**
+ *  Everything the 1802 sees on a COSMAC VIP: RAM, the monitor ROM at 0x8000, the hex keypad
+ *  (OUT 2 latches a key, EF3 is asserted while it's held down) and the 1861 display chip
+ *  (INP 1 turns it on, OUT 1 off, EF1 marks the start and end of the picture).
+ */
+struct VipBus {
+    ram: Vec<u8>,
+    monitor: Option<Vec<u8>>,
+    // After a reset the monitor ROM also shows up at 0x0000, until the first access above 0x8000
+    monitor_at_zero: bool,
+    keyboard: [bool; 16],
+    key_latch: u8,
+    display_on: bool,
+    ef1: bool,
+}
+
+impl Bus for VipBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        if addr >= RAM_END {
+            self.monitor_at_zero = false;
+        }
+        if addr >= RAM_END || self.monitor_at_zero {
+            // Without the monitor ROM reads there float to 0, which the 1802 idles on
+            return self.monitor.as_ref().map_or(0, |rom| rom[addr as usize % MONITOR_SIZE]);
+        }
+        self.ram[addr as usize % RAM_SIZE]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        if addr < RAM_END {
+            self.ram[addr as usize % RAM_SIZE] = value;
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 {
+            self.display_on = true;
+        }
+        0
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            1 => self.display_on = false,
+            2 => self.key_latch = value & 0x0F,
+            _ => {}
+        }
+    }
+
+    fn ef(&self, flag: u8) -> bool {
+        match flag {
+            1 => self.ef1 && self.display_on,
+            3 => self.keyboard[self.key_latch as usize],
+            _ => false,
+        }
+    }
+}
+
+/**
+ *  A COSMAC VIP running the original CHIP-8 interpreter on an emulated 1802, instead of Chip8's
+ *  high level version. Slower to emulate but exact, down to timing and the odd bugs, and the only
+ *  way to run hybrid ROMs that drop into 1802 machine code with 0nnn.
+ *  The interpreter (the first 512 bytes of a VIP CHIP-8 memory image) isn't ours to distribute,
+ *  so it's loaded from a file, as is the optional monitor ROM that Fx0A's key routine lives in.
+ */
+pub struct Vip {
+    cpu: Cdp1802,
+    bus: VipBus,
+    // The picture as the 1861 last drew it, one bit per pixel, 8 bytes a line
+    display: Vec<u8>,
+    // Cycles the last instruction of a frame ran into the next one
+    overrun: u32,
+}
+
+impl Vip {
+    pub fn new(interpreter: &[u8], monitor: Option<&[u8]>) -> Result<Self, String> {
+        if interpreter.len() > INTERPRETER_SIZE {
+            return Err(format!("The CHIP-8 interpreter is {} bytes, it has to fit in the {} before the program",
+                interpreter.len(), INTERPRETER_SIZE));
+        }
+        if let Some(rom) = monitor {
+            if rom.len() != MONITOR_SIZE {
+                return Err(format!("The VIP monitor ROM should be {} bytes, got {}", MONITOR_SIZE, rom.len()));
+            }
+        }
+
+        let mut ram = vec![0; RAM_SIZE];
+        ram[..interpreter.len()].copy_from_slice(interpreter);
+        let mut cpu = Cdp1802::new();
+        if monitor.is_none() {
+            // Skip straight to the interpreter with R1 holding the top of RAM, as the monitor leaves it
+            cpu.r[1] = RAM_SIZE as u16 - 1;
+        }
+        Ok(Vip {
+            cpu,
+            bus: VipBus {
+                ram,
+                monitor: monitor.map(|rom| rom.to_vec()),
+                monitor_at_zero: monitor.is_some(),
+                keyboard: [false; 16],
+                key_latch: 0,
+                display_on: false,
+                ef1: false,
+            },
+            display: vec![0; (DISPLAY_WIDTH / 8 * DISPLAY_LINES) as usize],
+            overrun: 0,
+        })
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+        let start = PROGRAM_START as usize;
+        let max = (PROGRAM_END - PROGRAM_START) as usize;
+        if rom.len() > max {
+            return Err(format!("Program is {} bytes, only {} fit in the VIP's memory", rom.len(), max));
+        }
+        self.bus.ram[start..start + rom.len()].copy_from_slice(rom);
+        Ok(())
+    }
+
+    // key is the hex keypad digit, 0x0-0xF
+    pub fn key_down(&mut self, key: u8) {
+        self.bus.keyboard[(key & 0xF) as usize] = true;
+    }
+
+    pub fn key_up(&mut self, key: u8) {
+        self.bus.keyboard[(key & 0xF) as usize] = false;
+    }
+
+    // The speaker is driven straight from Q
+    pub fn buzzer_active(&self) -> bool {
+        self.cpu.q
+    }
+
+    pub fn display_size(&self) -> (u32, u32) {
+        (DISPLAY_WIDTH, DISPLAY_LINES)
+    }
+
+    // XRGB pixels like Chip8::render_to_pixels, white on black
+    pub fn render_to_pixels(&self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.display.len() * 8 * 4);
+        for byte in &self.display {
+            for bit in (0..8).rev() {
+                let value = if byte >> bit & 1 != 0 { 0xFF } else { 0x00 };
+                pixels.extend_from_slice(&[value, value, value, 0xFF]);
+            }
+        }
+        pixels
+    }
+
+    /**
+     *  Runs one 60hz frame, 3668 machine cycles, with the 1861 interrupting and DMAing the picture
+     *  out line by line as it goes. The interpreter counts its timers down in the interrupt routine.
+     *  DMA and interrupts are only serviced between instructions, the same as on the 1802.
+     */
+    pub fn run_frame(&mut self) {
+        let mut cycle = self.overrun;
+        let mut line = 0;
+        let mut interrupted = false;
+        let first_dma = DISPLAY_START_LINE * CYCLES_PER_LINE + DMA_OFFSET;
+
+        while cycle < CYCLES_PER_FRAME {
+            let scanline = cycle / CYCLES_PER_LINE;
+            self.bus.ef1 = EF1_LINES.iter().any(|lines| lines.contains(&scanline));
+
+            if !self.bus.display_on {
+                line = DISPLAY_LINES;
+                interrupted = true;
+            }
+            let next_dma = first_dma + line * CYCLES_PER_LINE;
+            if line < DISPLAY_LINES && cycle >= next_dma {
+                let start = (line * DISPLAY_WIDTH / 8) as usize;
+                for n in 0..(DISPLAY_WIDTH / 8) as usize {
+                    self.display[start + n] = self.cpu.dma_out(&mut self.bus);
+                }
+                cycle += DISPLAY_WIDTH / 8;
+                line += 1;
+                continue;
+            }
+            // The interrupt stays requested until the picture starts
+            if !interrupted && cycle >= INTERRUPT_CYCLE {
+                if cycle >= first_dma {
+                    interrupted = true;
+                } else if self.cpu.ie {
+                    cycle += self.cpu.interrupt();
+                    interrupted = true;
+                    continue;
+                }
+            }
+
+            if self.cpu.idle {
+                // Nothing to do until the next thing the 1861 does
+                let next_event = if !interrupted {
+                    INTERRUPT_CYCLE
+                } else if line < DISPLAY_LINES {
+                    next_dma
+                } else {
+                    CYCLES_PER_FRAME
+                };
+                cycle = next_event.max(cycle + 1).min(CYCLES_PER_FRAME);
+                continue;
+            }
+            cycle += self.cpu.step(&mut self.bus);
+        }
+        self.overrun = cycle - CYCLES_PER_FRAME;
+        if !self.bus.display_on {
+            self.display.iter_mut().for_each(|b| *b = 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     *  A stand-in for the CHIP-8 interpreter: points R1 at an interrupt routine and R2 at a stack,
+     *  turns the display on and counts loop passes in R6 (4 cycles each). The interrupt routine
+     *  points R0 at the picture in 0x100-0x4FF, copies R6 to R8 and counts interrupts in R5.
+     */
+    const INTERPRETER: [u8; 0x2D] = [
+        0xF8, 0x00, 0xB1, 0xF8, 0x1C, 0xA1, // R1 = 001C
+        0xF8, 0x0F, 0xB2, 0xF8, 0xF0, 0xA2, // R2 = 0FF0
+        0xF8, 0x00, 0xB3, 0xF8, 0x15, 0xA3, // R3 = 0015
+        0xD3, 0x00, 0x00,                   // SEP 3
+        0xE2, 0x69,                         // 0015: SEX 2, INP 1
+        0x16, 0x30, 0x17,                   // 0017: INC R6, BR 17
+        0x72, 0x70,                         // 001A: LDXA, RET
+        0x22, 0x78, 0x22, 0x52,             // 001C: save T and D on the stack
+        0xF8, 0x01, 0xB0, 0xF8, 0x00, 0xA0, // R0 = 0100
+        0x96, 0xB8, 0x86, 0xA8,             // R8 = R6
+        0x15, 0x30, 0x1A,                   // INC R5, BR 1A
+    ];
+    // Cycles the interrupt routine takes, interrupt included
+    const ROUTINE_CYCLES: u32 = 1 + 16 * 2;
+
+    fn vip() -> Vip {
+        let mut vip = Vip::new(&INTERPRETER, None).unwrap();
+        for (n, byte) in vip.bus.ram[0x100..0x500].iter_mut().enumerate() {
+            *byte = n as u8;
+        }
+        vip
+    }
+
+    #[test]
+    fn frame_timing() {
+        let mut vip = vip();
+        // The display is off when the first frame starts, so no interrupt or DMA until the second
+        vip.run_frame();
+        assert!(vip.bus.display_on);
+        assert_eq!(vip.cpu.r[5], 0);
+        assert!(vip.display.iter().all(|&b| b == 0));
+
+        vip.cpu.r[6] = 0;
+        vip.run_frame();
+        assert_eq!(vip.cpu.r[5], 1);
+        // Taken at the first instruction boundary from INTERRUPT_CYCLE, after the BR of the 273rd pass
+        assert_eq!(vip.cpu.r[8] as u32, INTERRUPT_CYCLE.div_ceil(4));
+        // 128 lines of 8 bytes DMAed out from where the interrupt routine pointed R0
+        assert_eq!(vip.cpu.r[0], 0x500);
+        assert_eq!(vip.display, vip.bus.ram[0x100..0x500]);
+        // Each DMA byte takes a cycle away from the program
+        let program_cycles = vip.cpu.r[6] as u32 * 4 + ROUTINE_CYCLES;
+        assert_eq!(program_cycles + DISPLAY_LINES * DISPLAY_WIDTH / 8, CYCLES_PER_FRAME + vip.overrun);
+    }
+}
@@ -0,0 +1,210 @@
+// Runs test ROMs headlessly and compares the final screen against the images in tests/golden.
+//
+// ROMs live in tests/roms. The .8o programs there are ours. The third party suites, Timendus's
+// chip8-test-suite (GPL-3.0) and BestCoder's test_opcode.ch8 (MIT), aren't checked in yet, so their
+// tests are ignored. To bring one in, copy its .ch8 into tests/roms, run it with
+// `CHIP8_BLESS=1 cargo test --test golden_tests -- --ignored <name>`, check the screen written to
+// tests/golden by eye against the suite's reference screenshots, then commit both and drop the #[ignore].
+// A missing ROM or golden image fails the test.
+//
+// test_opcode.ch8 is MIT and just needs copying in along with its licence. Whether to vendor the
+// Timendus suite is still to be decided since it's GPL-3.0, if it's brought in its COPYING goes in
+// tests/roms too and the README has to say those ROMs are under the GPL. Until it is, its tests stay ignored.
+//
+// A golden image is the headless framebuffer_text of the screen ('.' off, '#' plane 1, ...).
+#![cfg(feature = "std")]
+
+use rusty_chip8_emu::chip8::Chip8;
+use rusty_chip8_emu::headless::{self, Halt, HeadlessOptions, KeyPress};
+use rusty_chip8_emu::octo;
+use rusty_chip8_emu::quirks::Quirks;
+use rusty_chip8_emu::util::Flat2DArray;
+use std::path::{Path, PathBuf};
+
+struct Golden {
+    rom: &'static str,
+    // tests/golden/<name>.txt
+    name: &'static str,
+    quirks: &'static str,
+    frames: u64,
+    keys: &'static [KeyPress],
+}
+
+const fn press(frame: u64, key: u8) -> KeyPress {
+    KeyPress { frame, key, frames: 4 }
+}
+
+fn tests_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+fn load(path: &Path) -> Vec<u8> {
+    let name = path.to_str().unwrap();
+    if name.ends_with(".8o") {
+        octo::compile_file(name).unwrap_or_else(|e| panic!("{}", e)).rom
+    } else {
+        std::fs::read(path).unwrap_or_else(|e| panic!("Error reading {} :: {}", name, e))
+    }
+}
+
+// Inverse of headless::framebuffer_text
+fn parse_screen(text: &str) -> Flat2DArray<u8> {
+    let rows: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+    let mut screen = Flat2DArray::new(rows[0].len(), rows.len());
+    for (y, row) in rows.iter().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            let cell = match c {
+                '.' => 0,
+                '#' => 1,
+                '+' => 2,
+                '%' => 3,
+                _ => panic!("Unexpected '{}' in golden image at {},{}", c, x, y),
+            };
+            screen.set(x, y, cell);
+        }
+    }
+    screen
+}
+
+// Same characters as the golden files so the Display output reads as a picture
+fn as_chars(screen: &Flat2DArray<u8>) -> Flat2DArray<char> {
+    let mut chars = Flat2DArray::new(screen.width(), screen.height());
+    for (dst, cell) in chars.data.iter_mut().zip(&screen.data) {
+        *dst = ['.', '#', '+', '%'][(cell & 0b11) as usize];
+    }
+    chars
+}
+
+// Expected and actual next to each other, rows that differ marked with '>'
+fn side_by_side(expected: &Flat2DArray<u8>, actual: &Flat2DArray<u8>) -> String {
+    let expected = as_chars(expected).to_string();
+    let actual = as_chars(actual).to_string();
+    let (expected, actual): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), actual.lines().collect());
+    let width = expected.iter().map(|l| l.len()).max().unwrap_or(0);
+
+    let mut out = format!("  {:<w$}   {}\n", "expected", "actual", w = width);
+    for row in 0..expected.len().max(actual.len()) {
+        let e = expected.get(row).copied().unwrap_or("");
+        let a = actual.get(row).copied().unwrap_or("");
+        let marker = if e == a { ' ' } else { '>' };
+        out += &format!("{} {:<w$}   {}\n", marker, e, a, w = width);
+    }
+    out
+}
+
+fn check(golden: &Golden) {
+    let dir = tests_dir();
+    let rom_path = dir.join("roms").join(golden.rom);
+    if !rom_path.exists() {
+        panic!("{} needs {}, see the top of tests/golden_tests.rs", golden.name, rom_path.display());
+    }
+
+    let mut chip8 = Chip8::new(golden.quirks.parse::<Quirks>().unwrap());
+    chip8.load_rom(&load(&rom_path)).unwrap();
+    let options = HeadlessOptions {
+        max_frames: Some(golden.frames),
+        keys: golden.keys.to_vec(),
+        ..HeadlessOptions::default()
+    };
+    let report = headless::run(&mut chip8, &options);
+    if let Halt::Error(e) = &report.halt {
+        panic!("{} stopped with an error after {} frames :: {}", golden.name, report.frames, e);
+    }
+
+    let golden_path = dir.join("golden").join(format!("{}.txt", golden.name));
+    let actual = headless::framebuffer_text(&chip8);
+    if std::env::var_os("CHIP8_BLESS").is_some() {
+        std::fs::write(&golden_path, &actual).unwrap();
+        return;
+    }
+    let expected = match std::fs::read_to_string(&golden_path) {
+        Ok(text) => parse_screen(&text),
+        Err(_) => panic!("No golden image at {}, the screen was:\n{}\nRun with CHIP8_BLESS=1 to save it",
+            golden_path.display(), actual),
+    };
+
+    let actual = parse_screen(&actual);
+    if expected.width() != actual.width() || expected.data != actual.data {
+        panic!("{} doesn't match {} ({})\n{}", golden.name, golden_path.display(), report.halt,
+            side_by_side(&expected, &actual));
+    }
+}
+
+#[test]
+fn font() {
+    check(&Golden { rom: "font.8o", name: "font", quirks: "chip48", frames: 120, keys: &[] });
+}
+
+#[test]
+fn flags() {
+    check(&Golden { rom: "flags.8o", name: "flags", quirks: "chip48", frames: 180, keys: &[] });
+}
+
+#[test]
+fn flags_vip() {
+    // Same results expected with the VIP's vf reset and shift quirks on
+    check(&Golden { rom: "flags.8o", name: "flags", quirks: "vip", frames: 180, keys: &[] });
+}
+
+#[test]
+fn hires() {
+    check(&Golden { rom: "hires.8o", name: "hires", quirks: "schip", frames: 60, keys: &[] });
+}
+
+#[test]
+fn key_wait_release() {
+    const KEYS: &[KeyPress] = &[press(10, 0x5)];
+    check(&Golden { rom: "keywait.8o", name: "keywait_release", quirks: "vip", frames: 60, keys: KEYS });
+}
+
+#[test]
+fn key_wait_press() {
+    const KEYS: &[KeyPress] = &[press(10, 0x5)];
+    check(&Golden { rom: "keywait.8o", name: "keywait_press", quirks: "xochip", frames: 60, keys: KEYS });
+}
+
+#[test]
+#[ignore = "needs tests/roms/test_opcode.ch8 from BestCoder's chip8 test ROM"]
+fn test_opcode() {
+    check(&Golden { rom: "test_opcode.ch8", name: "test_opcode", quirks: "chip48", frames: 120, keys: &[] });
+}
+
+#[test]
+#[ignore = "needs tests/roms/1-chip8-logo.ch8 from Timendus's chip8-test-suite"]
+fn timendus_chip8_logo() {
+    check(&Golden { rom: "1-chip8-logo.ch8", name: "1-chip8-logo", quirks: "vip", frames: 60, keys: &[] });
+}
+
+#[test]
+#[ignore = "needs tests/roms/2-ibm-logo.ch8 from Timendus's chip8-test-suite"]
+fn timendus_ibm_logo() {
+    check(&Golden { rom: "2-ibm-logo.ch8", name: "2-ibm-logo", quirks: "vip", frames: 60, keys: &[] });
+}
+
+#[test]
+#[ignore = "needs tests/roms/3-corax+.ch8 from Timendus's chip8-test-suite"]
+fn timendus_corax() {
+    check(&Golden { rom: "3-corax+.ch8", name: "3-corax+", quirks: "chip48", frames: 120, keys: &[] });
+}
+
+#[test]
+#[ignore = "needs tests/roms/4-flags.ch8 from Timendus's chip8-test-suite"]
+fn timendus_flags() {
+    check(&Golden { rom: "4-flags.ch8", name: "4-flags", quirks: "chip48", frames: 240, keys: &[] });
+}
+
+#[test]
+#[ignore = "needs tests/roms/5-quirks.ch8 from Timendus's chip8-test-suite"]
+fn timendus_quirks() {
+    // Key 1 picks CHIP-8 from the platform menu
+    const KEYS: &[KeyPress] = &[press(10, 0x1)];
+    check(&Golden { rom: "5-quirks.ch8", name: "5-quirks", quirks: "vip", frames: 600, keys: KEYS });
+}
+
+#[test]
+#[ignore = "needs tests/roms/6-keypad.ch8 from Timendus's chip8-test-suite"]
+fn timendus_keypad() {
+    // 3 picks the Fx0A test, then 5 is pressed and released for it to report
+    const KEYS: &[KeyPress] = &[press(10, 0x3), press(40, 0x5)];
+    check(&Golden { rom: "6-keypad.ch8", name: "6-keypad", quirks: "vip", frames: 120, keys: KEYS });
+}
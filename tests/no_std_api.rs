@@ -0,0 +1,69 @@
+// Drives the core through the API an embedded frontend gets, the same with or without std.
+// `cargo test --no-default-features --test no_std_api` builds the library as no_std.
+use rusty_chip8_emu::chip8::Chip8;
+use rusty_chip8_emu::quirks::Quirks;
+
+const ROM: &[u8] = &[
+    0x60, 0x05, // LD V0, 5
+    0xF0, 0x29, // LD F, V0
+    0x61, 0x00, // LD V1, 0
+    0xD1, 0x15, // DRW V1, V1, 5
+    0xC3, 0xFF, // RND V3, FF
+    0x64, 0x0A, // LD V4, 10
+    0xF4, 0x18, // LD ST, V4
+    0xF5, 0x0A, // LD V5, K
+    0x12, 0x10, // JP 210
+];
+
+fn chip8() -> Chip8<impl FnMut() -> u8 + Clone> {
+    let mut chip8 = Chip8::with_rng(Quirks::default(), || 0xA5);
+    chip8.load_rom(ROM).unwrap();
+    chip8
+}
+
+#[test]
+fn draws_to_the_packed_framebuffer() {
+    let mut chip8 = chip8();
+    assert!(chip8.step_frame(4).unwrap());
+
+    // The font's 5, top left, 8 bytes to a row of the 64 pixel wide display
+    let gfx = chip8.gfx();
+    assert_eq!((gfx.width(), gfx.height()), (64, 32));
+    let rows: Vec<u8> = gfx.plane(0).chunks(8).take(6).map(|row| row[0]).collect();
+    assert_eq!(rows, [0xF0, 0x80, 0xF0, 0x10, 0xF0, 0x00]);
+    assert_eq!(gfx.get(0, 0), 1);
+    assert_eq!(gfx.get(4, 0), 0);
+    assert!(gfx.plane(1).iter().all(|b| *b == 0));
+}
+
+#[test]
+fn random_numbers_come_from_the_rng() {
+    let mut chip8 = chip8();
+    chip8.step_frame(5).unwrap();
+    assert_eq!(chip8.v()[3], 0xA5);
+}
+
+#[test]
+fn buzzer_follows_the_sound_timer() {
+    let mut chip8 = chip8();
+    chip8.step_frame(7).unwrap();
+    assert!(chip8.buzzer_active());
+    for _ in 0..10 {
+        chip8.step_frame(0).unwrap();
+    }
+    assert!(!chip8.buzzer_active());
+}
+
+#[test]
+fn keys_by_hex_index() {
+    let mut chip8 = chip8();
+    chip8.step_frame(8).unwrap();
+    assert!(chip8.key_wait().is_some());
+
+    chip8.key_down(0xB);
+    chip8.step_frame(1).unwrap();
+    chip8.key_up(0xB);
+    chip8.step_frame(1).unwrap();
+    assert!(chip8.key_wait().is_none());
+    assert_eq!(chip8.v()[5], 0xB);
+}